@@ -0,0 +1,117 @@
+use chrono::{Local, NaiveDate};
+use flate2::read::GzDecoder;
+use glob::{MatchOptions, Pattern};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Compared in lowercase
+const TAR_EXTENSIONS: [&str; 3] = [".tar", ".tar.gz", ".tgz"];
+const ZIP_EXTENSION: &str = ".zip";
+
+fn is_zip(name: &str) -> bool { name.to_lowercase().ends_with(ZIP_EXTENSION) }
+
+fn is_tar(name: &str) -> bool {
+	let name = name.to_lowercase();
+	TAR_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+// A pattern reaching into archives, e.g. backup.zip/DCIM/**/*.jpg, split into
+// the pattern of the archives and the pattern of the files within them
+pub fn split(pattern: &str) -> Option<(&str, &str)> {
+	pattern
+		.match_indices(['/', std::path::MAIN_SEPARATOR])
+		.map(|(i, _)| (&pattern[..i], &pattern[i + 1..]))
+		.find(|(archive, inner)| (is_zip(archive) || is_tar(archive)) && !inner.is_empty())
+}
+
+// Only relative paths without .. can be extracted safely
+fn enclosed(path: &Path) -> Option<PathBuf> {
+	let path = path.components().filter(|c| *c != Component::CurDir).collect::<PathBuf>();
+	path.components().all(|c| matches!(c, Component::Normal(_))).then_some(path)
+}
+
+fn write_entry(reader: &mut impl io::Read, path: &Path, modified: Option<SystemTime>) -> io::Result<()> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	let mut file = fs::File::create(path)?;
+	io::copy(reader, &mut file)?;
+	match modified {
+		Some(modified) => file.set_modified(modified),
+		None => Ok(()),
+	}
+}
+
+// Zip timestamps carry no timezone, they are assumed to be in local time
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+	NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)?
+		.and_hms_opt(time.hour() as u32, time.minute() as u32, time.second() as u32)?
+		.and_local_timezone(Local)
+		.earliest()
+		.map(SystemTime::from)
+}
+
+fn extract_zip(archive: &Path, pattern: &Pattern, options: MatchOptions, dest: &Path) -> Result<Vec<PathBuf>, String> {
+	let file = fs::File::open(archive).map_err(|e| e.to_string())?;
+	let mut zip = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+	let mut extracted = Vec::new();
+	for i in 0..zip.len() {
+		let mut entry = zip.by_index(i).map_err(|e| e.to_string())?;
+		let Some(name) = entry.enclosed_name().and_then(|name| enclosed(&name)) else {
+			continue;
+		};
+		if entry.is_file() && pattern.matches_path_with(&name, options) {
+			let modified = entry.last_modified().and_then(zip_time);
+			write_entry(&mut entry, &dest.join(&name), modified)
+				.map_err(|e| format!("unable to extract {:?}: {}", name, e))?;
+			extracted.push(name);
+		}
+	}
+	Ok(extracted)
+}
+
+fn extract_tar(
+	reader: impl io::Read,
+	pattern: &Pattern,
+	options: MatchOptions,
+	dest: &Path,
+) -> Result<Vec<PathBuf>, String> {
+	let mut tar = tar::Archive::new(reader);
+	let mut extracted = Vec::new();
+	for entry in tar.entries().map_err(|e| e.to_string())? {
+		let mut entry = entry.map_err(|e| e.to_string())?;
+		let Some(name) = entry.path().ok().and_then(|name| enclosed(&name)) else {
+			continue;
+		};
+		if entry.header().entry_type().is_file() && pattern.matches_path_with(&name, options) {
+			let modified = entry.header().mtime().ok().map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime));
+			write_entry(&mut entry, &dest.join(&name), modified)
+				.map_err(|e| format!("unable to extract {:?}: {}", name, e))?;
+			extracted.push(name);
+		}
+	}
+	Ok(extracted)
+}
+
+// Extract the files of a zip or tar archive matching the pattern into dest.
+// Returns their paths within the archive, sorted as glob would
+pub fn extract(archive: &Path, pattern: &Pattern, options: MatchOptions, dest: &Path) -> Result<Vec<PathBuf>, String> {
+	let name = archive.to_string_lossy();
+	// as glob matches files on the filesystem, wildcards other than ** never
+	// match across folders
+	let options = MatchOptions { require_literal_separator: true, ..options };
+	let mut extracted = if is_zip(&name) {
+		extract_zip(archive, pattern, options, dest)?
+	} else {
+		let file = fs::File::open(archive).map_err(|e| e.to_string())?;
+		if name.to_lowercase().ends_with(".tar") {
+			extract_tar(file, pattern, options, dest)?
+		} else {
+			extract_tar(GzDecoder::new(file), pattern, options, dest)?
+		}
+	};
+	extracted.sort();
+	Ok(extracted)
+}
@@ -0,0 +1,88 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+
+// Properties extracted from the content of each file in previous runs, reused
+// as long as the file has the same size and modification time, and the options
+// which affect extraction have not changed. Changes are written in a single
+// transaction, committed at the end of the run
+pub struct PropertyCache {
+	connection: Connection,
+}
+
+// What is worth caching: the content hash and CRC32 checksum, if computed, and
+// the Exif properties, if extracted, serialized as JSON
+#[derive(Default)]
+pub struct CachedProperties {
+	pub sha1: Option<String>,
+	pub crc32: Option<String>,
+	pub exif: Option<String>,
+}
+
+// In the platform cache folder, e.g. ~/.cache/exif-namer on Linux
+pub fn default_path() -> Option<PathBuf> { dirs::cache_dir().map(|dir| dir.join("exif-namer").join("properties.db")) }
+
+impl PropertyCache {
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+		let path = path.as_ref();
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent)
+				.map_err(|e| format!("Unable to create property cache folder {:?}: {}", parent, e))?;
+		}
+		let connection =
+			Connection::open(path).map_err(|e| format!("Unable to open property cache {:?}: {}", path, e))?;
+		connection
+			.execute_batch(
+				"CREATE TABLE IF NOT EXISTS properties (
+					src TEXT PRIMARY KEY,
+					size INTEGER NOT NULL,
+					modified TEXT NOT NULL,
+					settings TEXT NOT NULL,
+					sha1 TEXT,
+					exif TEXT
+				);",
+			)
+			.map_err(|e| format!("Unable to initialize property cache {:?}: {}", path, e))?;
+		// missing from caches created by earlier versions, fails if already there
+		let _ = connection.execute("ALTER TABLE properties ADD COLUMN crc32 TEXT", []);
+		connection
+			.execute_batch("BEGIN")
+			.map_err(|e| format!("Unable to initialize property cache {:?}: {}", path, e))?;
+		Ok(PropertyCache { connection })
+	}
+
+	// Only if the file and the settings are the same as when it was cached
+	pub fn get(&self, src: &str, size: i64, modified: &str, settings: &str) -> Option<CachedProperties> {
+		self.connection
+			.query_row(
+				"SELECT sha1, crc32, exif FROM properties
+				WHERE src = ?1 AND size = ?2 AND modified = ?3 AND settings = ?4",
+				params![src, size, modified, settings],
+				|row| Ok(CachedProperties { sha1: row.get(0)?, crc32: row.get(1)?, exif: row.get(2)? }),
+			)
+			.optional()
+			.ok()
+			.flatten()
+	}
+
+	pub fn put(
+		&self,
+		src: &str,
+		size: i64,
+		modified: &str,
+		settings: &str,
+		properties: &CachedProperties,
+	) -> Result<(), String> {
+		self.connection
+			.execute(
+				"INSERT OR REPLACE INTO properties (src, size, modified, settings, sha1, crc32, exif)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+				params![src, size, modified, settings, properties.sha1, properties.crc32, properties.exif],
+			)
+			.map(|_| ())
+			.map_err(|e| format!("Unable to cache properties of {}: {}", src, e))
+	}
+
+	pub fn commit(&self) -> Result<(), String> {
+		self.connection.execute_batch("COMMIT").map_err(|e| format!("Unable to save the property cache: {}", e))
+	}
+}
@@ -0,0 +1,102 @@
+use crate::portable;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+// Sources completed by a run, appended one line at a time as they complete, so
+// that a run which was killed can be resumed. Removed once the run completes
+#[derive(Debug)]
+pub struct Checkpoint {
+	path: PathBuf,
+	file: fs::File,
+	// absolute paths of the sources completed by the interrupted run
+	completed: HashSet<String>,
+	// next SysIdx, and next DestSeq for each destination folder
+	next_idx: Option<usize>,
+	next_dest_seq: HashMap<PathBuf, usize>,
+}
+
+// In the platform cache folder, named after the working directory and the
+// arguments, so that only the same command resumes the same run
+pub fn default_path(cwd: &Path, args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+	let mut hasher = Sha1::new();
+	hasher.update(cwd.as_os_str().as_encoded_bytes());
+	for arg in args.into_iter().filter(|arg| arg != "--resume") {
+		hasher.update([0]);
+		hasher.update(arg.as_encoded_bytes());
+	}
+	let name = format!("checkpoint-{}.jsonl", &hex::encode(hasher.finalize())[..16]);
+	dirs::cache_dir().map(|dir| dir.join("exif-namer").join(name))
+}
+
+impl Checkpoint {
+	// With resume, the sources already completed are read back, otherwise any
+	// previous checkpoint is discarded
+	pub fn open(path: &Path, resume: bool) -> Result<Self, String> {
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)
+				.map_err(|e| format!("Unable to create checkpoint folder {:?}: {}", parent, e))?;
+		}
+		let mut checkpoint = Checkpoint {
+			path: path.to_path_buf(),
+			file: fs::File::options()
+				.create(true)
+				.append(true)
+				.truncate(false)
+				.open(path)
+				.map_err(|e| format!("Unable to open checkpoint {:?}: {}", path, e))?,
+			completed: HashSet::new(),
+			next_idx: None,
+			next_dest_seq: HashMap::new(),
+		};
+		if !resume {
+			checkpoint.file.set_len(0).map_err(|e| format!("Unable to reset checkpoint {:?}: {}", path, e))?;
+			return Ok(checkpoint);
+		}
+		let file = fs::File::open(path).map_err(|e| format!("Unable to read checkpoint {:?}: {}", path, e))?;
+		// the last line is incomplete if the run was killed while writing it
+		for line in io::BufReader::new(file).lines().map_while(Result::ok) {
+			let Ok(entry) = serde_json::from_str::<Value>(&line) else {
+				continue;
+			};
+			let Some(src) = entry["src"].as_str() else {
+				continue;
+			};
+			checkpoint.completed.insert(src.to_string());
+			if let Some(idx) = entry["idx"].as_u64() {
+				checkpoint.next_idx = checkpoint.next_idx.max(Some(idx as usize + 1));
+			}
+			if let (Some(dir), Some(seq)) = (entry["dest_dir"].as_str(), entry["dest_seq"].as_u64()) {
+				let next = checkpoint.next_dest_seq.entry(portable::unescape(dir)).or_default();
+				*next = (*next).max(seq as usize + 1);
+			}
+		}
+		Ok(checkpoint)
+	}
+
+	pub fn is_resumed(&self) -> bool { !self.completed.is_empty() }
+
+	pub fn is_completed(&self, src: &str) -> bool { self.completed.contains(src) }
+
+	pub fn next_idx(&self) -> Option<usize> { self.next_idx }
+
+	pub fn next_dest_seq(&self) -> &HashMap<PathBuf, usize> { &self.next_dest_seq }
+
+	// Written through at once, as the run may be killed at any time
+	pub fn record(&mut self, src: &str, idx: Option<usize>, dest_seq: Option<(&Path, usize)>) -> Result<(), String> {
+		let mut entry = json!({ "src": src, "idx": idx });
+		if let Some((dir, seq)) = dest_seq {
+			entry["dest_dir"] = Value::from(portable::escape(dir.as_os_str()));
+			entry["dest_seq"] = Value::from(seq);
+		}
+		writeln!(self.file, "{}", entry).map_err(|e| format!("Unable to update checkpoint {:?}: {}", self.path, e))
+	}
+
+	pub fn remove(self) -> Result<(), String> {
+		fs::remove_file(&self.path).map_err(|e| format!("Unable to remove checkpoint {:?}: {}", self.path, e))
+	}
+}
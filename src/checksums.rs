@@ -0,0 +1,76 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// The checksums of the files written by a run, appended one line at a time as
+// each file is applied, so that a run which was killed leaves the checksums of
+// the files it completed
+#[derive(Debug)]
+pub struct Checksums {
+	path: PathBuf,
+	file: fs::File,
+	// paths are relative to this folder, so that the list can be checked from there
+	dir: PathBuf,
+	// length before this run, to which a rolled back run restores the list
+	initial_len: u64,
+}
+
+// A line as sha1sum writes it. A name with a backslash or a newline is escaped,
+// and the line then starts with a backslash
+fn checksum_line(hash: &str, path: &Path) -> Vec<u8> {
+	let name = path.as_os_str().as_encoded_bytes();
+	let mut line = Vec::new();
+	if name.contains(&b'\\') || name.contains(&b'\n') {
+		line.push(b'\\');
+	}
+	line.extend_from_slice(hash.as_bytes());
+	line.extend_from_slice(b"  ");
+	for &byte in name {
+		match byte {
+			b'\\' => line.extend_from_slice(b"\\\\"),
+			b'\n' => line.extend_from_slice(b"\\n"),
+			byte => line.push(byte),
+		}
+	}
+	line.push(b'\n');
+	line
+}
+
+impl Checksums {
+	// A resumed run adds to the checksums of the interrupted one, otherwise any
+	// previous list is replaced
+	pub fn open(path: &Path, absolute: &Path, resume: bool) -> Result<Self, String> {
+		if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+			fs::create_dir_all(parent).map_err(|e| format!("Unable to create checksums folder {:?}: {}", parent, e))?;
+		}
+		let file = fs::File::options()
+			.create(true)
+			.append(true)
+			.truncate(false)
+			.open(path)
+			.map_err(|e| format!("Unable to open checksums {:?}: {}", path, e))?;
+		if !resume {
+			file.set_len(0).map_err(|e| format!("Unable to reset checksums {:?}: {}", path, e))?;
+		}
+		let initial_len = file.metadata().map_err(|e| format!("Unable to read checksums {:?}: {}", path, e))?.len();
+		Ok(Checksums {
+			path: path.to_path_buf(),
+			file,
+			dir: absolute.parent().map(Path::to_path_buf).unwrap_or_default(),
+			initial_len,
+		})
+	}
+
+	// Written through at once, as the run may be killed at any time
+	pub fn record(&mut self, dest: &Path, hash: &str) -> Result<(), String> {
+		let relative = pathdiff::diff_paths(dest, &self.dir).unwrap_or(dest.to_path_buf());
+		self.file
+			.write_all(&checksum_line(hash, &relative))
+			.map_err(|e| format!("Unable to write checksums to {:?}: {}", self.path, e))
+	}
+
+	// Only the checksums of the files written by earlier runs are left
+	pub fn rollback(self) -> Result<(), String> {
+		self.file.set_len(self.initial_len).map_err(|e| format!("Unable to roll back checksums {:?}: {}", self.path, e))
+	}
+}
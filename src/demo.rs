@@ -0,0 +1,283 @@
+use crate::{undo, Action, App, Cli};
+use exif::experimental::Writer;
+use exif::{Field, In, Rational, Tag, Value};
+use log::*;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+// A synthetic photo: name, camera make and model, shooting time, GPS position
+// in degrees, minutes and seconds, and GPS date and time in UTC
+struct DemoShot {
+	path: &'static str,
+	make: &'static str,
+	model: &'static str,
+	date_time_original: &'static str,
+	gps: Option<([u32; 3], [u32; 3])>,
+	gps_time: Option<(&'static str, [u32; 3])>,
+}
+
+const DEMO_SHOTS: &[DemoShot] = &[
+	DemoShot {
+		path: "incoming/card1/DCIM/100MSDCF/DSC00001.JPG",
+		make: "SONY",
+		model: "ILCE-9M3",
+		date_time_original: "2024:07:27 16:38:55",
+		gps: Some(([41, 53, 24], [12, 29, 32])),
+		gps_time: Some(("2024:07:27", [14, 38, 54])),
+	},
+	DemoShot {
+		path: "incoming/card1/DCIM/100MSDCF/DSC00002.JPG",
+		make: "SONY",
+		model: "ILCE-9M3",
+		date_time_original: "2024:07:27 16:39:02",
+		gps: Some(([41, 53, 25], [12, 29, 30])),
+		gps_time: Some(("2024:07:27", [14, 39, 1])),
+	},
+	DemoShot {
+		path: "incoming/card2/DCIM/101NIKON/DSC_0001.JPG",
+		make: "NIKON CORPORATION",
+		model: "NIKON D750",
+		date_time_original: "2024:07:28 09:12:33",
+		gps: None,
+		gps_time: None,
+	},
+];
+
+// Files without any Exif metadata: a phone export, a video and a stray text
+// file
+const DEMO_OTHER_FILES: &[(&str, &[u8])] = &[
+	("incoming/phone/IMG-20230114-WA0002.jpg", b"\xff\xd8\xff\xd9"),
+	("incoming/phone/VID_20230114_091233.mp4", b"\x00\x00\x00\x18ftypmp42\x00\x00\x00\x00mp42isom"),
+	("incoming/card2/README.txt", b"Not a picture\n"),
+];
+
+// A byte-identical copy of the first shot, as found in old backups
+const DEMO_DUPLICATE: (&str, &str) = ("incoming/backup/DSC00001.JPG", "incoming/card1/DCIM/100MSDCF/DSC00001.JPG");
+
+const DEMO_TEMPLATE: &str = "archive/\
+	{{#if ExifModel}}{{ExifMake}}_{{ExifModel}}{{else}}Unknown{{/if}}/\
+	{{#if ExifDateTimeOriginal}}{{ExifDateTimeOriginal}}{{else}}{{substr SysSha1 0 8}}{{/if}}{{SysDotExt}}";
+
+// Written by the apply step, and read back by the undo step
+const DEMO_EVENT_LOG: &str = "events.jsonl";
+
+// Sources are relative to the demo folder, and so is the event log when the
+// step writes one
+struct DemoStep {
+	title: &'static str,
+	explanation: &'static str,
+	args: &'static [&'static str],
+	sources: &'static [&'static str],
+	event_log: bool,
+}
+
+const DEMO_STEPS: &[DemoStep] = &[
+	DemoStep {
+		title: "Inspect",
+		explanation: "Every file is inspected for properties, which can be used in the destination template.",
+		args: &["-m", "info", "--no-sha1"],
+		sources: &["incoming/card1/DCIM/100MSDCF/DSC00001.JPG"],
+		event_log: false,
+	},
+	DemoStep {
+		title: "Plan",
+		explanation: "A dry run shows what would happen, without touching any file. Note the two identical shots \
+			competing for the same destination: only the first one is kept.",
+		args: &["-n", "-v", "-m", "cp", "-d", DEMO_TEMPLATE],
+		sources: &["incoming/**/*"],
+		event_log: false,
+	},
+	DemoStep {
+		title: "Apply",
+		explanation: "The same command without -n copies the files into the archive, leaving the originals in place. \
+			Each change is recorded in an event log, so that it can be undone.",
+		args: &["-v", "-m", "cp", "--verify", "-d", DEMO_TEMPLATE],
+		sources: &["incoming/**/*"],
+		event_log: true,
+	},
+	DemoStep {
+		title: "Audit",
+		explanation: "The archive is now organized according to the template: an audit reports no drift.",
+		args: &["-v", "-m", "audit", "-d", DEMO_TEMPLATE],
+		sources: &["archive/**/*"],
+		event_log: false,
+	},
+];
+
+fn rationals(dms: [u32; 3]) -> Value { Value::Rational(dms.iter().map(|&v| Rational { num: v, denom: 1 }).collect()) }
+
+fn ascii(text: &str) -> Value { Value::Ascii(vec![text.as_bytes().to_vec()]) }
+
+// A JPEG container with an Exif segment and no image data, which is all the
+// metadata parser needs
+fn demo_jpeg(shot: &DemoShot) -> Result<Vec<u8>, exif::Error> {
+	let mut fields = vec![
+		Field { tag: Tag::Make, ifd_num: In::PRIMARY, value: ascii(shot.make) },
+		Field { tag: Tag::Model, ifd_num: In::PRIMARY, value: ascii(shot.model) },
+		Field { tag: Tag::DateTime, ifd_num: In::PRIMARY, value: ascii(shot.date_time_original) },
+		Field { tag: Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: ascii(shot.date_time_original) },
+		Field { tag: Tag::PixelXDimension, ifd_num: In::PRIMARY, value: Value::Long(vec![6000]) },
+		Field { tag: Tag::PixelYDimension, ifd_num: In::PRIMARY, value: Value::Long(vec![4000]) },
+		Field {
+			tag: Tag::FNumber,
+			ifd_num: In::PRIMARY,
+			value: Value::Rational(vec![Rational { num: 28, denom: 10 }]),
+		},
+		Field {
+			tag: Tag::ExposureTime,
+			ifd_num: In::PRIMARY,
+			value: Value::Rational(vec![Rational { num: 1, denom: 250 }]),
+		},
+		Field {
+			tag: Tag::FocalLength,
+			ifd_num: In::PRIMARY,
+			value: Value::Rational(vec![Rational { num: 350, denom: 10 }]),
+		},
+	];
+	if let Some((latitude, longitude)) = shot.gps {
+		fields.push(Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: ascii("N") });
+		fields.push(Field { tag: Tag::GPSLatitude, ifd_num: In::PRIMARY, value: rationals(latitude) });
+		fields.push(Field { tag: Tag::GPSLongitudeRef, ifd_num: In::PRIMARY, value: ascii("E") });
+		fields.push(Field { tag: Tag::GPSLongitude, ifd_num: In::PRIMARY, value: rationals(longitude) });
+	}
+	if let Some((date, time)) = shot.gps_time {
+		fields.push(Field { tag: Tag::GPSDateStamp, ifd_num: In::PRIMARY, value: ascii(date) });
+		fields.push(Field { tag: Tag::GPSTimeStamp, ifd_num: In::PRIMARY, value: rationals(time) });
+	}
+	let mut writer = Writer::new();
+	for field in fields.iter() {
+		writer.push_field(field);
+	}
+	let mut tiff = io::Cursor::new(Vec::new());
+	writer.write(&mut tiff, false)?;
+	let tiff = tiff.into_inner();
+
+	let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+	jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+	jpeg.extend_from_slice(b"Exif\0\0");
+	jpeg.extend_from_slice(&tiff);
+	jpeg.extend_from_slice(&[0xff, 0xd9]);
+	Ok(jpeg)
+}
+
+fn write_file(path: &Path, content: &[u8]) -> io::Result<()> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	fs::write(path, content)
+}
+
+fn create_corpus(root: &Path) -> io::Result<()> {
+	for shot in DEMO_SHOTS {
+		let jpeg = demo_jpeg(shot).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		write_file(&root.join(shot.path), &jpeg)?;
+	}
+	for (path, content) in DEMO_OTHER_FILES {
+		write_file(&root.join(path), content)?;
+	}
+	let (duplicate, original) = DEMO_DUPLICATE;
+	write_file(&root.join(duplicate), &fs::read(root.join(original))?)
+}
+
+// Runs a single step of the tutorial as if it had been invoked from the command
+// line, from the demo folder. The working directory of the process is left
+// alone: sources and the event log are rooted in the demo folder, and so are
+// destinations, with -o
+fn demo_step(root: &Path, step: &DemoStep) -> bool {
+	let event_log: &[&str] = if step.event_log { &["--log-json", DEMO_EVENT_LOG] } else { &[] };
+	let shown = step.args.iter().chain(event_log).chain(step.sources);
+	println!();
+	println!("### {}", step.title);
+	println!("{}", step.explanation);
+	println!("$ exif-namer {}", shown.map(|a| format!("'{}'", a)).collect::<Vec<_>>().join(" "));
+	println!();
+	let rooted = |path: &str| root.join(path).into_os_string();
+	let escaped_root = PathBuf::from(glob::Pattern::escape(&root.to_string_lossy()));
+	let mut command_line: Vec<OsString> = vec!["exif-namer".into()];
+	command_line.extend(step.args.iter().map(OsString::from));
+	command_line.extend(["-o".into(), root.as_os_str().to_owned()]);
+	if step.event_log {
+		command_line.extend(["--log-json".into(), rooted(DEMO_EVENT_LOG)]);
+	}
+	command_line.extend(step.sources.iter().map(|source| escaped_root.join(source).into_os_string()));
+	let args = match Cli::try_parse_action(command_line) {
+		Ok(Action::Run(args)) => args,
+		Ok(_) => {
+			error!("Invalid demo step");
+			return false;
+		}
+		Err(e) => {
+			error!("Invalid demo arguments: {}", e);
+			return false;
+		}
+	};
+	match App::new(*args) {
+		Ok(app) => {
+			let report = app.run();
+			if report.has_errors_or_warnings() {
+				warn!("{} error(s), {} warning(s)", report.error_count(), report.warning_count());
+			}
+			report.error_count() == 0
+		}
+		Err(e) => {
+			error!("Invalid demo arguments: {}", e);
+			false
+		}
+	}
+}
+
+// Reverts the apply step from its event log, as the undo subcommand does
+fn demo_undo(root: &Path) -> bool {
+	println!();
+	println!("### Undo");
+	println!("The event log of the apply step lists the copies made, which are removed, most recent first.");
+	println!("$ exif-namer undo '{}'", DEMO_EVENT_LOG);
+	println!();
+	match undo::undo(&root.join(DEMO_EVENT_LOG), false) {
+		Ok((reverted, failed)) => {
+			info!("{} reverted, {} failed", reverted, failed);
+			failed == 0
+		}
+		Err(e) => {
+			error!("{}", e);
+			false
+		}
+	}
+}
+
+// The steps run against the demo folder, which is removed at the end
+pub fn run_demo() -> ExitCode {
+	let root: PathBuf = std::env::temp_dir().join(format!("exif-namer-demo-{}", uuid::Uuid::new_v4()));
+	if let Err(e) = create_corpus(&root) {
+		error!("Unable to create demo files in {:?}: {}", root, e);
+		return ExitCode::FAILURE;
+	}
+	println!("A small collection of sample files has been created in {:?}", root);
+	println!("All the following steps run as if from that folder.");
+
+	let mut success = true;
+	for step in DEMO_STEPS {
+		success &= demo_step(&root, step);
+	}
+	success &= demo_undo(&root);
+
+	match fs::remove_dir_all(&root) {
+		Ok(()) => {
+			println!();
+			println!("The demo folder {:?} has been removed.", root);
+		}
+		Err(e) => {
+			error!("Unable to remove demo folder {:?}: {}", root, e);
+			success = false;
+		}
+	}
+
+	if success {
+		ExitCode::SUCCESS
+	} else {
+		ExitCode::FAILURE
+	}
+}
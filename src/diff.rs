@@ -0,0 +1,65 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+const BOLD_YELLOW: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+const ABSENT: &str = "-";
+
+// At most max_len characters, with the middle cut out as in -m info. No limit
+// if max_len is 0
+fn shorten(value: &str, max_len: usize) -> String {
+	let len = value.chars().count();
+	if max_len == 0 || len <= max_len {
+		return value.to_string();
+	}
+	let head = value.chars().take(max_len / 2).collect::<String>();
+	let tail = value.chars().skip(len - max_len / 2).collect::<String>();
+	format!("{}...{}", head, tail)
+}
+
+// Properties of two files side by side, one line each, differing values
+// marked with * and, on a terminal, highlighted
+pub fn print(
+	(a, a_data): (&Path, &Map<String, Value>),
+	(b, b_data): (&Path, &Map<String, Value>),
+	max_len: usize,
+	highlight: bool,
+) -> usize {
+	let text = |data: &Map<String, Value>, key: &str| {
+		data.get(key).and_then(Value::as_str).map(|value| shorten(value, max_len))
+	};
+	let keys = a_data
+		.keys()
+		.chain(b_data.keys())
+		.filter(|key| text(a_data, key).is_some() || text(b_data, key).is_some())
+		.collect::<BTreeSet<_>>();
+	let rows = keys.into_iter().map(|key| (key, text(a_data, key), text(b_data, key))).collect::<Vec<_>>();
+	let a_name = a.to_string_lossy();
+	let b_name = b.to_string_lossy();
+	let key_width = rows.iter().map(|(key, _, _)| key.chars().count()).max().unwrap_or(0);
+	let a_width = rows
+		.iter()
+		.map(|(_, a_value, _)| a_value.as_deref().unwrap_or(ABSENT).chars().count())
+		.chain([a_name.chars().count()])
+		.max()
+		.unwrap_or(0);
+	println!("  {:<key_width$}  {:<a_width$}  {}", "", a_name, b_name);
+	let mut differences = 0;
+	for (key, a_value, b_value) in &rows {
+		let a_value = a_value.as_deref().unwrap_or(ABSENT);
+		let b_value = b_value.as_deref().unwrap_or(ABSENT);
+		if a_value == b_value {
+			println!("  {:<key_width$}  {:<a_width$}  {}", key, a_value, b_value);
+			continue;
+		}
+		differences += 1;
+		let line = format!("* {:<key_width$}  {:<a_width$}  {}", key, a_value, b_value);
+		if highlight {
+			println!("{}{}{}", BOLD_YELLOW, line, RESET);
+		} else {
+			println!("{}", line);
+		}
+	}
+	differences
+}
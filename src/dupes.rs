@@ -0,0 +1,69 @@
+use crate::{human_size, portable};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Files with the same content, as collected by -m dupes
+#[derive(Debug)]
+struct DuplicateSet {
+	hash: String,
+	size: u64,
+	paths: Vec<PathBuf>,
+}
+
+impl DuplicateSet {
+	// space taken by all the copies but one
+	fn wasted(&self) -> u64 { self.size * (self.paths.len() as u64 - 1) }
+}
+
+// Files grouped by content hash, in the order first seen
+#[derive(Default, Debug)]
+pub struct Dupes {
+	sets: Vec<DuplicateSet>,
+	by_hash: HashMap<String, usize>,
+}
+
+impl Dupes {
+	pub fn add(&mut self, hash: &str, size: u64, path: PathBuf) {
+		match self.by_hash.get(hash) {
+			Some(&i) => self.sets[i].paths.push(path),
+			None => {
+				self.by_hash.insert(hash.to_string(), self.sets.len());
+				self.sets.push(DuplicateSet { hash: hash.to_string(), size, paths: vec![path] });
+			}
+		}
+	}
+
+	// Sets of more than one file, those wasting the most space first
+	fn duplicates(&self) -> Vec<&DuplicateSet> {
+		let mut duplicates = self.sets.iter().filter(|set| set.paths.len() > 1).collect::<Vec<_>>();
+		duplicates.sort_by_key(|set| std::cmp::Reverse(set.wasted()));
+		duplicates
+	}
+
+	pub fn print(&self) {
+		let duplicates = self.duplicates();
+		for set in &duplicates {
+			println!("{} files of {} ({}):", set.paths.len(), human_size(set.size), set.hash);
+			for path in &set.paths {
+				println!("  {}", portable::escape(path.as_os_str()));
+			}
+		}
+		let copies = duplicates.iter().map(|set| set.paths.len() - 1).sum::<usize>();
+		let wasted = duplicates.iter().map(|set| set.wasted()).sum::<u64>();
+		println!(
+			"{} set(s) of duplicates, {} redundant copies, {} wasted",
+			duplicates.len(),
+			copies,
+			human_size(wasted)
+		);
+	}
+
+	// One object per set, one per line, as the export subcommand prints files
+	pub fn export(&self) {
+		for set in self.duplicates() {
+			let paths = set.paths.iter().map(|path| portable::escape(path.as_os_str())).collect::<Vec<_>>();
+			println!("{}", json!({ "sha1": set.hash, "size": set.size, "wasted": set.wasted(), "paths": paths }));
+		}
+	}
+}
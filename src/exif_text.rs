@@ -0,0 +1,60 @@
+use encoding_rs::{Encoding, ISO_2022_JP, SHIFT_JIS, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use exif::{Context, Field, Tag, Value};
+
+// Tags written by Windows Explorer, in UCS-2 little endian regardless of the
+// byte order of the file. Not known to the Exif parser
+const XP_TAGS: [(u16, &str); 5] =
+	[(0x9c9b, "XPTitle"), (0x9c9c, "XPComment"), (0x9c9d, "XPAuthor"), (0x9c9e, "XPKeywords"), (0x9c9f, "XPSubject")];
+
+pub fn xp_tag_name(tag: Tag) -> Option<&'static str> {
+	if tag.context() != Context::Tiff {
+		return None;
+	}
+	XP_TAGS.iter().find(|(number, _)| *number == tag.number()).map(|(_, name)| *name)
+}
+
+fn trim(text: &str) -> String { text.trim_end_matches(['\0', ' ']).to_string() }
+
+// UserComment starts with an 8 bytes character code, followed by the text
+fn user_comment(bytes: &[u8], little_endian: bool) -> String {
+	let (code, text) = bytes.split_at(bytes.len().min(8));
+	let decoded = match code {
+		b"UNICODE\0" if little_endian => UTF_16LE.decode_without_bom_handling(text).0,
+		b"UNICODE\0" => UTF_16BE.decode_without_bom_handling(text).0,
+		b"JIS\0\0\0\0\0" => ISO_2022_JP.decode_without_bom_handling(text).0,
+		// ASCII, or undefined
+		_ => String::from_utf8_lossy(text),
+	};
+	trim(&decoded)
+}
+
+// Text tags which are not stored as ASCII, decoded according to their own
+// conventions
+pub fn decode(field: &Field, little_endian: bool) -> Option<String> {
+	match (field.tag, &field.value) {
+		(Tag::UserComment, Value::Undefined(bytes, _)) => Some(user_comment(bytes, little_endian)),
+		(tag, Value::Byte(bytes)) if xp_tag_name(tag).is_some() =>
+			Some(trim(&UTF_16LE.decode_without_bom_handling(bytes).0)),
+		_ => None,
+	}
+}
+
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+	Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding '{}'", label))
+}
+
+// ASCII tags are often written in the local encoding of the camera. Without an
+// explicit encoding, UTF-8 is tried first, then Shift-JIS, common with Japanese
+// cameras, and finally Latin-1, which never fails
+pub fn decode_ascii(bytes: &[u8], encoding: Option<&'static Encoding>) -> String {
+	if let Some(encoding) = encoding {
+		return encoding.decode_without_bom_handling(bytes).0.into_owned();
+	}
+	if let Ok(text) = std::str::from_utf8(bytes) {
+		return text.to_string();
+	}
+	SHIFT_JIS
+		.decode_without_bom_handling_and_without_replacement(bytes)
+		.unwrap_or_else(|| WINDOWS_1252.decode_without_bom_handling(bytes).0)
+		.into_owned()
+}
@@ -0,0 +1,57 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Tags by file, as listed by exiftool -j: an array with an object for each
+// file, named after its path in SourceFile
+pub type Tags = HashMap<PathBuf, Map<String, Value>>;
+
+const SOURCE_FILE: &str = "SourceFile";
+
+fn parse(json: &str) -> Result<Tags, String> {
+	let Value::Array(files) = serde_json::from_str(json).map_err(|e| e.to_string())? else {
+		return Err("expected an array of objects".to_string());
+	};
+	Ok(files
+		.into_iter()
+		.filter_map(|file| match file {
+			Value::Object(mut tags) => match tags.remove(SOURCE_FILE) {
+				Some(Value::String(path)) => Some((PathBuf::from(path), tags)),
+				_ => None,
+			},
+			_ => None,
+		})
+		.collect())
+}
+
+// A dump generated in advance, e.g. with exiftool -j -r /media > dump.json
+pub fn load(path: &Path) -> Result<Tags, String> {
+	let json = fs::read_to_string(path).map_err(|e| format!("Unable to read exiftool output {:?}: {}", path, e))?;
+	parse(&json).map_err(|e| format!("Invalid exiftool output {:?}: {}", path, e))
+}
+
+// A single exiftool process for all the files, named in an argument file read
+// from stdin rather than on the command line, which could get too long
+pub fn run(paths: &[&PathBuf]) -> Result<Tags, String> {
+	let mut child = Command::new("exiftool")
+		.args(["-j", "-q", "-q", "-charset", "filename=utf8", "-@", "-"])
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::inherit())
+		.spawn()
+		.map_err(|e| format!("Unable to run exiftool: {}", e))?;
+	let arguments = paths.iter().map(|path| format!("{}\n", path.to_string_lossy())).collect::<String>();
+	if let Some(mut stdin) = child.stdin.take() {
+		stdin.write_all(arguments.as_bytes()).map_err(|e| format!("Unable to pass files to exiftool: {}", e))?;
+	}
+	let output = child.wait_with_output().map_err(|e| format!("Unable to read exiftool output: {}", e))?;
+	// exiftool fails if any file could not be read, but still lists the others
+	let json = String::from_utf8_lossy(&output.stdout);
+	if json.trim().is_empty() {
+		return Ok(Tags::new());
+	}
+	parse(&json).map_err(|e| format!("Invalid exiftool output: {}", e))
+}
@@ -0,0 +1,96 @@
+use log::*;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// A single filesystem change, with enough information to revert it
+#[derive(Debug)]
+pub enum JournalEntry {
+	// a file moved or renamed from one path to another
+	Renamed { from: PathBuf, to: PathBuf },
+	// a new file or link
+	Created(PathBuf),
+	CreatedDir(PathBuf),
+	RemovedDir(PathBuf),
+	// an existing destination moved out of the way, pending deletion
+	Displaced { original: PathBuf, temp: PathBuf },
+	// modification time changed, with the previous value
+	Touched { path: PathBuf, modified: SystemTime },
+}
+
+// Rename, or copy and delete if the two paths are on different filesystems
+fn move_back(from: &Path, to: &Path) -> io::Result<()> {
+	match fs::rename(from, to) {
+		Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+			fs::copy(from, to)?;
+			crate::copy_file_times(from, to)?;
+			fs::remove_file(from)
+		}
+		result => result,
+	}
+}
+
+impl JournalEntry {
+	fn revert(&self) -> io::Result<()> {
+		match self {
+			JournalEntry::Renamed { from, to } => move_back(to, from),
+			JournalEntry::Created(path) => fs::remove_file(path),
+			JournalEntry::CreatedDir(path) => fs::remove_dir(path),
+			JournalEntry::RemovedDir(path) => fs::create_dir(path),
+			JournalEntry::Displaced { original, temp } => fs::rename(temp, original),
+			JournalEntry::Touched { path, modified } =>
+				fs::File::options().write(true).open(path)?.set_modified(*modified),
+		}
+	}
+}
+
+#[derive(Default, Debug)]
+pub struct Journal {
+	entries: Vec<JournalEntry>,
+	// absolute paths of the files written so far, kept after a commit
+	written: HashSet<PathBuf>,
+}
+
+impl Journal {
+	pub fn record(&mut self, entry: JournalEntry) {
+		if let JournalEntry::Created(path) | JournalEntry::Renamed { to: path, .. } = &entry {
+			self.written.insert(std::path::absolute(path).unwrap_or_else(|_| path.clone()));
+		}
+		self.entries.push(entry);
+	}
+
+	// Whether the file was written by this run, given its absolute path
+	pub fn wrote(&self, path: &Path) -> bool { self.written.contains(path) }
+
+	// Reverts all the recorded changes, most recent first. Returns the number of
+	// changes which could not be reverted
+	pub fn rollback(&mut self) -> usize {
+		let mut failures = 0;
+		while let Some(entry) = self.entries.pop() {
+			debug!("Reverting {:?}", entry);
+			if let Err(e) = entry.revert() {
+				error!("Could not revert {:?}: {}", entry, e);
+				failures += 1;
+			}
+		}
+		failures
+	}
+
+	// Makes the recorded changes permanent, disposing of displaced files. Returns
+	// the number of displaced files which could not be disposed of
+	pub fn commit<F>(&mut self, mut discard: F) -> usize
+	where F: FnMut(&Path) -> Result<(), String> {
+		let mut failures = 0;
+		for entry in self.entries.drain(..) {
+			if let JournalEntry::Displaced { temp, .. } = entry {
+				if let Err(e) = discard(&temp) {
+					error!("{}", e);
+					failures += 1;
+				}
+			}
+		}
+		failures
+	}
+}
@@ -0,0 +1,178 @@
+use crate::{
+	EXIFTN_PREFIX, EXIFTOOL_PREFIX, EXIF_PREFIX, GPS_PREFIX, IFD0_PREFIX, IFD1_PREFIX, INTEROP_PREFIX, TYPED_KEY,
+	UNKNOWN_TAG_MARKER,
+};
+use handlebars::template::{HelperTemplate, Parameter, Template, TemplateElement};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashSet};
+
+// Named by the extractors rather than after an Exif tag
+const NAMED_PROPERTIES: [&str; 52] = [
+	"SysDateTimeNow",
+	"SysCwd",
+	"SysExt",
+	"SysDotExt",
+	"SysName",
+	"SysFullName",
+	"SysNameDateTime",
+	"SysPath",
+	"SysDateTimeModified",
+	"SysDateTimeCreated",
+	"SysDateTimeAccessed",
+	"SysSize",
+	"SysSizeHuman",
+	"SysIsSymlink",
+	"SysLinkTarget",
+	"SysImageWidth",
+	"SysImageHeight",
+	"SysSha1",
+	"SysShortId",
+	"SysCrc32",
+	"SysUuid",
+	"SysIdx",
+	"SysDirIdx",
+	"DestSeq",
+	"XmpRating",
+	"XmpLabel",
+	"XmpKeywords",
+	"XmpHierarchicalKeywords",
+	"ExifShutterCount",
+	"ExifDateTimeOriginalSubSec",
+	"ExifDateTimeOriginalUtc",
+	"ExifDateTimeOriginalTz",
+	"CalcMegapixels",
+	"CalcAspectRatio",
+	"CalcOrientation",
+	"CalcFocalLength35",
+	"CalcBurstId",
+	"CalcBurstFrame",
+	"CalcYear",
+	"CalcMonth",
+	"CalcIsoWeek",
+	"CalcQuarter",
+	"CalcSeason",
+	"CalcWeekday",
+	"CalcDaylight",
+	"GpsLatitudeDec",
+	"GpsLongitudeDec",
+	"GpsCoords",
+	"GpsGeoHash",
+	"GpsAltitudeM",
+	"GpsSpeedKmh",
+	"GpsImgDirectionDeg",
+];
+
+// Followed by the number of a folder in the path
+const NUMBERED_PROPERTIES: [&str; 4] = ["SysPathElem", "SysPathAncestor", "SysPathHead", "SysPathTail"];
+
+// Block helpers which change the context, so that names within them are not
+// properties of the file
+const CONTEXT_HELPERS: [&str; 2] = ["each", "with"];
+
+// Names of properties referenced by the template. Property names start with
+// an uppercase letter, helper names do not
+pub fn referenced(template: &Template) -> BTreeSet<String> {
+	let mut names = BTreeSet::new();
+	for element in &template.elements {
+		element_names(element, &mut names);
+	}
+	names.retain(|name| name.starts_with(|c: char| c.is_ascii_uppercase()));
+	names
+}
+
+fn element_names(element: &TemplateElement, names: &mut BTreeSet<String>) {
+	match element {
+		TemplateElement::Expression(helper) | TemplateElement::HtmlExpression(helper) => {
+			if helper.params.is_empty() && helper.hash.is_empty() {
+				if let Some(name) = helper.name.as_name() {
+					names.insert(name.to_string());
+				}
+			} else {
+				helper_names(helper, names);
+			}
+		}
+		TemplateElement::HelperBlock(helper) => {
+			helper_names(helper, names);
+			if !helper.name.as_name().is_some_and(|name| CONTEXT_HELPERS.contains(&name)) {
+				for template in helper.template.iter().chain(helper.inverse.iter()) {
+					template.elements.iter().for_each(|element| element_names(element, names));
+				}
+			}
+		}
+		_ => {}
+	}
+}
+
+fn helper_names(helper: &HelperTemplate, names: &mut BTreeSet<String>) {
+	for param in helper.params.iter().chain(helper.hash.values()) {
+		match param {
+			Parameter::Path(_) => names.extend(param.as_name().map(str::to_string)),
+			Parameter::Subexpression(subexpression) => element_names(&subexpression.element, names),
+			_ => {}
+		}
+	}
+}
+
+// Exif tag names as used in property names, for all the tags known to the exif
+// crate
+fn exif_tag_names() -> HashSet<String> {
+	[exif::Context::Tiff, exif::Context::Exif, exif::Context::Gps, exif::Context::Interop]
+		.into_iter()
+		.flat_map(|context| (0..=u16::MAX).map(move |number| exif::Tag(context, number)))
+		.filter(|tag| tag.description().is_some())
+		.map(|tag| tag.to_string())
+		.chain(
+			(0..=u16::MAX)
+				.filter_map(|number| crate::exif_text::xp_tag_name(exif::Tag(exif::Context::Tiff, number)))
+				.map(str::to_string),
+		)
+		.collect()
+}
+
+// Whether any extractor can produce the property. Names from exiftool, which
+// knows thousands of tags, and names of additional properties are assumed valid
+pub struct KnownProperties {
+	exif_tags: HashSet<String>,
+	additional: HashSet<String>,
+}
+
+impl KnownProperties {
+	pub fn new(additional: impl IntoIterator<Item = String>) -> Self {
+		KnownProperties { exif_tags: exif_tag_names(), additional: additional.into_iter().collect() }
+	}
+
+	pub fn contains(&self, name: &str) -> bool {
+		let name = name.strip_prefix(TYPED_KEY).and_then(|name| name.strip_prefix('.')).unwrap_or(name);
+		if self.additional.contains(name) || NAMED_PROPERTIES.contains(&name) || name.starts_with(EXIFTOOL_PREFIX) {
+			return true;
+		}
+		if NUMBERED_PROPERTIES.iter().any(|prefix| {
+			name.strip_prefix(prefix).is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+		}) {
+			return true;
+		}
+		[EXIFTN_PREFIX, EXIF_PREFIX, IFD0_PREFIX, IFD1_PREFIX, INTEROP_PREFIX, GPS_PREFIX]
+			.iter()
+			.filter_map(|prefix| name.strip_prefix(prefix))
+			.any(|tag| {
+				self.exif_tags.contains(tag)
+					|| self.exif_tags.contains(&format!("GPS{}", tag))
+					|| tag.trim_start_matches("Gps").trim_start_matches("Interop").starts_with(UNKNOWN_TAG_MARKER)
+			})
+	}
+}
+
+// Whether the property is missing or empty, following its path through
+// objects, e.g. Typed.SysSize
+pub fn is_empty(data: &Map<String, Value>, name: &str) -> bool {
+	let mut segments = name.split('.');
+	let mut value = segments.next().and_then(|first| data.get(first));
+	for segment in segments {
+		value = value.and_then(|value| value.get(segment));
+	}
+	match value {
+		None | Some(Value::Null) => true,
+		Some(Value::String(text)) => text.is_empty(),
+		Some(_) => false,
+	}
+}
@@ -1,36 +1,74 @@
-use chrono::{DateTime, Local, NaiveDateTime};
+mod archive;
+mod cache;
+mod checkpoint;
+mod checksums;
+mod demo;
+mod diff;
+mod dupes;
+mod exif_text;
+mod exiftool;
+mod journal;
+mod lint;
+mod makernote;
+mod metadata;
+mod pipeline;
+mod portable;
+mod progress;
+mod remote;
+mod review;
+mod sample;
+mod similar;
+mod state;
+mod stats;
+mod tz;
+mod undo;
+mod walk;
+mod xmp;
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use chrono_tz::Tz;
 use clap::builder::PossibleValue;
-use clap::{Parser, ValueEnum};
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
 use const_format::concatcp;
 use exif::In;
 use glob::*;
 use handlebars::handlebars_helper;
 use handlebars_misc_helpers::{env_helpers, path_helpers, regex_helpers, string_helpers};
+use journal::{Journal, JournalEntry};
 use log::*;
 use log4rs::append::console::{ConsoleAppender, Target};
-use num;
+use log4rs::append::file::FileAppender;
+use progress::Progress;
+use review::ReviewItem;
+use serde_json::json;
 use serde_json::value::*;
 use sha1::{Digest, Sha1};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fmt::Write;
-use std::io;
+use std::io::{self, IsTerminal as _, Read as _, Write as _};
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use std::time::UNIX_EPOCH;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Instant, UNIX_EPOCH};
 use std::{fmt, fs};
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 enum Mode {
+	#[default]
 	Move,
 	Copy,
+	Clone,
 	SymLink,
 	HardLink,
+	Dedup,
+	Audit,
 	Info,
-}
-
-impl Default for Mode {
-	fn default() -> Self { Self::Move }
+	Stats,
+	Dupes,
+	Similar,
+	Thumb,
 }
 
 impl fmt::Display for Mode {
@@ -40,21 +78,297 @@ impl fmt::Display for Mode {
 }
 
 impl ValueEnum for Mode {
-	fn value_variants<'a>() -> &'a [Self] { &[Self::Move, Self::Copy, Self::SymLink, Self::HardLink, Self::Info] }
+	fn value_variants<'a>() -> &'a [Self] {
+		&[
+			Self::Move,
+			Self::Copy,
+			Self::Clone,
+			Self::SymLink,
+			Self::HardLink,
+			Self::Dedup,
+			Self::Audit,
+			Self::Info,
+			Self::Stats,
+			Self::Dupes,
+			Self::Similar,
+			Self::Thumb,
+		]
+	}
 
 	fn to_possible_value(&self) -> Option<PossibleValue> {
 		Some(match self {
 			Self::Move => PossibleValue::new("mv"),
 			Self::Copy => PossibleValue::new("cp"),
+			Self::Clone => PossibleValue::new("clone")
+				.help("Copy-on-write clone where supported by the filesystem, regular copy otherwise"),
 			Self::SymLink => PossibleValue::new("symlink"),
 			Self::HardLink => PossibleValue::new("ln"),
+			Self::Dedup => PossibleValue::new("dedup")
+				.help("Copy the first file of each set with identical content, hard link the others to it"),
+			Self::Audit => PossibleValue::new("audit")
+				.help("Report source files whose path does not match the destination template, without changing them"),
 			Self::Info => PossibleValue::new("info"),
+			Self::Stats => PossibleValue::new("stats")
+				.help("Count files by camera, lens, month and extension, and files missing common metadata"),
+			Self::Dupes => PossibleValue::new("dupes")
+				.help("List the sets of files with identical content, with their size, without changing them"),
+			Self::Similar => PossibleValue::new("similar")
+				.help("List the sets of images which look alike, within --max-distance, without changing them"),
+			Self::Thumb => PossibleValue::new("thumb")
+				.help("Write the JPEG thumbnail embedded in the Exif data of each file to the destination"),
+		})
+	}
+}
+
+impl Mode {
+	// As shown in the end of run summary
+	fn past_tense(&self) -> &'static str {
+		match self {
+			Self::Move => "moved",
+			Self::Copy | Self::Dedup => "copied",
+			Self::Clone => "cloned",
+			Self::SymLink => "symlinked",
+			Self::HardLink => "hard linked",
+			Self::Audit => "audited",
+			Self::Info => "inspected",
+			Self::Stats => "counted",
+			Self::Dupes | Self::Similar => "compared",
+			Self::Thumb => "extracted",
+		}
+	}
+}
+
+// Why a file was left alone
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+enum SkipReason {
+	// another file in the same run has the same destination
+	Conflict,
+	// source and destination are already the same file
+	SameFile,
+	// the destination exists, and neither --force nor --backup were given
+	Exists,
+	// the source changed between planning and applying
+	Changed,
+	// -m thumb, and the source has no embedded thumbnail
+	NoThumbnail,
+}
+
+impl fmt::Display for SkipReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Conflict => "skipped-conflict",
+			Self::SameFile => "skipped-same",
+			Self::Exists => "skipped-exists",
+			Self::Changed => "skipped-changed",
+			Self::NoThumbnail => "skipped-no-thumbnail",
 		})
 	}
 }
 
+// Exit code of a run which completed without errors, but skipped some files or
+// raised warnings. 2 is already used by clap for invalid arguments
+const EXIT_WARNINGS: u8 = 3;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum)]
+enum SortBy {
+	/// Exif DateTimeOriginal, or DateTime, or the modification time if missing
+	ExifDate,
+	/// File modification time
+	Mtime,
+	/// File name, without the folder
+	Name,
+	/// File size
+	Size,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum)]
+enum ExtCase {
+	/// As in the source, or in the template
+	#[default]
+	Keep,
+	Lower,
+	Upper,
+}
+
+impl ExtCase {
+	fn apply(self, ext: &OsStr) -> std::ffi::OsString {
+		match self {
+			Self::Keep => ext.to_os_string(),
+			// left alone if not valid UTF-8
+			Self::Lower => ext.to_str().map_or_else(|| ext.to_os_string(), |ext| ext.to_lowercase().into()),
+			Self::Upper => ext.to_str().map_or_else(|| ext.to_os_string(), |ext| ext.to_uppercase().into()),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum)]
+enum Strip {
+	/// GPS position and time
+	Gps,
+	/// Body and lens serial numbers, unique image IDs and maker notes
+	Serial,
+	/// All the Exif and XMP metadata
+	AllExif,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum)]
+enum RecordOriginal {
+	/// xmpMM:PreservedFileName in the XMP sidecar, which is created if missing
+	Xmp,
+	/// DocumentName in the Exif data, JPEG files only. With -m mv, the same as
+	/// xmp
+	Exif,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum)]
+enum OnSourceChanged {
+	/// Skip the file, and apply the others
+	Skip,
+	/// Stop the run, before applying any change to the files of the pattern
+	Abort,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, ValueEnum)]
+enum TargetOs {
+	/// The system exif-namer runs on
+	#[default]
+	Auto,
+	/// Also for NTFS and exFAT volumes mounted on other systems
+	Windows,
+	Unix,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum)]
+enum BackupMode {
+	/// Append the backup suffix
+	Simple,
+	/// Append .~N~
+	Numbered,
+	/// Numbered if numbered backups already exist, simple otherwise
+	Existing,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum)]
+enum PrintPaths {
+	/// The source of each file
+	Src,
+	/// The destination of each file
+	Dest,
+	/// The source and the destination of each file
+	Both,
+}
+
 #[derive(Parser, Debug)]
-#[command(version, about = "Bulk rename large collections of images using Exif and OS data in the destination names")]
+#[command(
+	version,
+	about = "Bulk rename large collections of images using Exif and OS data in the destination names",
+	args_conflicts_with_subcommands = true
+)]
+struct Cli {
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	// without a subcommand, the same as rename
+	#[command(flatten)]
+	args: Args,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+	/// Move, copy or link files to their destination, the same as without a
+	/// subcommand
+	Rename(Args),
+	/// List the properties of each file, the same as -m info
+	Info(Args),
+	/// Show what would be done, without changing any file, the same as -n
+	Plan(Args),
+	/// Apply the changes, the same as rename
+	Apply(Args),
+	/// Report files which are not where the destination template would put
+	/// them, the same as -m audit
+	Verify(Args),
+	/// Print the properties of each file as a JSON object, one per line
+	Export(Args),
+	/// Summarize a collection, the same as -m stats
+	Stats(Args),
+	/// List the sets of files with identical content, the same as -m dupes
+	Dupes(Args),
+	/// List the sets of images which look alike, the same as -m similar
+	Similar(Args),
+	/// Compare the properties of two files side by side, highlighting the
+	/// differences
+	Diff(Args),
+	/// Render the destination template against sample properties, without
+	/// reading or changing any file
+	TestTemplate(Args),
+	/// Revert the changes of a previous run, as recorded with --log-json
+	Undo(UndoArgs),
+	/// Walk through a dry run, copy, audit and undo of sample files, the same
+	/// as --demo
+	Demo,
+}
+
+#[derive(clap::Args, Debug)]
+struct UndoArgs {
+	#[arg(help = "Event log written with --log-json. Run from the same folder as the run to revert")]
+	log: PathBuf,
+
+	#[arg(short = 'n', long, default_value_t = false, help = "Only list the changes which would be reverted")]
+	dry_run: bool,
+}
+
+enum Action {
+	Run(Box<Args>),
+	Undo(UndoArgs),
+	Demo,
+}
+
+impl Cli {
+	fn try_parse_action<I, T>(command_line: I) -> Result<Action, clap::Error>
+	where
+		I: IntoIterator<Item = T>,
+		T: Into<std::ffi::OsString> + Clone, {
+		let matches = Cli::command().try_get_matches_from(command_line)?;
+		Cli::from_arg_matches(&matches)?.action(&matches)
+	}
+
+	// Subcommands are shortcuts for flat arguments, except undo and demo. An
+	// explicit -m other than the one implied by the subcommand is rejected rather
+	// than silently replaced
+	fn action(self, matches: &clap::ArgMatches) -> Result<Action, clap::Error> {
+		// only called for subcommands taking Args, the others have no -m
+		let with_mode = |args: Args, mode: Mode| {
+			let explicit_mode = matches
+				.subcommand()
+				.is_some_and(|(_, sub)| sub.value_source("mode") == Some(clap::parser::ValueSource::CommandLine));
+			if explicit_mode && args.mode != mode {
+				let name = matches.subcommand_name().unwrap_or_default();
+				let message = format!("-m {} cannot be used with {}, which implies -m {}", args.mode, name, mode);
+				return Err(Cli::command().error(clap::error::ErrorKind::ArgumentConflict, message));
+			}
+			Ok(Args { mode, ..args })
+		};
+		let args = match self.command {
+			None if self.args.demo => return Ok(Action::Demo),
+			None => self.args,
+			Some(Command::Rename(args) | Command::Apply(args)) => args,
+			Some(Command::Info(args)) => with_mode(args, Mode::Info)?,
+			Some(Command::Plan(args)) => Args { dry_run: true, ..args },
+			Some(Command::Verify(args)) => with_mode(args, Mode::Audit)?,
+			Some(Command::Export(args)) => Args { export: true, ..with_mode(args, Mode::Info)? },
+			Some(Command::Stats(args)) => with_mode(args, Mode::Stats)?,
+			Some(Command::Dupes(args)) => with_mode(args, Mode::Dupes)?,
+			Some(Command::Similar(args)) => with_mode(args, Mode::Similar)?,
+			Some(Command::Diff(args)) => Args { diff: true, ..with_mode(args, Mode::Info)? },
+			Some(Command::TestTemplate(args)) => Args { test_template: true, ..args },
+			Some(Command::Undo(undo_args)) => return Ok(Action::Undo(undo_args)),
+			Some(Command::Demo) => return Ok(Action::Demo),
+		};
+		Ok(Action::Run(Box::new(args)))
+	}
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
 	#[arg(help = "A list of glob patterns, each identifying a set of files to inspect and rename")]
 	sources: Vec<String>,
@@ -69,6 +383,33 @@ struct Args {
 	)]
 	destination: String,
 
+	#[arg(
+		short,
+		long,
+		value_name = "PATH",
+		help = "Folder prepended to every relative destination, so that templates can stay relative"
+	)]
+	output_dir: Option<PathBuf>,
+
+	#[arg(
+		long,
+		value_name = "FILE",
+		conflicts_with = "destination",
+		help = "Read the destination template from a file, reloaded for each file. Leading and trailing blanks of \
+			each line, and line breaks, are ignored, so that the template can be laid out on several lines"
+	)]
+	destination_file: Option<PathBuf>,
+
+	#[arg(
+		long,
+		value_name = "MODE:TEMPLATE",
+		value_parser = parse_also,
+		help = "Also create a copy or link of each file in another destination, once it is in place, e.g. \
+			\"symlink:by-camera/{{ExifModel}}/{{SysFullName}}\". MODE is one of cp, clone, symlink or ln. Can be \
+			repeated"
+	)]
+	also: Vec<(Mode, String)>,
+
 	#[arg(short, long, default_value_t=Mode::Move)]
 	mode: Mode,
 
@@ -76,12 +417,70 @@ struct Args {
 		short,
 		long,
 		default_value = "%Y%m%d_%H%M%S",
-		help = "Format string for datetime type properties. Uses chrono and POSIX date syntax"
+		help = "Format string for datetime type properties. Uses chrono and POSIX date syntax",
+		long_help = "Format string for datetime type properties. Uses chrono and POSIX date syntax. Exif \
+			timestamps include the fraction of second from the SubSecTime tags, if present, which can be rendered \
+			with %f (nanoseconds), %3f (milliseconds) or %6f (microseconds)"
 	)]
 	timestamp_format: String,
 
-	#[arg(short, long, default_value_t = false, help = "Log more debugging information.")]
-	verbose: bool,
+	#[arg(
+		short,
+		long,
+		action = clap::ArgAction::Count,
+		help = "List each operation. Repeat to log debugging (-vv) and tracing (-vvv) information"
+	)]
+	verbose: u8,
+
+	#[arg(
+		short,
+		long,
+		default_value_t = false,
+		conflicts_with = "verbose",
+		help = "Only log errors and the end of run summary"
+	)]
+	quiet: bool,
+
+	#[arg(long, value_name = "FILE", help = "Append a JSON object for each file processed to FILE, one per line")]
+	log_json: Option<PathBuf>,
+
+	#[arg(long, value_name = "PATH", help = "Also append log messages to a file")]
+	log_file: Option<PathBuf>,
+
+	#[arg(
+		long,
+		value_name = "FILE",
+		conflicts_with = "no_sha1",
+		help = "Write the sha1 hash of each destination file to FILE, as sha1sum does, for checking with sha1sum -c"
+	)]
+	write_checksums: Option<PathBuf>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "In -m mv, cp, clone and dedup, append an entry for each file added to .exif-namer.json in its \
+			destination folder, with its original path, hash and the time it was added, so that it can be traced back \
+			long after the run"
+	)]
+	dest_manifest: bool,
+
+	#[arg(
+		long,
+		value_name = "PATH",
+		conflicts_with = "log_file",
+		help = "Configure logging from a log4rs YAML file, instead of logging to stderr"
+	)]
+	log_config: Option<PathBuf>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Review the planned operations full screen, and choose which ones to apply"
+	)]
+	review: bool,
+
+	#[arg(long, default_value_t = false, help = "Show progress, throughput and estimated time left on stderr")]
+	progress: bool,
 
 	#[arg(short = 'n', long, default_value_t = false, help = "Do not apply any changes to the filesystem")]
 	dry_run: bool,
@@ -89,103 +488,542 @@ struct Args {
 	#[arg(short, long, default_value_t = false, help = "Force overwrite if destination file exists")]
 	force: bool,
 
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Move destination files overwritten by --force to the trash, instead of deleting them"
+	)]
+	use_trash: bool,
+
+	#[arg(
+		long,
+		num_args = 0..=1,
+		default_missing_value = "simple",
+		value_name = "CONTROL",
+		help = "Overwrite existing destination files, renaming them aside first"
+	)]
+	backup: Option<BackupMode>,
+
+	#[arg(long, default_value = "~", help = "Suffix appended to the name of simple backups")]
+	backup_suffix: String,
+
+	#[arg(
+		long,
+		value_name = "PATHS",
+		help = "Print the paths of each file processed on stdout, one file per line, for other tools to consume, \
+			e.g. with -n. The source and the destination are separated by a tab"
+	)]
+	print: Option<PrintPaths>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Terminate each path printed by --print with a NUL character, for xargs -0 and names with any \
+			character. Prints both paths unless --print is specified"
+	)]
+	print0: bool,
+
+	#[arg(
+		long,
+		value_name = "ACTION",
+		default_value = "skip",
+		help = "What to do with sources whose size or modification time changed between planning and applying, \
+			e.g. while reviewing the plan"
+	)]
+	on_source_changed: OnSourceChanged,
+
+	#[arg(
+		long,
+		value_name = "WHAT",
+		value_delimiter = ',',
+		help = "In -m cp and clone, remove metadata from the copies, leaving the sources untouched, e.g. gps,serial. \
+			XMP packets which may contain it are removed as a whole. Only JPEG files are supported"
+	)]
+	strip: Vec<Strip>,
+
+	#[arg(
+		long,
+		value_name = "WHERE",
+		help = "In -m mv, cp and clone, record the original path of each file in its metadata, so that it can be \
+			traced even without the journal. A path already recorded by an earlier run is kept"
+	)]
+	record_original: Option<RecordOriginal>,
+
+	#[arg(long, default_value_t = false, help = "Abort the run at the first error")]
+	fail_fast: bool,
+
+	#[arg(long, value_name = "N", help = "Abort the run once more than N errors have occurred")]
+	max_errors: Option<usize>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "If any operation fails, revert all the changes applied so far and stop"
+	)]
+	transactional: bool,
+
 	#[arg(long, default_value_t = false, help = "Disable Handlebars strict mode")]
 	no_strict: bool,
 
 	#[arg(long, default_value_t = false, help = "Disable (slow) sha1 hash calculation")]
 	no_sha1: bool,
 
+	#[arg(
+		long,
+		default_value_t = 6,
+		value_parser = clap::value_parser!(u8).range(1..=12),
+		help = "Number of base36 characters of the content hash in SysShortId"
+	)]
+	short_id_length: u8,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		conflicts_with = "no_sha1",
+		help = "Compute the CRC32 checksum of the content as SysCrc32, in the same pass as the sha1 hash"
+	)]
+	crc32: bool,
+
 	#[arg(long, default_value_t = false, help = "Disable exif parsing")]
 	no_exif: bool,
 
+	#[arg(
+		long,
+		short = 'j',
+		value_name = "N",
+		help = "Threads hashing and parsing files ahead of the one being processed, 0 for none. Defaults to the number \
+		        of CPUs"
+	)]
+	jobs: Option<usize>,
+
+	#[arg(
+		long,
+		value_name = "ENCODING",
+		value_parser = exif_text::parse_encoding,
+		help = "Encoding of Exif text, e.g. latin1 or shift_jis. Detected for each value if not specified"
+	)]
+	exif_encoding: Option<&'static encoding_rs::Encoding>,
+
 	#[arg(long, default_value_t = false, help = "When moving files, delete the source folder if empty")]
 	delete_empty_dirs: bool,
 
 	#[arg(
 		long,
 		default_value_t = false,
-		help = "Convert symlink targets to absolute path even if a relative path is available"
+		help = "In -m audit, move non-conforming files to their expected destination. Use with -n to list the moves only"
 	)]
-	force_absolute_symlinks: bool,
+	repair: bool,
 
-	#[arg(long, default_value_t = 100, help = "Truncate long values in -m info. Set to 0 for infinite length")]
-	max_display_len: usize,
-
-	#[arg(long, default_value_t = 0, help = "Index counter start")]
-	idx_start: usize,
+	#[arg(
+		long,
+		default_value_t = false,
+		conflicts_with = "fail_on_unreadable",
+		help = "Silently skip files and folders which cannot be read, without counting them as errors"
+	)]
+	skip_unreadable: bool,
 
-	#[arg(long, default_value_t = 6, help = "Width of zero-padding for index counter")]
-	idx_width: usize,
+	#[arg(long, default_value_t = false, help = "Abort the run as soon as a file or folder cannot be read")]
+	fail_on_unreadable: bool,
 
 	#[arg(
 		long,
-		default_value = "[^\\w\\+\\-]+",
-		help = "Regex pattern which identifies invalid characters or sequences in properties"
+		default_value_t = false,
+		help = "Match hidden files and folders, whose name starts with a dot, with wildcards. Otherwise only a pattern \
+			spelling out the dot matches them"
 	)]
-	invalid_characters: String,
+	hidden: bool,
 
-	#[arg(long, default_value = "_", help = "Replacement for invalid characters or sequences in properties")]
-	replacement: String,
-}
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Match the wildcard parts of source patterns regardless of case, e.g. *.jpg also matches IMG_0001.JPG"
+	)]
+	iglob: bool,
 
-#[derive(Clone, Debug)]
-enum PropertyValue {
-	Text(String),
-	Path(PathBuf),
-	Timestamp(NaiveDateTime),
-	Integer(i64),
-	Fraction(i64, i64),
-	Real(f64),
-	Nothing,
-}
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Process the targets of symbolic links among the sources rather than the links, each target once \
+			however many links lead to it"
+	)]
+	follow_symlinks: bool,
 
-trait Pair<I> {
-	fn as_pair(&self) -> (I, I);
-}
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Copy extended attributes (e.g. Finder tags, user.* attributes) when copying or moving across filesystems"
+	)]
+	preserve_xattrs: bool,
 
-impl Pair<u32> for exif::Rational {
-	fn as_pair(&self) -> (u32, u32) { (self.num, self.denom) }
-}
+	#[arg(
+		long,
+		num_args = 0..=1,
+		default_missing_value = "ExifDateTimeOriginal",
+		value_name = "PROPERTY",
+		help = "After moving or copying, set the destination modification time from a timestamp property"
+	)]
+	touch: Option<String>,
 
-impl Pair<i32> for exif::SRational {
-	fn as_pair(&self) -> (i32, i32) { (self.num, self.denom) }
-}
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Treat destinations differing only by case as the same file, as on Windows and macOS filesystems. \
+			Detected on the destination otherwise"
+	)]
+	case_insensitive: bool,
 
-impl PropertyValue {
-	fn from_opt_str(from: Option<&str>) -> Self {
-		match from {
-			Some(word) => PropertyValue::Text(String::from(word)),
-			None => PropertyValue::Nothing,
-		}
-	}
+	#[arg(
+		long,
+		value_enum,
+		default_value_t = TargetOs::Auto,
+		help = "System whose naming rules destinations must follow. On Windows, reserved names such as CON or \
+			AUX, trailing dots and spaces, and characters such as : are replaced"
+	)]
+	target_os: TargetOs,
 
-	fn from_opt_str_datetime(from: Option<&str>) -> Self {
-		match from {
-			Some(word) => match NaiveDateTime::parse_from_str(word, "%Y:%m:%d %H:%M:%S") {
-				Ok(dt) => PropertyValue::Timestamp(dt),
-				Err(e) => {
-					warn!("Unable to parse '{}' as date: {:?}", word, e);
-					PropertyValue::Text(String::from(word))
-				}
-			},
-			None => PropertyValue::Nothing,
-		}
-	}
+	#[arg(
+		long,
+		value_enum,
+		default_value_t = ExtCase::Keep,
+		help = "Case of the extension, both in SysExt and SysDotExt and in the rendered destination"
+	)]
+	ext_case: ExtCase,
 
-	fn from_opt_path<P: AsRef<Path>>(from: Option<P>) -> Self {
-		match from {
-			Some(dir) => PropertyValue::Path(PathBuf::from(dir.as_ref())),
-			None => PropertyValue::Nothing,
-		}
-	}
+	#[arg(
+		long,
+		default_value_t = 255,
+		help = "Truncate names in the destination longer than this number of bytes, keeping the extension"
+	)]
+	max_name_len: usize,
 
-	fn from_opt_integer<T>(from: Option<&T>) -> Self
-	where T: Into<i64> + Copy {
-		match from {
-			Some(n) => PropertyValue::Integer((*n).into()),
-			None => PropertyValue::Nothing,
-		}
-	}
+	#[arg(
+		long,
+		value_name = "MAX_PATH_LEN",
+		help = "Truncate the file name if the full destination path is longer than this number of bytes"
+	)]
+	max_path_len: Option<usize>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Append a short hash of the original name to truncated names, so that they stay distinct"
+	)]
+	truncate_hash: bool,
+
+	#[arg(long, default_value_t = false, help = "After copying, hash the destination and compare it with the source")]
+	verify: bool,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Convert symlink targets to absolute path even if a relative path is available"
+	)]
+	force_absolute_symlinks: bool,
+
+	#[arg(long, default_value_t = 6, help = "Number of decimal digits of GPS coordinates in decimal degrees")]
+	gps_precision: usize,
+
+	#[arg(
+		long,
+		default_value_t = 5,
+		value_parser = clap::value_parser!(u8).range(1..=12),
+		help = "Number of characters of GpsGeoHash, 5 for cells of about 5km, each more about 8 times smaller"
+	)]
+	geohash_precision: u8,
+
+	#[arg(
+		long,
+		value_name = "SHIFT",
+		allow_hyphen_values = true,
+		value_parser = parse_time_shift,
+		help = "Shift all Exif timestamps, e.g. -1h30m or +2d, to correct a camera clock. Units are d, h, m and s"
+	)]
+	time_shift: Option<Duration>,
+
+	#[arg(
+		long,
+		value_name = "FILE",
+		help = "Per-camera clock offsets, one per line as MODEL[#SERIAL] = OFFSET, e.g. NIKON D750#6001234 = +00:02:13",
+		long_help = "Per-camera clock offsets, added to all the Exif timestamps of matching files together with \
+			--time-shift. One per line as MODEL[#SERIAL] = OFFSET, e.g. NIKON D750#6001234 = +00:02:13. \
+			MODEL and SERIAL are matched against the Exif Model and BodySerialNumber tags, and an entry with a \
+			serial number takes precedence over one without. OFFSET is either [+-]HH:MM[:SS] or in --time-shift \
+			syntax. Lines starting with # are ignored"
+	)]
+	camera_offsets: Option<PathBuf>,
+
+	#[arg(
+		long,
+		value_name = "FILE",
+		help = "GeoJSON timezone boundaries, as published by timezone-boundary-builder, used to look up the timezone \
+			of GPS positions"
+	)]
+	tz_boundaries: Option<PathBuf>,
+
+	#[arg(
+		long,
+		value_name = "TZ",
+		help = "Convert ExifDateTimeOriginal to this IANA timezone, when the timezone where it was taken is known"
+	)]
+	render_timezone: Option<Tz>,
+
+	#[arg(long, default_value_t = 100, help = "Truncate long values in -m info. Set to 0 for infinite length")]
+	max_display_len: usize,
+
+	#[arg(
+		long,
+		value_name = "REGEX",
+		help = "In -m info, only list the properties whose name matches REGEX, e.g. ^Gps"
+	)]
+	keys: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "PATTERNS",
+		value_delimiter = ',',
+		help = "In -m info, only list the properties whose name, with or without the prefix of its source, matches one of these comma separated wildcard patterns, e.g. DateTime*,Gps*"
+	)]
+	only: Vec<String>,
+
+	#[arg(long, default_value_t = false, help = "In -m info, only list the properties which came back empty")]
+	missing: bool,
+
+	#[arg(long, value_name = "KEY", help = "Sort the files matching each source pattern before assigning indexes")]
+	sort_by: Option<SortBy>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Keep the matches in the order the filesystem lists them, rather than sorted bytewise by path"
+	)]
+	unsorted: bool,
+
+	#[arg(
+		long,
+		value_name = "MS",
+		default_value_t = 500,
+		help = "Maximum time between two shots of the same burst, in milliseconds. Shots of the same camera taken \
+			closer than this are grouped as CalcBurstId and numbered as CalcBurstFrame"
+	)]
+	burst_gap: i64,
+
+	#[arg(long, default_value_t = false, requires = "sort_by", help = "Sort in descending order")]
+	reverse: bool,
+
+	#[arg(long, default_value_t = 0, help = "Index counter start")]
+	idx_start: usize,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Restart the index counter every time the folder of the source file changes"
+	)]
+	idx_per_dir: bool,
+
+	#[arg(
+		long,
+		value_name = "EXT",
+		value_delimiter = ',',
+		help = "Companion files to move or copy together with each file, e.g. xmp,aae,thm,lrv. Named either after \
+			the file, e.g. IMG_0001.xmp, or after the file and its extension, e.g. IMG_0001.CR2.xmp"
+	)]
+	with_sidecars: Vec<String>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Assign the same index to files with the same name in the same folder, e.g. IMG_0001.CR3 and \
+			IMG_0001.JPG, so that they stay paired"
+	)]
+	idx_per_stem: bool,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Leave alone files which are already where the destination template would put them, regardless of \
+			their index, without consuming any index"
+	)]
+	skip_organized: bool,
+
+	#[arg(
+		long,
+		value_name = "FILE",
+		help = "Record processed files in this SQLite database, and skip them in later runs unless they have changed"
+	)]
+	state: Option<PathBuf>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Resume the last run of the same command which was interrupted, skipping the files it completed and \
+		        continuing its indexes"
+	)]
+	resume: bool,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Neither reuse nor save the properties extracted from unchanged files in previous runs"
+	)]
+	no_cache: bool,
+
+	#[arg(
+		long,
+		value_name = "COMMAND",
+		help = "Run a command after each file is processed successfully, with {src} and {dest} replaced by its paths, \
+			e.g. \"convert -thumbnail 256 {dest} {dest}.thumb.jpg\". Not run in a dry run"
+	)]
+	exec_after: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "COMMAND",
+		help = "Run a command once at the end, if any file was processed, with the EXIF_NAMER_JOURNAL environment \
+			variable set to a file listing the files processed as JSON objects, one per line. Not run in a dry run"
+	)]
+	exec_batch_after: Option<String>,
+
+	#[arg(
+		long,
+		value_name = "NAME=COMMAND",
+		value_parser = parse_prop_command,
+		help = "Add a property computed by a shell command for each file, with {} replaced by its path, e.g. \
+			\"Pages=pdfinfo {} | grep Pages | tr -dc 0-9\". The output is the value of the property. Can be repeated"
+	)]
+	prop_command: Vec<(String, String)>,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "Add the tags found by exiftool, which must be installed, as Exiftool properties, e.g. ExiftoolLensID"
+	)]
+	exiftool: bool,
+
+	#[arg(
+		long,
+		value_name = "FILE",
+		conflicts_with = "exiftool",
+		help = "Add the tags listed in FILE as Exiftool properties, as generated by exiftool -j from the current folder"
+	)]
+	exiftool_json: Option<PathBuf>,
+
+	#[arg(long, default_value_t = 6, help = "Width of zero-padding for index counter")]
+	idx_width: usize,
+
+	#[arg(
+		long,
+		default_value = "[^\\w\\+\\-]+",
+		help = "Regex pattern which identifies invalid characters or sequences in properties"
+	)]
+	invalid_characters: String,
+
+	#[arg(long, default_value = "_", help = "Replacement for invalid characters or sequences in properties")]
+	replacement: String,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		exclusive = true,
+		help = "Walk through a dry run, copy, audit and undo of sample files in a temporary folder, removed at the end"
+	)]
+	demo: bool,
+
+	// set by the export subcommand
+	#[arg(skip)]
+	export: bool,
+
+	#[arg(
+		long,
+		default_value_t = false,
+		help = "With -m dupes or -m similar, print each set of files as a JSON object, one per line"
+	)]
+	json: bool,
+
+	#[arg(
+		long,
+		default_value_t = 10,
+		value_parser = clap::value_parser!(u32).range(0..=64),
+		help = "With -m similar, the number of bits by which the perceptual hashes of two similar images may differ"
+	)]
+	max_distance: u32,
+
+	// set by the diff subcommand
+	#[arg(skip)]
+	diff: bool,
+
+	#[arg(
+		long,
+		value_name = "FILE",
+		help = "In test-template, render against the properties in FILE instead of built-in sample properties: JSON \
+			objects of properties, or the output of export"
+	)]
+	sample: Option<PathBuf>,
+
+	// set by the test-template subcommand
+	#[arg(skip)]
+	test_template: bool,
+}
+
+#[derive(Clone, Debug)]
+enum PropertyValue {
+	Text(String),
+	Path(PathBuf),
+	Timestamp(NaiveDateTime),
+	Integer(i64),
+	Fraction(i64, i64),
+	Real(f64),
+	// Exif values with more than one element, formatted as the first one
+	List(Vec<PropertyValue>),
+	Nothing,
+}
+
+trait Pair<I> {
+	fn as_pair(&self) -> (I, I);
+}
+
+impl Pair<u32> for exif::Rational {
+	fn as_pair(&self) -> (u32, u32) { (self.num, self.denom) }
+}
+
+impl Pair<i32> for exif::SRational {
+	fn as_pair(&self) -> (i32, i32) { (self.num, self.denom) }
+}
+
+impl PropertyValue {
+	fn from_opt_str(from: Option<&str>) -> Self {
+		match from {
+			Some(word) => PropertyValue::Text(String::from(word)),
+			None => PropertyValue::Nothing,
+		}
+	}
+
+	fn from_opt_str_datetime(from: Option<&str>) -> Self {
+		match from {
+			Some(word) => match NaiveDateTime::parse_from_str(word, "%Y:%m:%d %H:%M:%S") {
+				Ok(dt) => PropertyValue::Timestamp(dt),
+				Err(e) => {
+					warn!("Unable to parse '{}' as date: {:?}", word, e);
+					PropertyValue::Text(String::from(word))
+				}
+			},
+			None => PropertyValue::Nothing,
+		}
+	}
+
+	fn from_opt_path<P: AsRef<Path>>(from: Option<P>) -> Self {
+		match from {
+			Some(dir) => PropertyValue::Path(PathBuf::from(dir.as_ref())),
+			None => PropertyValue::Nothing,
+		}
+	}
+
+	fn from_opt_integer<T>(from: Option<&T>) -> Self
+	where T: Into<i64> + Copy {
+		match from {
+			Some(n) => PropertyValue::Integer((*n).into()),
+			None => PropertyValue::Nothing,
+		}
+	}
 
 	fn from_opt_real<T>(from: Option<&T>) -> Self
 	where T: Into<f64> + Copy {
@@ -208,6 +1046,84 @@ impl PropertyValue {
 		}
 	}
 
+	// Numbers stay numbers, fractions are evaluated, lists become arrays
+	fn to_typed(&self) -> Value {
+		match self {
+			PropertyValue::Text(text) => Value::String(text.clone()),
+			PropertyValue::Path(path) => Value::String(portable::escape(path.as_os_str())),
+			PropertyValue::Timestamp(timestamp) => Value::String(timestamp.format(ISO_TIMESTAMP_FORMAT).to_string()),
+			PropertyValue::Integer(n) => Value::from(*n),
+			PropertyValue::Fraction(_, 0) => Value::Null,
+			PropertyValue::Fraction(n, d) => number(*n as f64 / *d as f64),
+			PropertyValue::Real(v) => number(*v),
+			PropertyValue::List(values) => Value::Array(values.iter().map(PropertyValue::to_typed).collect()),
+			PropertyValue::Nothing => Value::Null,
+		}
+	}
+
+	fn from_slice<T, F>(from: &[T], convert: F) -> Self
+	where F: Fn(Option<&T>) -> Self {
+		if from.len() > 1 {
+			PropertyValue::List(from.iter().map(|v| convert(Some(v))).collect())
+		} else {
+			convert(from.first())
+		}
+	}
+
+	// As listed by exiftool, with dates in the Exif format
+	fn from_json(value: &Value) -> Self {
+		match value {
+			Value::String(text) => match NaiveDateTime::parse_from_str(text, "%Y:%m:%d %H:%M:%S") {
+				Ok(timestamp) => PropertyValue::Timestamp(timestamp),
+				Err(_) => PropertyValue::Text(text.clone()),
+			},
+			Value::Number(n) => match n.as_i64() {
+				Some(n) => PropertyValue::Integer(n),
+				None => n.as_f64().map_or(PropertyValue::Nothing, PropertyValue::Real),
+			},
+			Value::Bool(b) => PropertyValue::Text(b.to_string()),
+			Value::Array(values) => PropertyValue::List(values.iter().map(PropertyValue::from_json).collect()),
+			Value::Object(_) | Value::Null => PropertyValue::Nothing,
+		}
+	}
+
+	// Tagged with the variant, so that it can be restored from the property cache
+	fn to_cached(&self) -> Value {
+		let (variant, value) = match self {
+			PropertyValue::Text(text) => ("Text", Value::String(text.clone())),
+			PropertyValue::Path(path) => ("Path", Value::String(portable::escape(path.as_os_str()))),
+			PropertyValue::Timestamp(timestamp) =>
+				("Timestamp", Value::String(timestamp.format(ISO_TIMESTAMP_FORMAT).to_string())),
+			PropertyValue::Integer(n) => ("Integer", Value::from(*n)),
+			PropertyValue::Fraction(n, d) => ("Fraction", Value::from(vec![*n, *d])),
+			PropertyValue::Real(v) => ("Real", Value::from(*v)),
+			PropertyValue::List(values) =>
+				("List", Value::Array(values.iter().map(PropertyValue::to_cached).collect())),
+			PropertyValue::Nothing => ("Nothing", Value::Null),
+		};
+		let mut cached = Map::new();
+		cached.insert(variant.to_string(), value);
+		Value::Object(cached)
+	}
+
+	fn from_cached(cached: &Value) -> Option<Self> {
+		let (variant, value) = cached.as_object()?.iter().next()?;
+		Some(match (variant.as_str(), value) {
+			("Text", Value::String(text)) => PropertyValue::Text(text.clone()),
+			("Path", Value::String(path)) => PropertyValue::Path(portable::unescape(path)),
+			("Timestamp", Value::String(timestamp)) =>
+				PropertyValue::Timestamp(NaiveDateTime::parse_from_str(timestamp, ISO_TIMESTAMP_FORMAT).ok()?),
+			("Integer", n) => PropertyValue::Integer(n.as_i64()?),
+			("Fraction", Value::Array(pair)) =>
+				PropertyValue::Fraction(pair.first()?.as_i64()?, pair.get(1)?.as_i64()?),
+			("Real", v) => PropertyValue::Real(v.as_f64()?),
+			("List", Value::Array(values)) =>
+				PropertyValue::List(values.iter().map(PropertyValue::from_cached).collect::<Option<_>>()?),
+			("Nothing", _) => PropertyValue::Nothing,
+			_ => return None,
+		})
+	}
+
 	fn from_opt_filetime(from: Option<std::time::SystemTime>) -> PropertyValue {
 		match from
 			.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
@@ -220,51 +1136,587 @@ impl PropertyValue {
 	}
 }
 
-struct ExifAttrFormatter {
-	date_time_format: String,
-	sanitize_key_pattern: regex::Regex,
-	sanitize_value_pattern: regex::Regex,
-	sanitize_replacement: String,
+// Either a signed [+-]HH:MM[:SS] or a time shift
+fn parse_clock_offset(offset: &str) -> Result<Duration, String> {
+	if !offset.contains(':') {
+		return parse_time_shift(offset);
+	}
+	let (negative, hms) = match offset.strip_prefix('-') {
+		Some(rest) => (true, rest),
+		None => (false, offset.strip_prefix('+').unwrap_or(offset)),
+	};
+	let parts = hms
+		.split(':')
+		.map(|part| part.parse::<i64>().map_err(|_| format!("invalid offset '{}'", offset)))
+		.collect::<Result<Vec<_>, _>>()?;
+	let total = match parts[..] {
+		[h, m] => Duration::hours(h) + Duration::minutes(m),
+		[h, m, s] => Duration::hours(h) + Duration::minutes(m) + Duration::seconds(s),
+		_ => return Err(format!("invalid offset '{}', expected [+-]HH:MM[:SS]", offset)),
+	};
+	Ok(if negative { -total } else { total })
 }
 
-impl ExifAttrFormatter {
-	fn new(
-		date_time_format: String,
-		sanitize_pattern: &str,
-		sanitize_replacement: String,
-	) -> Result<Self, regex::Error> {
-		Ok(ExifAttrFormatter {
-			date_time_format,
-			sanitize_key_pattern: regex::Regex::new("\\W+")?,
-			sanitize_value_pattern: regex::Regex::new(sanitize_pattern)?,
-			sanitize_replacement,
-		})
+// MODEL[#SERIAL] -> clock offset
+fn load_camera_offsets<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Duration>, String> {
+	let path = path.as_ref();
+	let content = fs::read_to_string(path).map_err(|e| format!("Unable to read camera offsets {:?}: {}", path, e))?;
+	let mut offsets = HashMap::new();
+	for (n, line) in content.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let (camera, offset) = line
+			.rsplit_once('=')
+			.ok_or_else(|| format!("{:?} line {}: expected MODEL[#SERIAL] = OFFSET", path, n + 1))?;
+		let offset = parse_clock_offset(offset.trim()).map_err(|e| format!("{:?} line {}: {}", path, n + 1, e))?;
+		offsets.insert(camera.trim().to_string(), offset);
 	}
+	Ok(offsets)
+}
 
-	fn fmt<W>(&self, value: &PropertyValue, f: &mut W) -> fmt::Result
-	where W: Write {
-		match value {
-			// write!(f, "{}", strings)
-			PropertyValue::Text(ref text) => f.write_str(text),
-			PropertyValue::Path(ref path) => f.write_str(path.to_str().unwrap_or("")),
-			PropertyValue::Timestamp(ref timestamp) =>
-				f.write_str(&timestamp.format(&self.date_time_format).to_string()),
-			PropertyValue::Integer(ref value) => write!(f, "{}", value),
-			PropertyValue::Fraction(ref num, ref den) => write!(f, "{}_{}", num, den),
+// A signed sequence of amounts and units, such as -1h30m
+fn parse_time_shift(shift: &str) -> Result<Duration, String> {
+	let (negative, mut rest) = match shift.strip_prefix('-') {
+		Some(rest) => (true, rest),
+		None => (false, shift.strip_prefix('+').unwrap_or(shift)),
+	};
+	if rest.is_empty() {
+		return Err("empty time shift".to_string());
+	}
+	let mut total = Duration::zero();
+	while !rest.is_empty() {
+		let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+		let amount: i64 = rest[..digits].parse().map_err(|_| format!("expected a number in '{}'", shift))?;
+		let unit =
+			rest[digits..].chars().next().ok_or_else(|| format!("missing unit after {} in '{}'", amount, shift))?;
+		total += match unit {
+			'd' => Duration::days(amount),
+			'h' => Duration::hours(amount),
+			'm' => Duration::minutes(amount),
+			's' => Duration::seconds(amount),
+			_ => return Err(format!("unknown unit '{}' in '{}', expected d, h, m or s", unit, shift)),
+		};
+		rest = &rest[digits + unit.len_utf8()..];
+	}
+	Ok(if negative { -total } else { total })
+}
+
+fn parse_also(output: &str) -> Result<(Mode, String), String> {
+	let Some((mode, template)) = output.split_once(':') else {
+		return Err(format!("expected MODE:TEMPLATE, found '{}'", output));
+	};
+	match Mode::from_str(mode, true)? {
+		mode @ (Mode::Copy | Mode::Clone | Mode::SymLink | Mode::HardLink) => Ok((mode, template.to_string())),
+		mode => Err(format!("{} cannot be used for an additional destination", mode)),
+	}
+}
+
+fn parse_prop_command(declaration: &str) -> Result<(String, String), String> {
+	match declaration.split_once('=') {
+		Some((name, command)) if !name.trim().is_empty() && !command.trim().is_empty() =>
+			Ok((name.trim().to_string(), command.to_string())),
+		_ => Err(format!("expected NAME=COMMAND, found '{}'", declaration)),
+	}
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str, path: &Path) -> std::process::Command {
+	let mut process = std::process::Command::new("sh");
+	process.arg("-c").arg(command.replace("{}", &shell_words::quote(&path.to_string_lossy())));
+	process
+}
+
+#[cfg(not(unix))]
+fn shell_command(command: &str, path: &Path) -> std::process::Command {
+	let mut process = std::process::Command::new("cmd");
+	process.arg("/C").arg(command.replace("{}", &format!("\"{}\"", path.to_string_lossy())));
+	process
+}
+
+// Files are read in large chunks, and larger files on a thread of their own, so
+// that reading the next chunk overlaps hashing the current one. SHA-1 itself
+// can only be computed sequentially
+const HASH_CHUNK_SIZE: usize = 4 << 20;
+
+// The content hash, and the CRC32 checksum if asked for, in uppercase hex as
+// SFV files have it
+#[derive(Debug)]
+struct FileHashes {
+	sha1: String,
+	crc32: Option<String>,
+}
+
+fn file_sha1<P: AsRef<Path>>(path: P) -> io::Result<String> { file_hashes(path, false).map(|hashes| hashes.sha1) }
+
+// Both computed in the same pass over the content
+fn file_hashes<P: AsRef<Path>>(path: P, crc32: bool) -> io::Result<FileHashes> {
+	let mut file = fs::File::open(path)?;
+	let mut sha1_hasher = Sha1::new();
+	let mut crc32_hasher = crc32.then(crc32fast::Hasher::new);
+	let mut update = |chunk: &[u8]| {
+		sha1_hasher.update(chunk);
+		if let Some(ref mut crc32_hasher) = crc32_hasher {
+			crc32_hasher.update(chunk);
+		}
+	};
+	let len = file.metadata()?.len();
+	if len <= HASH_CHUNK_SIZE as u64 {
+		let mut content = Vec::with_capacity(len as usize);
+		file.read_to_end(&mut content)?;
+		update(&content);
+	} else {
+		std::thread::scope(|scope| {
+			// at most two chunks read ahead
+			let (sender, receiver) = std::sync::mpsc::sync_channel::<io::Result<Vec<u8>>>(2);
+			scope.spawn(move || loop {
+				let mut chunk = Vec::with_capacity(HASH_CHUNK_SIZE);
+				let read = (&mut file).take(HASH_CHUNK_SIZE as u64).read_to_end(&mut chunk);
+				let last = !matches!(read, Ok(n) if n > 0);
+				// stops early if the receiver has given up on an error
+				if sender.send(read.map(|_| chunk)).is_err() || last {
+					break;
+				}
+			});
+			receiver.into_iter().try_for_each(|chunk| chunk.map(|chunk| update(&chunk)))
+		})?;
+	}
+	Ok(FileHashes {
+		sha1: hex::encode(sha1_hasher.finalize()),
+		crc32: crc32_hasher.map(|hasher| format!("{:08X}", hasher.finalize())),
+	})
+}
+
+// The Exif data of a file, None if it has none or it cannot be parsed
+fn read_exif(src: &Path) -> io::Result<Option<exif::Exif>> {
+	let file = fs::File::open(src)?;
+	Ok(exif::Reader::new().read_from_container(&mut io::BufReader::new(&file)).ok())
+}
+
+// The content of a file, hashed and parsed by the --jobs threads. None where
+// not needed
+struct Content {
+	hashes: Option<io::Result<FileHashes>>,
+	exif: Option<io::Result<Option<exif::Exif>>>,
+}
+
+// exif::Exif is not Debug
+impl fmt::Debug for Content {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Content").field("hashes", &self.hashes).finish_non_exhaustive()
+	}
+}
+
+// The JPEG thumbnail in IFD1, at an offset from the start of the TIFF data
+fn read_thumbnail(src: &Path) -> Result<Vec<u8>, String> {
+	let file = fs::File::open(src).map_err(|e| format!("Could not open {:?}: {}", src, e))?;
+	let exif = exif::Reader::new()
+		.read_from_container(&mut io::BufReader::new(file))
+		.map_err(|e| format!("Could not read Exif data of {:?}: {}", src, e))?;
+	let field = |tag| exif.get_field(tag, In::THUMBNAIL).and_then(|f| f.value.get_uint(0)).map(|n| n as usize);
+	let (Some(offset), Some(len)) =
+		(field(exif::Tag::JPEGInterchangeFormat), field(exif::Tag::JPEGInterchangeFormatLength))
+	else {
+		return Err(format!("No embedded thumbnail in {:?}", src));
+	};
+	exif.buf()
+		.get(offset..offset.saturating_add(len))
+		.map(<[u8]>::to_vec)
+		.ok_or_else(|| format!("Embedded thumbnail of {:?} is truncated", src))
+}
+
+fn record_original_exif(dest: &Path, original: &str) -> Result<bool, String> {
+	let content = fs::read(dest).map_err(|e| format!("Could not read {:?}: {}", dest, e))?;
+	match metadata::record_document_name(&content, original)? {
+		Some(content) => write_in_place(dest, &content).map(|_| true),
+		None => Ok(false),
+	}
+}
+
+// Replace the content of a file through a temporary file, so that it is never
+// left half written, keeping its timestamps
+fn write_in_place(path: &Path, content: &[u8]) -> Result<(), String> {
+	let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+	let temp = path.with_file_name(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+	fs::write(&temp, content).and_then(|_| copy_file_times(path, &temp)).and_then(|_| fs::rename(&temp, path)).map_err(
+		|e| {
+			let _ = fs::remove_file(&temp);
+			format!("Could not write {:?}: {}", path, e)
+		},
+	)
+}
+
+fn copy_file_times<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> io::Result<()> {
+	let metadata = fs::metadata(src)?;
+	let times = fs::FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?);
+	fs::File::options().write(true).open(dest)?.set_times(times)
+}
+
+#[cfg(unix)]
+fn copy_xattrs<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> io::Result<()> {
+	for name in xattr::list(&src)? {
+		if let Some(value) = xattr::get(&src, &name)? {
+			xattr::set(&dest, &name, &value)?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs<P: AsRef<Path>, Q: AsRef<Path>>(_src: P, _dest: Q) -> io::Result<()> {
+	warn!("Extended attributes are not supported on this platform");
+	Ok(())
+}
+
+// Independent sequences, one for each distinct rendered name, e.g.
+// {{counter ExifModel}} counts files per camera
+struct CounterHelper {
+	counters: Mutex<HashMap<String, usize>>,
+	start: usize,
+	width: usize,
+}
+
+impl handlebars::HelperDef for CounterHelper {
+	fn call_inner<'reg: 'rc, 'rc>(
+		&self,
+		h: &handlebars::Helper<'rc>,
+		_: &'reg handlebars::Handlebars<'reg>,
+		ctx: &'rc handlebars::Context,
+		_: &mut handlebars::RenderContext<'reg, 'rc>,
+	) -> Result<handlebars::ScopedJson<'rc>, handlebars::RenderError> {
+		// a trial rendering must not consume any value
+		if ctx.data().get(PROBE_KEY).is_some() {
+			return Ok(handlebars::ScopedJson::Derived(Value::String(IDX_WILDCARD.to_string())));
+		}
+		let name = h.params().iter().map(|p| p.render()).collect::<Vec<_>>().join("/");
+		let mut counters = self.counters.lock().expect("counter lock poisoned");
+		let counter = counters.entry(name).or_insert(self.start);
+		let value = format!("{:01$}", counter, self.width);
+		*counter += 1;
+		Ok(handlebars::ScopedJson::Derived(Value::String(value)))
+	}
+}
+
+// Properties as typed values rather than formatted strings, e.g.
+// {{#if (gt Typed.ExifFNumber 4)}}. Timestamps are in ISO 8601 format
+const TYPED_KEY: &str = "Typed";
+const ISO_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+// Whole results render without decimals. Infinite or undefined results, e.g.
+// from a division by zero, are null
+fn number(value: f64) -> Value {
+	if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+		Value::from(value as i64)
+	} else {
+		Value::from(value)
+	}
+}
+
+// The leading 64 bits of a hex hash in base36, least significant digit first,
+// so that any length is as evenly spread
+fn short_id(hash: &str, length: u8) -> Option<String> {
+	const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+	let mut value = u64::from_str_radix(hash.get(..16)?, 16).ok()?;
+	let mut id = String::new();
+	for _ in 0..length {
+		id.push(DIGITS[(value % 36) as usize] as char);
+		value /= 36;
+	}
+	Some(id)
+}
+
+// The cell of the position in the geohash grid. Each character halves the
+// longitude and latitude ranges in turn, five times, so that positions in
+// the same cell share the same prefix
+fn geohash(latitude: f64, longitude: f64, length: u8) -> String {
+	const DIGITS: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+	let mut ranges = [(-180.0, 180.0), (-90.0, 90.0)];
+	let values = [longitude, latitude];
+	let mut hash = String::new();
+	let mut bit = 0;
+	while hash.len() < length as usize {
+		let mut digit = 0;
+		for _ in 0..5 {
+			let (min, max) = &mut ranges[bit % 2];
+			let mid = (*min + *max) / 2.0;
+			digit <<= 1;
+			if values[bit % 2] >= mid {
+				digit |= 1;
+				*min = mid;
+			} else {
+				*max = mid;
+			}
+			bit += 1;
+		}
+		hash.push(DIGITS[digit] as char);
+	}
+	hash
+}
+
+// The height of the sun above the horizon in degrees, from the NOAA
+// approximations of the equation of time and of the declination, good to a
+// fraction of a degree
+fn solar_elevation(utc: NaiveDateTime, latitude: f64, longitude: f64) -> f64 {
+	use std::f64::consts::PI;
+	let minutes = utc.num_seconds_from_midnight() as f64 / 60.0;
+	// fractional year, in radians
+	let g = 2.0 * PI / 365.0 * (utc.ordinal0() as f64 + (minutes / 60.0 - 12.0) / 24.0);
+	let equation_of_time = 229.18
+		* (0.000075 + 0.001868 * g.cos()
+			- 0.032077 * g.sin()
+			- 0.014615 * (2.0 * g).cos()
+			- 0.040849 * (2.0 * g).sin());
+	let declination = 0.006918 - 0.399912 * g.cos() + 0.070257 * g.sin() - 0.006758 * (2.0 * g).cos()
+		+ 0.000907 * (2.0 * g).sin()
+		- 0.002697 * (3.0 * g).cos()
+		+ 0.00148 * (3.0 * g).sin();
+	let solar_time = minutes + equation_of_time + 4.0 * longitude;
+	let hour_angle = (solar_time / 4.0 - 180.0).to_radians();
+	let latitude = latitude.to_radians();
+	let cos_zenith = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+	90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+// In binary units with one decimal, e.g. 24.3MiB, bytes below 1KiB
+fn human_size(bytes: u64) -> String {
+	const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+	let mut size = bytes as f64;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < UNITS.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	if unit == 0 {
+		format!("{}{}", bytes, UNITS[0])
+	} else {
+		format!("{:.1}{}", size, UNITS[unit])
+	}
+}
+
+// Formatted timestamps, as found in properties, are parsed back as a last
+// resort
+fn parse_timestamp(text: &str, timestamp_format: &str) -> Option<NaiveDateTime> {
+	[ISO_TIMESTAMP_FORMAT, "%Y:%m:%d %H:%M:%S", timestamp_format]
+		.iter()
+		.find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+// {{coalesce ExifDateTimeOriginal ExifDateTime SysDateTimeModified}} renders
+// the first of its arguments which is present and not empty, even in strict
+// mode
+struct CoalesceHelper;
+
+impl handlebars::HelperDef for CoalesceHelper {
+	fn call_inner<'reg: 'rc, 'rc>(
+		&self,
+		h: &handlebars::Helper<'rc>,
+		_: &'reg handlebars::Handlebars<'reg>,
+		_: &'rc handlebars::Context,
+		_: &mut handlebars::RenderContext<'reg, 'rc>,
+	) -> Result<handlebars::ScopedJson<'rc>, handlebars::RenderError> {
+		let first = h.params().iter().map(|p| p.value()).find(|value| match value {
+			Value::Null => false,
+			Value::String(text) => !text.is_empty(),
+			_ => true,
+		});
+		Ok(handlebars::ScopedJson::Derived(first.cloned().unwrap_or_else(|| Value::String(String::new()))))
+	}
+}
+
+// {{date ExifDateTimeOriginal "%Y/%m"}} formats a timestamp property with its
+// own format string, regardless of --timestamp-format
+struct DateHelper {
+	timestamp_format: String,
+}
+
+impl handlebars::HelperDef for DateHelper {
+	fn call_inner<'reg: 'rc, 'rc>(
+		&self,
+		h: &handlebars::Helper<'rc>,
+		_: &'reg handlebars::Handlebars<'reg>,
+		ctx: &'rc handlebars::Context,
+		_: &mut handlebars::RenderContext<'reg, 'rc>,
+	) -> Result<handlebars::ScopedJson<'rc>, handlebars::RenderError> {
+		let param = h.param(0).ok_or(handlebars::RenderErrorReason::ParamNotFoundForIndex("date", 0))?;
+		let format = h
+			.param(1)
+			.and_then(|p| p.value().as_str())
+			.ok_or(handlebars::RenderErrorReason::InvalidParamType("format string"))?;
+		let text = param.value().as_str().unwrap_or("");
+		if text.is_empty() {
+			return Ok(handlebars::ScopedJson::Derived(Value::String(String::new())));
+		}
+		let timestamp = param
+			.relative_path()
+			.and_then(|path| ctx.data().get(TYPED_KEY)?.get(path)?.as_str())
+			.and_then(|raw| NaiveDateTime::parse_from_str(raw, ISO_TIMESTAMP_FORMAT).ok())
+			.or_else(|| parse_timestamp(text, &self.timestamp_format))
+			.ok_or_else(|| handlebars::RenderErrorReason::Other(format!("date: '{}' is not a timestamp", text)))?;
+		let mut formatted = String::new();
+		write!(formatted, "{}", timestamp.format(format))
+			.map_err(|_| handlebars::RenderErrorReason::Other(format!("date: invalid format string '{}'", format)))?;
+		Ok(handlebars::ScopedJson::Derived(Value::String(formatted)))
+	}
+}
+
+#[derive(Clone, Copy)]
+enum PhotoFormat {
+	Shutter,
+	Aperture,
+	Focal,
+}
+
+impl PhotoFormat {
+	fn name(self) -> &'static str {
+		match self {
+			PhotoFormat::Shutter => "shutter",
+			PhotoFormat::Aperture => "aperture",
+			PhotoFormat::Focal => "focal",
+		}
+	}
+
+	fn format(self, value: f64) -> String {
+		// one decimal is enough for apertures and focal lengths, and drops the
+		// rounding errors of the fractions
+		let rounded = (value * 10.0).round() / 10.0;
+		match self {
+			PhotoFormat::Shutter if value > 0.0 && value < 1.0 => format!("1-{}s", (1.0 / value).round()),
+			PhotoFormat::Shutter => format!("{}s", rounded),
+			PhotoFormat::Aperture => format!("f{}", rounded),
+			PhotoFormat::Focal => format!("{}mm", rounded),
+		}
+	}
+}
+
+// Fractions are formatted as NUM_DEN, or NUM/DEN before sanitization
+fn parse_fraction(text: &str) -> Option<f64> {
+	match text.split_once(['_', '/']) {
+		Some((num, den)) => {
+			let den: f64 = den.parse().ok()?;
+			(den != 0.0).then_some(num.parse::<f64>().ok()? / den)
+		}
+		None => text.parse().ok(),
+	}
+}
+
+// Letters usually spelled out with an e, or doubled, rather than dropping the
+// diacritic
+const TRANSLIT_EXPANSIONS: [(char, &str); 9] = [
+	('ä', "ae"),
+	('ö', "oe"),
+	('ü', "ue"),
+	('Ä', "Ae"),
+	('Ö', "Oe"),
+	('Ü', "Ue"),
+	('ß', "ss"),
+	('æ', "ae"),
+	('ø', "oe"),
+];
+
+// Plain ASCII, e.g. Zürich becomes Zuerich and 東京 becomes Dong Jing
+fn transliterate(text: &str) -> String {
+	let expanded = text
+		.chars()
+		.map(|c| match TRANSLIT_EXPANSIONS.iter().find(|(from, _)| *from == c) {
+			Some((_, to)) => to.to_string(),
+			None => c.to_string(),
+		})
+		.collect::<String>();
+	deunicode::deunicode(&expanded)
+}
+
+// Lower case ASCII words separated by dashes, e.g. Zürich becomes zurich
+fn slugify(text: &str) -> String {
+	deunicode::deunicode(text)
+		.to_lowercase()
+		.split(|c: char| !c.is_ascii_alphanumeric())
+		.filter(|word| !word.is_empty())
+		.collect::<Vec<_>>()
+		.join("-")
+}
+
+// {{shutter ExifExposureTime}} renders 1-250s, {{aperture ExifFNumber}} f2.8
+// and {{focal ExifFocalLength}} 35mm, from either the typed or the formatted
+// property
+struct PhotoHelper(PhotoFormat);
+
+impl handlebars::HelperDef for PhotoHelper {
+	fn call_inner<'reg: 'rc, 'rc>(
+		&self,
+		h: &handlebars::Helper<'rc>,
+		_: &'reg handlebars::Handlebars<'reg>,
+		ctx: &'rc handlebars::Context,
+		_: &mut handlebars::RenderContext<'reg, 'rc>,
+	) -> Result<handlebars::ScopedJson<'rc>, handlebars::RenderError> {
+		let param = h.param(0).ok_or(handlebars::RenderErrorReason::ParamNotFoundForIndex(self.0.name(), 0))?;
+		let text = param.value().as_str().unwrap_or("");
+		let value = param
+			.value()
+			.as_f64()
+			.or_else(|| param.relative_path().and_then(|path| ctx.data().get(TYPED_KEY)?.get(path)?.as_f64()))
+			.or_else(|| parse_fraction(text));
+		let formatted = match value {
+			Some(value) => self.0.format(value),
+			None if text.is_empty() => String::new(),
+			None =>
+				return Err(handlebars::RenderErrorReason::Other(format!(
+					"{}: '{}' is not a number",
+					self.0.name(),
+					text
+				))
+				.into()),
+		};
+		Ok(handlebars::ScopedJson::Derived(Value::String(formatted)))
+	}
+}
+
+struct ExifAttrFormatter {
+	date_time_format: String,
+	sanitize_key_pattern: regex::Regex,
+	sanitize_value_pattern: regex::Regex,
+	sanitize_replacement: String,
+}
+
+impl ExifAttrFormatter {
+	fn new(
+		date_time_format: String,
+		sanitize_pattern: &str,
+		sanitize_replacement: String,
+	) -> Result<Self, regex::Error> {
+		Ok(ExifAttrFormatter {
+			date_time_format,
+			sanitize_key_pattern: regex::Regex::new("\\W+")?,
+			sanitize_value_pattern: regex::Regex::new(sanitize_pattern)?,
+			sanitize_replacement,
+		})
+	}
+
+	fn fmt<W>(&self, value: &PropertyValue, f: &mut W) -> fmt::Result
+	where W: Write {
+		match value {
+			// write!(f, "{}", strings)
+			PropertyValue::Text(ref text) => f.write_str(text),
+			PropertyValue::Path(ref path) => f.write_str(&portable::escape(path.as_os_str())),
+			PropertyValue::Timestamp(ref timestamp) =>
+				f.write_str(&timestamp.format(&self.date_time_format).to_string()),
+			PropertyValue::Integer(ref value) => write!(f, "{}", value),
+			PropertyValue::Fraction(ref num, ref den) => write!(f, "{}_{}", num, den),
 			PropertyValue::Real(ref value) => write!(f, "{}", value),
+			PropertyValue::List(ref values) => values.first().map_or(Ok(()), |value| self.fmt(value, f)),
 			PropertyValue::Nothing => Ok(()),
 		}
 	}
 
-	fn sanitize_value(&self, value: &String) -> String {
+	fn sanitize_value(&self, value: &str) -> String {
 		self.sanitize_value_pattern.replace_all(value, &self.sanitize_replacement).to_string()
 	}
 
-	pub fn sanitize_key(&self, key: &String) -> String { self.sanitize_key_pattern.replace_all(key, "").to_string() }
+	pub fn sanitize_key(&self, key: &str) -> String { self.sanitize_key_pattern.replace_all(key, "").to_string() }
 
 	pub fn as_string(&self, value: &PropertyValue) -> Result<String, fmt::Error> {
 		let mut value_as_string = String::new();
-		self.fmt(&value, &mut value_as_string)?;
+		self.fmt(value, &mut value_as_string)?;
 		match value {
 			PropertyValue::Path(_) => Ok(value_as_string),
 			_ => Ok(self.sanitize_value(&value_as_string)),
@@ -275,14 +1727,136 @@ impl ExifAttrFormatter {
 struct AppState {
 	warning_count: usize,
 	error_count: usize,
+	// content hash -> first destination materialized with that content, used by -m dedup
+	dedup_targets: HashMap<String, PathBuf>,
+	// content hash -> Exif properties already extracted from a file with that content
+	exif_cache: HashMap<String, Rc<Vec<(String, PropertyValue)>>>,
+	// normalized destination -> source planned to be written there
+	planned_dests: HashMap<String, PathBuf>,
+	// all the filesystem changes applied so far
+	journal: Journal,
+	// sources completed so far, to resume an interrupted run
+	checkpoint: Option<checkpoint::Checkpoint>,
+	// tags found by exiftool in the files of the current glob, by absolute path
+	exiftool_tags: exiftool::Tags,
+	// content of the file being planned, read ahead by the --jobs threads
+	read_ahead: Option<(PathBuf, Content)>,
+	// collected by -m stats
+	stats: stats::Stats,
+	// collected by -m dupes
+	dupes: dupes::Dupes,
+	// collected by -m similar
+	similar: similar::Similar,
+	// files compared by the diff subcommand, with the properties to compare
+	diffed: Vec<(PathBuf, Map<String, Value>)>,
+	// files for which each property referenced by the destination template was empty
+	empty_properties: BTreeMap<String, usize>,
+	// events of the files processed successfully, with --exec-batch-after
+	batch_events: Vec<Map<String, Value>>,
+	// destinations written and their content hashes, with --write-checksums
+	checksums: Option<checksums::Checksums>,
+	// destinations written and their manifest entries, with --dest-manifest
+	manifest_entries: Vec<(PathBuf, Value)>,
+	// sources which a dry run would have renamed out of the way
+	vacated: HashSet<PathBuf>,
+	// source folder of the last file indexed, with --idx-per-dir
+	idx_dir: Option<PathBuf>,
+	// source path without extension -> index, with --idx-per-stem
+	stem_idx: HashMap<PathBuf, usize>,
+	// destination folder -> next DestSeq value
+	dest_seq: HashMap<PathBuf, usize>,
+	// files of the current pattern extracted from archives -> path within the archive
+	archive_entries: HashMap<PathBuf, PathBuf>,
+	// where the files of the current pattern are extracted to
+	archive_scratch: Option<PathBuf>,
+	// targets of symbolic links replaced by --follow-symlinks -> link matched
+	followed_links: HashMap<PathBuf, PathBuf>,
+	// sources matched so far with --follow-symlinks, resolved
+	resolved_sources: HashSet<PathBuf>,
+	// WebDAV collections created or found to exist so far
+	remote_collections: HashSet<String>,
+	// unknown Exif tags reported so far by -m info
+	unknown_tags: HashSet<String>,
+	// files processed, by operation
+	applied: BTreeMap<Mode, usize>,
+	skipped: BTreeMap<SkipReason, usize>,
+	// outcome of the operation in progress, for the event log
+	last_error: Option<String>,
+	last_skipped: Option<SkipReason>,
+	event_log: Option<io::BufWriter<fs::File>>,
+	// events of the changes applied so far, logged again if rolled back, with
+	// --transactional
+	transaction_events: Option<Vec<Map<String, Value>>>,
+	access_denied_count: usize,
+	access_denied_examples: Vec<PathBuf>,
+	aborted: bool,
 }
 
+const MAX_ACCESS_DENIED_EXAMPLES: usize = 5;
+
+// Files planned and applied at a time as the walk proceeds, rounded up to the
+// end of a folder
+const STREAM_BATCH_SIZE: usize = 1000;
+
+// Written by --dest-manifest in each destination folder, one JSON object per
+// line
+const MANIFEST_NAME: &str = ".exif-namer.json";
+
+// Left behind by file managers, or by --dest-manifest, never matched unless
+// named by the pattern
+const JUNK_FILES: [&str; 6] = [".DS_Store", "._*", "Thumbs.db", "ehthumbs.db", "desktop.ini", MANIFEST_NAME];
+
 impl AppState {
 	fn report_error(&mut self) { self.error_count += 1; }
 	fn report_warning(&mut self) { self.warning_count += 1; }
+	fn report_error_message(&mut self, message: String) {
+		error!("{}", message);
+		self.last_error = Some(message);
+		self.report_error();
+	}
 	fn error_count(&self) -> usize { self.error_count }
 	fn warning_count(&self) -> usize { self.warning_count }
 	fn has_errors_or_warnings(&self) -> bool { self.error_count > 0 || self.warning_count > 0 }
+	fn report_applied(&mut self, mode: Mode) { *self.applied.entry(mode).or_default() += 1; }
+	// skipped files are also warnings
+	fn report_skipped(&mut self, reason: SkipReason) {
+		*self.skipped.entry(reason).or_default() += 1;
+		self.last_skipped = Some(reason);
+		self.report_warning();
+	}
+	fn summary(&self) -> String {
+		let applied = self.applied.iter().map(|(mode, count)| format!("{} {}", count, mode.past_tense()));
+		let skipped = self.skipped.iter().map(|(reason, count)| format!("{} {}", count, reason));
+		applied.chain(skipped).collect::<Vec<_>>().join(", ")
+	}
+	fn report_access_denied(&mut self, path: PathBuf) {
+		self.access_denied_count += 1;
+		if self.access_denied_examples.len() < MAX_ACCESS_DENIED_EXAMPLES {
+			self.access_denied_examples.push(path);
+		}
+	}
+	fn access_denied_count(&self) -> usize { self.access_denied_count }
+	fn access_denied_examples(&self) -> &[PathBuf] { &self.access_denied_examples }
+	fn abort(&mut self) { self.aborted = true; }
+	// Each event is a single line, so that a partial log of an interrupted run is
+	// still readable
+	fn log_event(&mut self, mut event: Map<String, Value>) {
+		let Some(ref mut event_log) = self.event_log else {
+			return;
+		};
+		event.insert("time".to_string(), Value::String(Local::now().to_rfc3339()));
+		if let (Some(events), Some("applied")) =
+			(self.transaction_events.as_mut(), event.get("result").and_then(Value::as_str))
+		{
+			events.push(event.clone());
+		}
+		if let Err(e) = writeln!(event_log, "{}", Value::Object(event)).and_then(|_| event_log.flush()) {
+			error!("Unable to write to the event log: {}", e);
+			self.event_log = None;
+			self.report_error();
+		}
+	}
+	fn is_aborted(&self) -> bool { self.aborted }
 }
 
 struct App<'a> {
@@ -291,11 +1865,58 @@ struct App<'a> {
 	cwd: PathBuf,
 	attr_formatter: ExifAttrFormatter,
 	handlebars: handlebars::Handlebars<'a>,
+	progress: Progress,
+	tz_boundaries: Option<tz::TzBoundaries>,
+	camera_offsets: HashMap<String, Duration>,
+	name_date_pattern: regex::Regex,
+	state_db: Option<state::StateDb>,
+	property_cache: Option<cache::PropertyCache>,
+	// options which affect the extracted properties, cached properties are only
+	// reused if they were extracted with the same options
+	cache_settings: String,
+	// loaded from --exiftool-json, by absolute path
+	exiftool_dump: exiftool::Tags,
+	// either forced by --case-insensitive, or detected on the destination
+	case_insensitive: bool,
+	// hook commands, split into arguments
+	exec_after: Option<Vec<String>>,
+	exec_batch_after: Option<Vec<String>>,
+	// properties listed by -m info, from --keys and --only
+	info_keys: Option<regex::Regex>,
+	info_only: Vec<Pattern>,
+	// properties referenced by the destination template
+	template_properties: BTreeSet<String>,
 }
 
-const EXIF_PREFIX: &'static str = "Exif";
-const EXIFTN_PREFIX: &'static str = "ExifTn";
-const SYS_PREFIX: &'static str = "Sys";
+const EXIF_PREFIX: &str = "Exif";
+const EXIFTN_PREFIX: &str = "ExifTn";
+const SYS_PREFIX: &str = "Sys";
+const GPS_PREFIX: &str = "Gps";
+// Exif tags qualified by IFD, other than Exif and Gps
+const IFD0_PREFIX: &str = "Ifd0";
+const IFD1_PREFIX: &str = "Ifd1";
+const INTEROP_PREFIX: &str = "Interop";
+// unknown Exif tags are named after their number
+const UNKNOWN_TAG_MARKER: &str = "Tag0x";
+// from XMP sidecars
+const XMP_PREFIX: &str = "Xmp";
+// derived from other properties
+const CALC_PREFIX: &str = "Calc";
+// from exiftool
+const EXIFTOOL_PREFIX: &str = "Exiftool";
+// stripped from property names matched by --only, ExifTn before Exif
+const SOURCE_PREFIXES: [&str; 10] = [
+	EXIFTN_PREFIX,
+	EXIF_PREFIX,
+	SYS_PREFIX,
+	GPS_PREFIX,
+	IFD0_PREFIX,
+	IFD1_PREFIX,
+	INTEROP_PREFIX,
+	XMP_PREFIX,
+	CALC_PREFIX,
+	EXIFTOOL_PREFIX,
+];
 
 macro_rules! prepend {
 	($prefix:tt, $name:expr) => {
@@ -303,10 +1924,56 @@ macro_rules! prepend {
 	};
 }
 
-const DESTINATION_TEMPLATE_ID: &'static str = "destination";
+const DESTINATION_TEMPLATE_ID: &str = "destination";
+// followed by the position of the template in --also
+const ALSO_TEMPLATE_ID: &str = "also";
+
+// set for the --exec-batch-after command
+const JOURNAL_ENV: &str = "EXIF_NAMER_JOURNAL";
+
+// A date, optionally followed by a time, as found in the names given by phones
+// and apps, e.g. IMG-20230114-WA0002, Screenshot_2023-01-14-09-12-33 or
+// PXL_20230114_091233123
+const NAME_DATE_PATTERN: &str = "(?:^|[^0-9])((?:19|20)[0-9]{2})[-_.]?([01][0-9])[-_.]?([0-3][0-9])\
+	(?:[-_ T.]?([0-2][0-9])[-_.:]?([0-5][0-9])[-_.:]?([0-5][0-9]))?";
+// DestSeq and SysDirIdx are the same sequence under two names
+const DEST_SEQ_KEYS: [&str; 2] = ["DestSeq", prepend!(SYS_PREFIX, "DirIdx")];
+const DEST_SEQ_PLACEHOLDER: &str = "\u{1}DestSeq\u{1}";
+// With --skip-organized, the destination is first rendered with a wildcard in
+// place of each index, to be matched against the source path
+const PROBE_KEY: &str = "\u{1}Probe";
+const IDX_WILDCARD: &str = "\u{1}Idx\u{1}";
+
+// The absolute path of a source, with its size and modification time
+type CacheKey = (String, (i64, String));
+
+// The files matching a pattern, found as the walk proceeds, or extracted from
+// archives all at once
+struct Matches {
+	pattern: String,
+	walk: Option<std::iter::Peekable<walk::Walk>>,
+	extracted: Option<Vec<PathBuf>>,
+	// absolute paths of the files found so far, when moves are streamed
+	found: HashSet<PathBuf>,
+}
+
+// A single source file, with the properties extracted from it and its rendered
+// destination
+struct PlannedOp {
+	src: PathBuf,
+	dest: PathBuf,
+	data: Map<String, Value>,
+	timestamps: HashMap<String, NaiveDateTime>,
+	// additional destinations from --also, created from the destination
+	also: Vec<(Mode, PathBuf)>,
+	// size and modification time of the source when planned
+	planned_state: Option<(i64, String)>,
+	// carried along with a main file
+	sidecar: bool,
+}
 
 impl<'a> App<'a> {
-	fn new(args: Args) -> Result<Self, regex::Error> {
+	fn new(mut args: Args) -> Result<Self, regex::Error> {
 		let attr_formatter =
 			ExifAttrFormatter::new(args.timestamp_format.clone(), &args.invalid_characters, args.replacement.clone())?;
 		let mut handlebars = handlebars::Handlebars::new();
@@ -321,37 +1988,456 @@ impl<'a> App<'a> {
 				let end = num::clamp(from + len, start, l);
 				v[start..end].to_owned()
 			});
-			handlebars.register_helper("substr", Box::new(substr))
+			handlebars.register_helper("substr", Box::new(substr));
+			handlebars_helper!(translit: |v: str| transliterate(v));
+			handlebars_helper!(slug: |v: str| slugify(v));
+			handlebars.register_helper("translit", Box::new(translit));
+			handlebars.register_helper("slug", Box::new(slug));
+			// arithmetic on typed properties, e.g. {{mul Typed.ExifFocalLength 1.5}}
+			handlebars_helper!(add: |a: f64, b: f64| number(a + b));
+			handlebars_helper!(sub: |a: f64, b: f64| number(a - b));
+			handlebars_helper!(mul: |a: f64, b: f64| number(a * b));
+			handlebars_helper!(div: |a: f64, b: f64| number(a / b));
+			handlebars.register_helper("add", Box::new(add));
+			handlebars.register_helper("sub", Box::new(sub));
+			handlebars.register_helper("mul", Box::new(mul));
+			handlebars.register_helper("div", Box::new(div));
+			// the built-in comparisons only accept integers
+			handlebars_helper!(gt: |a: f64, b: f64| a > b);
+			handlebars_helper!(gte: |a: f64, b: f64| a >= b);
+			handlebars_helper!(lt: |a: f64, b: f64| a < b);
+			handlebars_helper!(lte: |a: f64, b: f64| a <= b);
+			handlebars.register_helper("gt", Box::new(gt));
+			handlebars.register_helper("gte", Box::new(gte));
+			handlebars.register_helper("lt", Box::new(lt));
+			handlebars.register_helper("lte", Box::new(lte));
+			// sizes in bytes, as found in SysSize or Typed.SysSize
+			handlebars_helper!(filesize: |v: Json| {
+				v.as_u64().or_else(|| v.as_str().and_then(|v| v.parse().ok())).map(human_size).unwrap_or_default()
+			});
+			handlebars.register_helper("filesize", Box::new(filesize));
+		}
+
+		handlebars.register_helper("coalesce", Box::new(CoalesceHelper));
+		for format in [PhotoFormat::Shutter, PhotoFormat::Aperture, PhotoFormat::Focal] {
+			handlebars.register_helper(format.name(), Box::new(PhotoHelper(format)));
 		}
+		handlebars.register_helper("date", Box::new(DateHelper { timestamp_format: args.timestamp_format.clone() }));
+		handlebars.register_helper(
+			"counter",
+			Box::new(CounterHelper {
+				counters: Mutex::new(HashMap::new()),
+				start: args.idx_start,
+				width: args.idx_width,
+			}),
+		);
 
 		string_helpers::register(&mut handlebars);
 		regex_helpers::register(&mut handlebars);
 		path_helpers::register(&mut handlebars);
 		regex_helpers::register(&mut handlebars);
 		env_helpers::register(&mut handlebars);
-		handlebars
-			.register_template_string(DESTINATION_TEMPLATE_ID, &args.destination)
-			.map_err(|e| regex::Error::Syntax(format!("Handlebar syntax error in {}: {}", args.destination, e)))?;
+		match args.destination_file {
+			// templates registered from a file are reloaded on each render in dev mode
+			Some(ref path) => handlebars
+				.register_template_file(DESTINATION_TEMPLATE_ID, path)
+				.map_err(|e| regex::Error::Syntax(format!("Handlebar syntax error in {:?}: {}", path, e)))?,
+			None => handlebars
+				.register_template_string(DESTINATION_TEMPLATE_ID, &args.destination)
+				.map_err(|e| regex::Error::Syntax(format!("Handlebar syntax error in {}: {}", args.destination, e)))?,
+		}
+		for (i, (_, template)) in args.also.iter().enumerate() {
+			handlebars
+				.register_template_string(&format!("{}{}", ALSO_TEMPLATE_ID, i), template)
+				.map_err(|e| regex::Error::Syntax(format!("Handlebar syntax error in {}: {}", template, e)))?;
+		}
+		let template_properties =
+			handlebars.get_template(DESTINATION_TEMPLATE_ID).map(lint::referenced).unwrap_or_default();
+		if !args.strip.is_empty() && !matches!(args.mode, Mode::Copy | Mode::Clone) {
+			return Err(regex::Error::Syntax("--strip only applies to -m cp and clone".to_string()));
+		}
+		if args.record_original.is_some() && !matches!(args.mode, Mode::Move | Mode::Copy | Mode::Clone) {
+			return Err(regex::Error::Syntax("--record-original only applies to -m mv, cp and clone".to_string()));
+		}
+		// moved files are the originals, which are not rewritten, so that rollback
+		// and undo leave them as they were
+		if args.record_original == Some(RecordOriginal::Exif) && args.mode == Mode::Move {
+			warn!("--record-original exif only applies to -m cp and clone, recording in the XMP sidecar instead");
+			args.record_original = Some(RecordOriginal::Xmp);
+		}
 		let now = Local::now();
 		let cwd = std::env::current_dir().expect("Unable to determine current directory");
-		Ok(App { args, now, cwd, attr_formatter, handlebars })
+		let progress = Progress::new(args.progress, !args.no_sha1 || matches!(args.mode, Mode::Dedup | Mode::Dupes));
+		let tz_boundaries = match args.tz_boundaries {
+			Some(ref path) => Some(tz::TzBoundaries::load(path).map_err(regex::Error::Syntax)?),
+			None => None,
+		};
+		let camera_offsets = match args.camera_offsets {
+			Some(ref path) => load_camera_offsets(path).map_err(regex::Error::Syntax)?,
+			None => HashMap::new(),
+		};
+		let name_date_pattern = regex::Regex::new(NAME_DATE_PATTERN)?;
+		let state_db = match args.state {
+			Some(ref path) => Some(state::StateDb::open(path).map_err(regex::Error::Syntax)?),
+			None => None,
+		};
+		let property_cache = if args.no_cache {
+			None
+		} else {
+			cache::default_path().and_then(|path| match cache::PropertyCache::open(path) {
+				Ok(cache) => Some(cache),
+				Err(e) => {
+					warn!("{}, continuing without", e);
+					None
+				}
+			})
+		};
+		let cache_settings = format!(
+			"{} {:?} {:?} {:?} {:?} {} {} {} {:?}",
+			env!("CARGO_PKG_VERSION"),
+			args.time_shift,
+			camera_offsets.iter().collect::<BTreeMap<_, _>>(),
+			args.tz_boundaries,
+			args.render_timezone,
+			args.gps_precision,
+			args.geohash_precision,
+			args.timestamp_format,
+			args.exif_encoding.map(encoding_rs::Encoding::name),
+		);
+		let split_command = |command: &Option<String>| match command {
+			Some(command) => shell_words::split(command)
+				.map(Some)
+				.map_err(|e| regex::Error::Syntax(format!("Invalid command {}: {}", command, e))),
+			None => Ok(None),
+		};
+		let exiftool_dump = match args.exiftool_json {
+			Some(ref path) => exiftool::load(path)
+				.map_err(regex::Error::Syntax)?
+				.into_iter()
+				.map(|(path, tags)| (std::path::absolute(&path).unwrap_or_else(|_| cwd.join(&path)), tags))
+				.collect(),
+			None => exiftool::Tags::new(),
+		};
+		let case_insensitive = args.case_insensitive || Self::detect_case_insensitive(&args, &cwd);
+		let exec_after = split_command(&args.exec_after)?;
+		let exec_batch_after = split_command(&args.exec_batch_after)?;
+		let info_keys = args.keys.as_deref().map(regex::Regex::new).transpose()?;
+		let info_only = args
+			.only
+			.iter()
+			.map(|pattern| {
+				Pattern::new(pattern).map_err(|e| regex::Error::Syntax(format!("Invalid pattern {}: {}", pattern, e)))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(App {
+			args,
+			now,
+			cwd,
+			attr_formatter,
+			handlebars,
+			progress,
+			tz_boundaries,
+			camera_offsets,
+			name_date_pattern,
+			state_db,
+			property_cache,
+			cache_settings,
+			exiftool_dump,
+			case_insensitive,
+			exec_after,
+			exec_batch_after,
+			info_keys,
+			info_only,
+			template_properties,
+		})
 	}
 
-	fn find_matches(&self, pattern: &str, reporter: &mut AppState) -> Result<Vec<PathBuf>, PatternError> {
-		let mut out = Vec::new();
-		for iter in glob::glob(pattern)? {
+	// Whether -m info lists the property, according to --keys, --only and --missing
+	fn lists_property(&self, key: &str, value: &Value) -> bool {
+		if self.args.missing && !(value.is_null() || value.as_str() == Some("")) {
+			return false;
+		}
+		if self.info_keys.as_ref().is_some_and(|keys| !keys.is_match(key)) {
+			return false;
+		}
+		self.info_only.is_empty()
+			|| self.info_only.iter().any(|pattern| {
+				pattern.matches(key)
+					|| SOURCE_PREFIXES
+						.iter()
+						.filter_map(|prefix| key.strip_prefix(prefix))
+						.any(|name| pattern.matches(name))
+			})
+	}
+
+	// Looked up on the nearest existing folder of the fixed part of the
+	// destination, e.g. /media/photos for
+	// /media/photos/{{ExifModel}}/{{SysFullName}}. Assumed from the system if it
+	// cannot be told
+	fn detect_case_insensitive(args: &Args, cwd: &Path) -> bool {
+		let fixed = match args.output_dir {
+			Some(ref output_dir) => output_dir.clone(),
+			None if args.destination_file.is_some() => PathBuf::new(),
+			None => {
+				let prefix = args.destination.split("{{").next().unwrap_or_default();
+				if prefix.ends_with(std::path::is_separator) {
+					PathBuf::from(prefix)
+				} else {
+					Path::new(prefix).parent().map(Path::to_path_buf).unwrap_or_default()
+				}
+			}
+		};
+		let fixed = cwd.join(fixed);
+		let detected = fixed.ancestors().find(|dir| dir.is_dir()).and_then(portable::is_case_insensitive);
+		if detected == Some(true) {
+			info!("Destinations differing only by case are the same file in {:?}", fixed);
+		}
+		detected.unwrap_or(cfg!(any(windows, target_os = "macos")))
+	}
+
+	fn match_options(&self) -> MatchOptions {
+		MatchOptions {
+			case_sensitive: !self.args.iglob,
+			require_literal_separator: false,
+			require_literal_leading_dot: !self.args.hidden,
+		}
+	}
+
+	fn find_matches(&self, pattern: &str, reporter: &mut AppState) -> Result<Matches, PatternError> {
+		if let Some((archives, inner)) = archive::split(pattern) {
+			let archives = glob::glob_with(archives, self.match_options())?
+				.filter_map(Result::ok)
+				.filter(|path| path.is_file())
+				.collect::<Vec<_>>();
+			if !archives.is_empty() {
+				let out = self.extract_archives(reporter, &archives, &Pattern::new(inner)?);
+				return Ok(Matches {
+					pattern: pattern.to_string(),
+					walk: None,
+					extracted: Some(Self::skip_junk(pattern, out)),
+					found: HashSet::new(),
+				});
+			}
+		}
+		Ok(Matches {
+			pattern: pattern.to_string(),
+			walk: Some(walk::walk(pattern, self.match_options())?.peekable()),
+			extracted: None,
+			found: HashSet::new(),
+		})
+	}
+
+	// The whole pattern is planned at once to sort the files, or to review the
+	// plan. Moves which may swap places across batches are deferred instead
+	fn batch_size(&self) -> Option<usize> {
+		(self.args.sort_by.is_none() && !self.args.review).then_some(STREAM_BATCH_SIZE)
+	}
+
+	// A streamed move whose destination is an existing file not found yet, which
+	// may be a source of a later batch, or the source of a deferred move, waits
+	// for the end of the walk. Deferred moves are then applied together, as they
+	// would have been planned as a whole. Sidecars, planned before their main
+	// file, are deferred with it
+	fn defer_moves(&self, matches: &Matches, plan: Vec<PlannedOp>, deferred: &mut Vec<PlannedOp>) -> Vec<PlannedOp> {
+		let mut ready = Vec::with_capacity(plan.len());
+		let mut group = Vec::new();
+		for op in plan {
+			let sidecar = op.sidecar;
+			group.push(op);
+			if sidecar {
+				continue;
+			}
+			let blocked = group.iter().any(|op| {
+				let src = self.absolute(&op.src);
+				std::iter::once(&op.dest).chain(op.also.iter().map(|(_, dest)| dest)).any(|dest| {
+					let dest = self.absolute(dest);
+					dest != src
+						&& ((dest.is_file() && !matches.found.contains(&dest))
+							|| deferred.iter().any(|other| self.absolute(&other.src) == dest))
+				})
+			});
+			if blocked {
+				debug!("Deferring the move of {:?} to the end of {}", group.last().map(|op| &op.src), matches.pattern);
+				deferred.append(&mut group);
+			} else {
+				ready.append(&mut group);
+			}
+		}
+		ready.append(&mut group);
+		ready
+	}
+
+	// The next batch of matches, None once the walk is complete. A batch ends with
+	// a folder, so that a file and its sidecars are planned together
+	fn next_matches(&self, reporter: &mut AppState, matches: &mut Matches) -> Option<Vec<PathBuf>> {
+		if let Some(extracted) = matches.extracted.take() {
+			return Some(self.sort_matches(extracted));
+		}
+		let walk = matches.walk.as_mut()?;
+		let batch_size = self.batch_size();
+		let mut out: Vec<PathBuf> = Vec::new();
+		let mut complete = false;
+		while !reporter.is_aborted() {
+			if let (Some(batch_size), Some(Ok(next))) = (batch_size, walk.peek()) {
+				if out.len() >= batch_size && next.parent() != out.last().and_then(|last| last.parent()) {
+					break;
+				}
+			}
+			let Some(iter) = walk.next() else {
+				complete = true;
+				break;
+			};
 			match iter {
 				Ok(path) =>
 					if path.is_file() {
-						out.push(path)
+						// copies and moves into folders not walked yet are not matched again
+						if reporter.journal.wrote(&self.absolute(&path)) {
+							debug!("{:?} was written by this run, skipping", path);
+							continue;
+						}
+						// unreadable files are singled out here, rather than failing later
+						match fs::File::open(&path) {
+							Err(e) if e.kind() == io::ErrorKind::PermissionDenied => self.access_denied(reporter, path),
+							_ => out.push(path),
+						}
 					},
+				Err(e) if e.error.kind() == io::ErrorKind::PermissionDenied => self.access_denied(reporter, e.path),
 				Err(e) => {
-					error!("Invalid glob pattern {}: {}", pattern, e);
+					error!("Unable to read {:?} matching {}: {}", e.path, matches.pattern, e.error);
 					reporter.report_error();
 				}
 			}
 		}
-		return Ok(out);
+		if complete || reporter.is_aborted() {
+			matches.walk = None;
+		}
+		if !self.args.hidden {
+			out.retain(|path| !Self::is_hidden(&matches.pattern, path));
+		}
+		if self.args.follow_symlinks {
+			out = self.follow_symlinks(reporter, out);
+		}
+		Some(self.sort_matches(Self::skip_junk(&matches.pattern, out)))
+	}
+
+	// The order of the matches depends on how the filesystem lists them, and so
+	// would SysIdx. glob walks folders by name and batches end with a folder, so
+	// sorting each batch gives the same order on every run
+	fn sort_matches(&self, mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+		if !self.args.unsorted {
+			paths.sort_by(|a, b| a.as_os_str().as_encoded_bytes().cmp(b.as_os_str().as_encoded_bytes()));
+		}
+		paths
+	}
+
+	// glob descends into hidden folders with **, which only the folders spelled
+	// out by the pattern should
+	fn is_hidden(pattern: &str, path: &Path) -> bool {
+		let spelled_out = Path::new(pattern)
+			.components()
+			.filter_map(|component| component.as_os_str().to_str())
+			.filter(|name| name.starts_with('.'))
+			.filter_map(|name| Pattern::new(name).ok())
+			.collect::<Vec<_>>();
+		path.components().any(|component| match component {
+			std::path::Component::Normal(name) => {
+				let name = name.to_string_lossy();
+				name.starts_with('.') && !spelled_out.iter().any(|pattern| pattern.matches(&name))
+			}
+			_ => false,
+		})
+	}
+
+	// Junk files are kept only if the pattern names them, without wildcards
+	fn skip_junk(pattern: &str, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+		let last = Path::new(pattern).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+		if Pattern::escape(&last) == last {
+			return paths;
+		}
+		let junk = JUNK_FILES.iter().filter_map(|junk| Pattern::new(junk).ok()).collect::<Vec<_>>();
+		paths
+			.into_iter()
+			.filter(|path| {
+				let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+				let is_junk = junk.iter().any(|junk| junk.matches(&name));
+				if is_junk {
+					debug!("Skipping {:?}", path);
+				}
+				!is_junk
+			})
+			.collect()
+	}
+
+	fn follow_symlinks(&self, app_state: &mut AppState, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+		let mut out = Vec::new();
+		for path in paths {
+			let is_link = path.is_symlink();
+			let resolved = match fs::canonicalize(&path) {
+				Ok(resolved) => resolved,
+				Err(e) => {
+					app_state.report_error_message(format!("Unable to resolve {:?}: {}", path, e));
+					continue;
+				}
+			};
+			if !app_state.resolved_sources.insert(resolved.clone()) {
+				debug!("{:?} leads to a file already matched, skipping", path);
+				continue;
+			}
+			if is_link {
+				app_state.followed_links.insert(resolved.clone(), path);
+				out.push(resolved);
+			} else {
+				out.push(path);
+			}
+		}
+		out
+	}
+
+	// Files within archives are extracted to a scratch folder, one pattern at a
+	// time, and copied out from there. Each is removed once applied
+	fn extract_archives(&self, app_state: &mut AppState, archives: &[PathBuf], pattern: &Pattern) -> Vec<PathBuf> {
+		if !matches!(self.args.mode, Mode::Copy | Mode::Clone | Mode::Dedup | Mode::Thumb | Mode::Info | Mode::Stats) {
+			app_state.report_error_message(format!("Files within archives cannot be used with -m {}", self.args.mode));
+			return Vec::new();
+		}
+		let scratch = std::env::temp_dir().join(format!("exif-namer-{}", uuid::Uuid::new_v4()));
+		app_state.archive_scratch = Some(scratch.clone());
+		let mut out = Vec::new();
+		for (i, archive) in archives.iter().enumerate() {
+			let dest = scratch.join(i.to_string());
+			match archive::extract(archive, pattern, self.match_options(), &dest) {
+				Ok(entries) =>
+					for entry in entries {
+						app_state.archive_entries.insert(dest.join(&entry), archive.join(&entry));
+						out.push(dest.join(entry));
+					},
+				Err(e) => app_state.report_error_message(format!("Unable to read archive {:?}: {}", archive, e)),
+			}
+		}
+		out
+	}
+
+	fn remove_scratch(&self, app_state: &mut AppState) {
+		app_state.archive_entries.clear();
+		if let Some(scratch) = app_state.archive_scratch.take() {
+			if let Err(e) = fs::remove_dir_all(&scratch) {
+				warn!("Unable to remove extracted files in {:?}: {}", scratch, e);
+				app_state.report_warning();
+			}
+		}
+	}
+
+	fn access_denied(&self, app_state: &mut AppState, path: PathBuf) {
+		if self.args.fail_on_unreadable {
+			error!("Access denied to {:?}, aborting", path);
+			app_state.abort();
+		} else {
+			debug!("Access denied to {:?}, skipping", path);
+		}
+		if !self.args.skip_unreadable {
+			app_state.report_error();
+		}
+		app_state.report_access_denied(path);
 	}
 
 	fn extract_properties<F>(&self, app_state: &mut AppState, src: &PathBuf, mut add_property: F)
@@ -361,7 +2447,7 @@ impl<'a> App<'a> {
 		add_property(
 			// extension without the leading dot
 			app_state,
-			&prepend!(SYS_PREFIX, "DateTimeNow"),
+			prepend!(SYS_PREFIX, "DateTimeNow"),
 			&PropertyValue::Timestamp(self.now.naive_local()),
 		);
 		add_property(
@@ -370,20 +2456,21 @@ impl<'a> App<'a> {
 			prepend!(SYS_PREFIX, "Cwd"),
 			&PropertyValue::from_opt_path(Some(&self.cwd)),
 		);
-		// Path properties
+		// Path properties, of the path within the archive for extracted files
+		let path = app_state.archive_entries.get(src).cloned().unwrap_or_else(|| src.clone());
 		add_property(
 			// extension without the leading dot
 			app_state,
 			prepend!(SYS_PREFIX, "Ext"),
-			&PropertyValue::from_opt_path(src.extension()),
+			&PropertyValue::from_opt_path(path.extension().map(|ext| self.args.ext_case.apply(ext))),
 		);
 		add_property(
 			// extension with the leading dot
 			app_state,
 			prepend!(SYS_PREFIX, "DotExt"),
-			&PropertyValue::from_opt_path(src.extension().map(|ext| {
+			&PropertyValue::from_opt_path(path.extension().map(|ext| {
 				let mut d = OsStr::new(".").to_os_string();
-				d.push(ext);
+				d.push(self.args.ext_case.apply(ext));
 				d
 			})),
 		);
@@ -391,18 +2478,22 @@ impl<'a> App<'a> {
 			app_state,
 			// name without extension
 			prepend!(SYS_PREFIX, "Name"),
-			&PropertyValue::from_opt_path(src.file_stem()),
+			&PropertyValue::from_opt_path(path.file_stem()),
 		);
 		add_property(
 			// name with extension
 			app_state,
 			prepend!(SYS_PREFIX, "FullName"),
-			&PropertyValue::from_opt_path(src.file_name()),
+			&PropertyValue::from_opt_path(path.file_name()),
 		);
-		let parent = src.parent();
+		let name_timestamp = path.file_stem().and_then(|name| self.name_date_time(&name.to_string_lossy()));
+		if let Some(timestamp) = name_timestamp {
+			add_property(app_state, prepend!(SYS_PREFIX, "NameDateTime"), &PropertyValue::Timestamp(timestamp));
+		}
+		let parent = path.parent();
 		add_property(app_state, prepend!(SYS_PREFIX, "Path"), &PropertyValue::from_opt_path(parent));
 		let mut path_head = PathBuf::new();
-		let components = src.components().collect::<Vec<_>>();
+		let components = path.components().collect::<Vec<_>>();
 		let n_components = components.len();
 		for (i, component) in components.iter().enumerate() {
 			add_property(
@@ -422,216 +2513,2426 @@ impl<'a> App<'a> {
 				&PropertyValue::from_opt_path(Some(path_head.as_path())),
 			);
 		}
-		if let Some(up) = parent {
-			let mut path_tail = up.components();
-			for i in 0..(n_components - 1) {
-				add_property(
-					app_state,
-					&format!("{}{}", prepend!(SYS_PREFIX, "PathTail"), i),
-					&PropertyValue::from_opt_path(Some(&path_tail)),
-				);
-				path_tail.next();
+		if let Some(up) = parent {
+			let mut path_tail = up.components();
+			for i in 0..(n_components - 1) {
+				add_property(
+					app_state,
+					&format!("{}{}", prepend!(SYS_PREFIX, "PathTail"), i),
+					&PropertyValue::from_opt_path(Some(&path_tail)),
+				);
+				path_tail.next();
+			}
+		}
+
+		// Symbolic links, whether followed or not
+		let link_target = fs::read_link(app_state.followed_links.get(src).unwrap_or(src)).ok();
+		add_property(
+			app_state,
+			prepend!(SYS_PREFIX, "IsSymlink"),
+			&PropertyValue::from_opt_str(link_target.as_ref().map(|_| "true")),
+		);
+		add_property(app_state, prepend!(SYS_PREFIX, "LinkTarget"), &PropertyValue::from_opt_path(link_target));
+
+		// Filesystem metadata properties
+		let mut modified_timestamp = None;
+		match fs::metadata(src) {
+			Ok(metadata) => {
+				let modified = PropertyValue::from_opt_filetime(metadata.modified().ok());
+				if let PropertyValue::Timestamp(timestamp) = modified {
+					modified_timestamp = Some(timestamp);
+				}
+				add_property(app_state, prepend!(SYS_PREFIX, "DateTimeModified"), &modified);
+				add_property(
+					app_state,
+					prepend!(SYS_PREFIX, "DateTimeCreated"),
+					&PropertyValue::from_opt_filetime(metadata.created().or_else(|_| metadata.modified()).ok()),
+				);
+				add_property(
+					app_state,
+					prepend!(SYS_PREFIX, "DateTimeAccessed"),
+					&PropertyValue::from_opt_filetime(metadata.accessed().ok()),
+				);
+				add_property(app_state, prepend!(SYS_PREFIX, "Size"), &PropertyValue::Integer(metadata.len() as i64));
+				add_property(
+					app_state,
+					prepend!(SYS_PREFIX, "SizeHuman"),
+					&PropertyValue::Text(human_size(metadata.len())),
+				);
+				// Read from the image header, even when Exif metadata is missing
+				if let Ok(size) = imagesize::size(src) {
+					add_property(
+						app_state,
+						prepend!(SYS_PREFIX, "ImageWidth"),
+						&PropertyValue::Integer(size.width as i64),
+					);
+					add_property(
+						app_state,
+						prepend!(SYS_PREFIX, "ImageHeight"),
+						&PropertyValue::Integer(size.height as i64),
+					);
+				}
+			}
+			Err(e) => {
+				error!("Unable to read fs metadata for {:?}: {}", src, e);
+				app_state.report_error();
+			}
+		}
+
+		// File content, hashed and parsed again only if it changed since the last run
+		let (cache_key, mut cached) = self.cached_properties(src);
+		let mut cache_changed = false;
+		let (ahead_hashes, ahead_exif) = match app_state.read_ahead.take() {
+			Some((path, content)) if path == *src => (content.hashes, content.exif),
+			_ => (None, None),
+		};
+
+		let content_hash = if self.needs_sha1() {
+			// File content - Sha1 and Crc32 properties
+			let hashes = match self.cached_hashes(&cached) {
+				Some(hashes) => Ok(hashes),
+				None => ahead_hashes.unwrap_or_else(|| file_hashes(src, self.args.crc32)),
+			};
+			match hashes {
+				Ok(hashes) => {
+					add_property(app_state, prepend!(SYS_PREFIX, "Sha1"), &PropertyValue::Text(hashes.sha1.clone()));
+					add_property(
+						app_state,
+						prepend!(SYS_PREFIX, "ShortId"),
+						&PropertyValue::from_opt_str(short_id(&hashes.sha1, self.args.short_id_length).as_deref()),
+					);
+					if self.args.crc32 {
+						add_property(
+							app_state,
+							prepend!(SYS_PREFIX, "Crc32"),
+							&PropertyValue::from_opt_str(hashes.crc32.as_deref()),
+						);
+					}
+					if self.cached_hashes(&cached).is_none() {
+						cached.sha1 = Some(hashes.sha1.clone());
+						cached.crc32 = hashes.crc32;
+						cache_changed = true;
+					}
+					Some(hashes.sha1)
+				}
+				Err(e) => {
+					error!("Unable to compute hash for {:?}: {}", src, e);
+					app_state.report_error();
+					None
+				}
+			}
+		} else {
+			None
+		};
+
+		add_property(
+			app_state,
+			prepend!(SYS_PREFIX, "Uuid"),
+			&PropertyValue::from_opt_str(Some(&uuid::Uuid::new_v4().to_string())),
+		);
+
+		// Editor metadata - XMP sidecar properties
+		if let Some(sidecar) = xmp::find_sidecar(src) {
+			match xmp::load(&sidecar) {
+				Ok(xmp) => {
+					if let Some(rating) = xmp.rating {
+						add_property(app_state, prepend!(XMP_PREFIX, "Rating"), &PropertyValue::Integer(rating));
+					}
+					if let Some(ref label) = xmp.label {
+						add_property(app_state, prepend!(XMP_PREFIX, "Label"), &PropertyValue::Text(label.clone()));
+					}
+					if !xmp.keywords.is_empty() {
+						add_property(
+							app_state,
+							prepend!(XMP_PREFIX, "Keywords"),
+							&PropertyValue::Text(xmp.keywords.join(",")),
+						);
+					}
+					if !xmp.hierarchical_keywords.is_empty() {
+						add_property(
+							app_state,
+							prepend!(XMP_PREFIX, "HierarchicalKeywords"),
+							&PropertyValue::Text(xmp.hierarchical_keywords.join(",")),
+						);
+					}
+				}
+				Err(e) => {
+					warn!("{}", e);
+					app_state.report_warning();
+				}
+			}
+		}
+
+		let mut exif_timestamp = None;
+		let mut southern = false;
+		if !self.args.no_exif {
+			// File content - Exif properties, parsed only once for each distinct content
+			let reused = content_hash
+				.as_ref()
+				.and_then(|hash| app_state.exif_cache.get(hash))
+				.cloned()
+				.or_else(|| cached.exif.as_deref().and_then(Self::from_cached_exif).map(Rc::new));
+			let exif_properties = match reused {
+				Some(properties) => {
+					debug!("Reusing Exif properties already extracted for {:?}", src);
+					properties
+				}
+				None => {
+					let properties = Rc::new(self.extract_exif(src, ahead_exif));
+					if let Some(hash) = content_hash {
+						app_state.exif_cache.insert(hash, properties.clone());
+					}
+					properties
+				}
+			};
+			if cached.exif.is_none() {
+				cached.exif = Some(Self::to_cached_exif(&exif_properties));
+				cache_changed = true;
+			}
+			for (key, value) in exif_properties.iter() {
+				if self.args.mode == Mode::Info
+					&& key.contains(UNKNOWN_TAG_MARKER)
+					&& app_state.unknown_tags.insert(key.clone())
+				{
+					info!("Unknown Exif tag found, available as {{{{{}}}}}", key);
+				}
+				add_property(app_state, key, value);
+			}
+			let exif_property =
+				|name: &str| exif_properties.iter().find(|(key, _)| key == name).map(|(_, value)| value);
+			exif_timestamp = [
+				prepend!(EXIF_PREFIX, "DateTimeOriginal"),
+				prepend!(EXIF_PREFIX, "DateTimeDigitized"),
+				prepend!(IFD0_PREFIX, "DateTime"),
+			]
+			.into_iter()
+			.find_map(|name| match exif_property(name) {
+				Some(PropertyValue::Timestamp(timestamp)) => Some(*timestamp),
+				_ => None,
+			});
+			southern = matches!(
+				exif_property(prepend!(GPS_PREFIX, "LatitudeDec")),
+				Some(PropertyValue::Real(latitude)) if *latitude < 0.0
+			);
+		}
+
+		// Calendar properties, of the best timestamp available
+		if let Some(timestamp) = exif_timestamp.or(name_timestamp).or(modified_timestamp) {
+			for (key, value) in Self::calendar_properties(timestamp, southern) {
+				add_property(app_state, &key, &value);
+			}
+		}
+
+		// exiftool tags, either found in this run or loaded from a dump
+		let absolute_src = self.absolute(src);
+		let exiftool_tags =
+			app_state.exiftool_tags.remove(&absolute_src).or_else(|| self.exiftool_dump.get(&absolute_src).cloned());
+		for (tag, value) in exiftool_tags.iter().flatten() {
+			let key = self.attr_formatter.sanitize_key(&format!("{}{}", EXIFTOOL_PREFIX, tag));
+			add_property(app_state, &key, &PropertyValue::from_json(value));
+		}
+
+		// External commands, never cached as their output may depend on more than
+		// the content of the file
+		for (name, command) in &self.args.prop_command {
+			if let Some(value) = self.run_prop_command(app_state, src, command) {
+				add_property(app_state, &self.attr_formatter.sanitize_key(name), &value);
+			}
+		}
+
+		if let (true, Some(cache), Some((key, (size, modified)))) = (cache_changed, &self.property_cache, &cache_key) {
+			if let Err(e) = cache.put(key, *size, modified, &self.cache_settings, &cached) {
+				debug!("{}", e);
+			}
+		}
+	}
+
+	// The properties cached for the file, if unchanged, with the cache key
+	fn cached_properties(&self, src: &Path) -> (Option<CacheKey>, cache::CachedProperties) {
+		let cache_key = self
+			.property_cache
+			.as_ref()
+			.and_then(|_| Some((portable::escape(self.absolute(src).as_os_str()), self.source_state(src)?)));
+		let cached = match (&self.property_cache, &cache_key) {
+			(Some(cache), Some((key, (size, modified)))) =>
+				cache.get(key, *size, modified, &self.cache_settings).unwrap_or_default(),
+			_ => cache::CachedProperties::default(),
+		};
+		(cache_key, cached)
+	}
+
+	// The hashes of the file if cached, with the CRC32 checksum if needed
+	fn cached_hashes(&self, cached: &cache::CachedProperties) -> Option<FileHashes> {
+		let sha1 = cached.sha1.clone()?;
+		let crc32 = cached.crc32.clone();
+		(crc32.is_some() || !self.args.crc32).then_some(FileHashes { sha1, crc32 })
+	}
+
+	// Hashing and parsing the files on --jobs threads, so that reading them
+	// overlaps extracting the other properties and rendering the destination
+	fn read_ahead(&self, sources: &[&PathBuf]) -> Option<pipeline::ReadAhead<Content>> {
+		let jobs = self.args.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+		if jobs == 0 || (!self.needs_sha1() && self.args.no_exif) {
+			return None;
+		}
+		// cached content is not read again
+		let files = sources
+			.iter()
+			.map(|src| {
+				let (_, cached) = self.cached_properties(src);
+				let needs_sha1 = self.needs_sha1() && self.cached_hashes(&cached).is_none();
+				let needs_exif = !self.args.no_exif && cached.exif.is_none();
+				(src.to_path_buf(), needs_sha1, needs_exif)
+			})
+			.collect();
+		let crc32 = self.args.crc32;
+		Some(pipeline::read_ahead(jobs, files, move |(src, needs_sha1, needs_exif): (PathBuf, bool, bool)| Content {
+			hashes: needs_sha1.then(|| file_hashes(&src, crc32)),
+			exif: needs_exif.then(|| read_exif(&src)),
+		}))
+	}
+
+	// The output of the command, without trailing blanks, as a number if possible
+	fn run_prop_command(&self, app_state: &mut AppState, src: &Path, command: &str) -> Option<PropertyValue> {
+		let output = shell_command(command, src).stderr(std::process::Stdio::inherit()).output();
+		match output {
+			Ok(output) if output.status.success() => {
+				let text = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+				Some(match (text.parse::<i64>(), text.parse::<f64>()) {
+					(Ok(n), _) => PropertyValue::Integer(n),
+					(_, Ok(v)) if v.is_finite() => PropertyValue::Real(v),
+					_ => PropertyValue::Text(text),
+				})
+			}
+			Ok(output) => {
+				warn!("Command {} failed for {:?}: {}", command, src, output.status);
+				app_state.report_warning();
+				None
+			}
+			Err(e) => {
+				warn!("Unable to run {} for {:?}: {}", command, src, e);
+				app_state.report_warning();
+				None
+			}
+		}
+	}
+
+	// A list of key and value pairs
+	fn to_cached_exif(properties: &[(String, PropertyValue)]) -> String {
+		Value::Array(
+			properties
+				.iter()
+				.map(|(key, value)| Value::Array(vec![Value::String(key.clone()), value.to_cached()]))
+				.collect(),
+		)
+		.to_string()
+	}
+
+	fn from_cached_exif(cached: &str) -> Option<Vec<(String, PropertyValue)>> {
+		let Ok(Value::Array(properties)) = serde_json::from_str::<Value>(cached) else {
+			return None;
+		};
+		properties
+			.iter()
+			.map(|pair| Some((pair.get(0)?.as_str()?.to_string(), PropertyValue::from_cached(pair.get(1)?)?)))
+			.collect()
+	}
+
+	// The first valid date in the name, at midnight if there is no time
+	fn name_date_time(&self, name: &str) -> Option<NaiveDateTime> {
+		self.name_date_pattern.captures_iter(name).find_map(|captures| {
+			let number = |i: usize| captures.get(i).map_or(Some(0), |m| m.as_str().parse::<u32>().ok());
+			NaiveDate::from_ymd_opt(number(1)? as i32, number(2)?, number(3)?)?.and_hms_opt(
+				number(4)?,
+				number(5)?,
+				number(6)?,
+			)
+		})
+	}
+
+	// From the Exif data already read ahead, if any
+	fn extract_exif(&self, src: &Path, read: Option<io::Result<Option<exif::Exif>>>) -> Vec<(String, PropertyValue)> {
+		let mut properties = Vec::new();
+		match read.unwrap_or_else(|| read_exif(src)) {
+			Ok(exif) => {
+				if let Some(exif) = exif {
+					for f in exif.fields() {
+						debug!(
+							"{:30} {:50} {:10} {:.50}",
+							f.tag,
+							f.tag.description().unwrap_or(""),
+							f.ifd_num,
+							f.display_value().with_unit(&exif).to_string()
+						);
+						let value = if let Some(text) = exif_text::decode(f, exif.little_endian()) {
+							PropertyValue::Text(text)
+						} else {
+							match f.value {
+								exif::Value::Byte(ref n) =>
+									PropertyValue::from_slice(n, PropertyValue::from_opt_integer),
+								exif::Value::Ascii(ref text) => {
+									let src = text.first().map(|v| exif_text::decode_ascii(v, self.args.exif_encoding));
+									match f.tag {
+										exif::Tag::DateTime
+										| exif::Tag::DateTimeOriginal
+										| exif::Tag::DateTimeDigitized => PropertyValue::from_opt_str_datetime(src.as_deref()),
+										_ => PropertyValue::from_opt_str(src.as_deref()),
+									}
+								}
+								exif::Value::Short(ref n) =>
+									PropertyValue::from_slice(n, PropertyValue::from_opt_integer),
+								exif::Value::Long(ref n) =>
+									PropertyValue::from_slice(n, PropertyValue::from_opt_integer),
+								exif::Value::Rational(ref r) =>
+									PropertyValue::from_slice(r, PropertyValue::from_opt_rational),
+								exif::Value::SByte(ref n) =>
+									PropertyValue::from_slice(n, PropertyValue::from_opt_integer),
+								exif::Value::Undefined(_, _) => PropertyValue::Text(f.display_value().to_string()),
+								exif::Value::SShort(ref n) =>
+									PropertyValue::from_slice(n, PropertyValue::from_opt_integer),
+								exif::Value::SLong(ref n) =>
+									PropertyValue::from_slice(n, PropertyValue::from_opt_integer),
+								exif::Value::SRational(ref r) =>
+									PropertyValue::from_slice(r, PropertyValue::from_opt_rational),
+								exif::Value::Float(ref v) => PropertyValue::from_slice(v, PropertyValue::from_opt_real),
+								exif::Value::Double(ref v) =>
+									PropertyValue::from_slice(v, PropertyValue::from_opt_real),
+								exif::Value::Unknown(_, _, _) => PropertyValue::Nothing,
+							}
+						};
+						let key = match f.ifd_num {
+							In::THUMBNAIL => format!("{}{}", EXIFTN_PREFIX, Self::tag_name(f.tag)),
+							_ => format!("{}{}", EXIF_PREFIX, Self::tag_name(f.tag)),
+						};
+						let key = self.attr_formatter.sanitize_key(&key);
+						let qualified_key =
+							self.attr_formatter.sanitize_key(&Self::qualified_tag_name(f.ifd_num, f.tag));
+						if qualified_key != key {
+							properties.push((qualified_key, value.clone()));
+						}
+						properties.push((key, value));
+					}
+					Self::extract_makernote(&exif, &mut properties);
+					Self::merge_subsec(&mut properties);
+					// the camera clock is corrected before any timezone estimate based on it
+					let shift = self.clock_offset(&properties);
+					if !shift.is_zero() {
+						for (_, value) in properties.iter_mut() {
+							if let PropertyValue::Timestamp(ref mut timestamp) = value {
+								*timestamp += shift;
+							}
+						}
+					}
+					let position = self.extract_gps(&exif, &mut properties);
+					self.extract_timezone(&exif, position, &mut properties);
+					Self::extract_daylight(&exif, position, &mut properties);
+					self.extract_subsec_timestamp(&mut properties);
+					Self::extract_calc(&exif, &mut properties);
+				}
+			}
+			Err(e) => error!("Unable to read EXIF from {:?}: {}", src, e),
+		}
+		properties
+	}
+
+	// Tags unknown to the Exif parser are named after their number, e.g.
+	// Tag0x9999, qualified by IFD where numbers could clash
+	fn tag_name(tag: exif::Tag) -> String {
+		if tag.description().is_some() {
+			return tag.to_string();
+		}
+		if let Some(name) = exif_text::xp_tag_name(tag) {
+			return name.to_string();
+		}
+		let ifd = match tag.context() {
+			exif::Context::Gps => "Gps",
+			exif::Context::Interop => "Interop",
+			_ => "",
+		};
+		format!("{}{}{:04x}", ifd, UNKNOWN_TAG_MARKER, tag.number())
+	}
+
+	// The same tag may be found in more than one IFD, e.g. in the primary image and
+	// in the thumbnail: qualified names tell them apart, e.g. Ifd0DateTime,
+	// ExifDateTimeOriginal, GpsLatitude, Ifd1Compression
+	fn qualified_tag_name(ifd_num: In, tag: exif::Tag) -> String {
+		let name = if tag.description().is_some() {
+			tag.to_string()
+		} else if let Some(name) = exif_text::xp_tag_name(tag) {
+			name.to_string()
+		} else {
+			format!("{}{:04x}", UNKNOWN_TAG_MARKER, tag.number())
+		};
+		match (ifd_num, tag.context()) {
+			(In::PRIMARY, exif::Context::Tiff) => format!("{}{}", IFD0_PREFIX, name),
+			(In::PRIMARY, exif::Context::Exif) => format!("{}{}", EXIF_PREFIX, name),
+			(In::PRIMARY, exif::Context::Gps) => format!("{}{}", GPS_PREFIX, name.trim_start_matches("GPS")),
+			(In::PRIMARY, exif::Context::Interop) => format!("{}{}", INTEROP_PREFIX, name),
+			_ => format!("{}{}", IFD1_PREFIX, name),
+		}
+	}
+
+	// Only recent cameras record BodySerialNumber, older ones keep it in the
+	// MakerNote, as well as the number of pictures taken so far
+	fn extract_makernote(exif: &exif::Exif, properties: &mut Vec<(String, PropertyValue)>) {
+		let Some(exif::Value::Undefined(note, offset)) =
+			exif.get_field(exif::Tag::MakerNote, In::PRIMARY).map(|f| &f.value)
+		else {
+			return;
+		};
+		let Some(exif::Value::Ascii(ref make)) = exif.get_field(exif::Tag::Make, In::PRIMARY).map(|f| &f.value) else {
+			return;
+		};
+		let make = make.first().map(|v| String::from_utf8_lossy(v)).unwrap_or_default();
+		let Some(maker_note) = makernote::decode(&make, exif.buf(), exif.little_endian(), *offset as usize, note.len())
+		else {
+			return;
+		};
+		let serial_key = prepend!(EXIF_PREFIX, "BodySerialNumber");
+		if let Some(serial_number) = maker_note.serial_number {
+			if !properties.iter().any(|(k, _)| k == serial_key) {
+				properties.push((serial_key.to_string(), PropertyValue::Text(serial_number)));
+			}
+		}
+		if let Some(shutter_count) = maker_note.shutter_count {
+			properties.push((
+				prepend!(EXIF_PREFIX, "ShutterCount").to_string(),
+				PropertyValue::Integer(shutter_count as i64),
+			));
+		}
+	}
+
+	// SubSecTime tags hold the decimal digits of the fraction of second, e.g. "45"
+	// for 0.45s
+	fn merge_subsec(properties: &mut [(String, PropertyValue)]) {
+		for (key, subsec_key) in [
+			(prepend!(EXIF_PREFIX, "DateTime"), prepend!(EXIF_PREFIX, "SubSecTime")),
+			(prepend!(IFD0_PREFIX, "DateTime"), prepend!(EXIF_PREFIX, "SubSecTime")),
+			(prepend!(EXIF_PREFIX, "DateTimeOriginal"), prepend!(EXIF_PREFIX, "SubSecTimeOriginal")),
+			(prepend!(EXIF_PREFIX, "DateTimeDigitized"), prepend!(EXIF_PREFIX, "SubSecTimeDigitized")),
+		] {
+			let nanos = properties.iter().find_map(|(k, v)| match v {
+				PropertyValue::Text(digits) if k == subsec_key => {
+					let digits = digits.trim();
+					if digits.is_empty() || digits.len() > 9 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+						return None;
+					}
+					format!("{:0<9}", digits).parse::<i64>().ok()
+				}
+				_ => None,
+			});
+			if let Some(nanos) = nanos {
+				if let Some((_, PropertyValue::Timestamp(ref mut timestamp))) =
+					properties.iter_mut().find(|(k, _)| k == key)
+				{
+					*timestamp += Duration::nanoseconds(nanos);
+				}
+			}
+		}
+	}
+
+	// Always unique within a burst, as long as the camera records
+	// SubSecTimeOriginal: the formatted timestamp followed by milliseconds
+	fn extract_subsec_timestamp(&self, properties: &mut Vec<(String, PropertyValue)>) {
+		let key = prepend!(EXIF_PREFIX, "DateTimeOriginal");
+		let original = properties.iter().find_map(|(k, v)| match v {
+			PropertyValue::Timestamp(t) if k == key => Some(*t),
+			_ => None,
+		});
+		if let Some(original) = original {
+			let formatted = format!(
+				"{}{:03}",
+				original.format(&self.args.timestamp_format),
+				original.and_utc().timestamp_subsec_millis()
+			);
+			properties
+				.push((prepend!(EXIF_PREFIX, "DateTimeOriginalSubSec").to_string(), PropertyValue::Text(formatted)));
+		}
+	}
+
+	fn exif_uint(exif: &exif::Exif, tag: exif::Tag) -> Option<u32> {
+		exif.get_field(tag, In::PRIMARY)?.value.get_uint(0).filter(|&n| n > 0)
+	}
+
+	fn exif_f64(exif: &exif::Exif, tag: exif::Tag) -> Option<f64> {
+		match exif.get_field(tag, In::PRIMARY)?.value {
+			exif::Value::Rational(ref r) => r.first().map(|r| r.to_f64()),
+			exif::Value::SRational(ref r) => r.first().map(|r| r.to_f64()),
+			ref value => value.get_uint(0).map(f64::from),
+		}
+		.filter(|&v| v.is_finite() && v > 0.0)
+	}
+
+	// Image size in pixels, as recorded by the camera
+	fn pixel_dimensions(exif: &exif::Exif) -> Option<(u32, u32)> {
+		let width = Self::exif_uint(exif, exif::Tag::PixelXDimension)
+			.or_else(|| Self::exif_uint(exif, exif::Tag::ImageWidth))?;
+		let height = Self::exif_uint(exif, exif::Tag::PixelYDimension)
+			.or_else(|| Self::exif_uint(exif, exif::Tag::ImageLength))?;
+		Some((width, height))
+	}
+
+	// Reduced to the smallest terms, e.g. 3x2, unless those are still large, as
+	// with cropped pictures, in which case the closest common ratio is used
+	fn aspect_ratio(width: u32, height: u32) -> String {
+		const COMMON_RATIOS: [(u32, u32); 9] =
+			[(1, 1), (5, 4), (4, 3), (7, 5), (3, 2), (16, 10), (16, 9), (2, 1), (3, 1)];
+		fn gcd(a: u32, b: u32) -> u32 {
+			if b == 0 {
+				a
+			} else {
+				gcd(b, a % b)
+			}
+		}
+		let divisor = gcd(width, height);
+		let (w, h) = (width / divisor, height / divisor);
+		if w.max(h) <= 32 {
+			return format!("{}x{}", w, h);
+		}
+		let ratio = width.max(height) as f64 / width.min(height) as f64;
+		let (long, short) = COMMON_RATIOS
+			.iter()
+			.copied()
+			.min_by(|a, b| {
+				let distance = |(l, s): (u32, u32)| (l as f64 / s as f64 - ratio).abs();
+				distance(*a).total_cmp(&distance(*b))
+			})
+			.unwrap_or((1, 1));
+		if width >= height {
+			format!("{}x{}", long, short)
+		} else {
+			format!("{}x{}", short, long)
+		}
+	}
+
+	// Focal length of a full frame camera with the same field of view: recorded by
+	// most cameras, otherwise estimated from the size of the sensor
+	fn focal_length_35(exif: &exif::Exif) -> Option<f64> {
+		if let Some(focal_length) = Self::exif_uint(exif, exif::Tag::FocalLengthIn35mmFilm) {
+			return Some(focal_length as f64);
+		}
+		let focal_length = Self::exif_f64(exif, exif::Tag::FocalLength)?;
+		let (width, height) = Self::pixel_dimensions(exif)?;
+		let unit_mm = match Self::exif_uint(exif, exif::Tag::FocalPlaneResolutionUnit).unwrap_or(2) {
+			2 => 25.4,
+			3 => 10.0,
+			4 => 1.0,
+			5 => 0.001,
+			_ => return None,
+		};
+		let sensor_width = width as f64 / Self::exif_f64(exif, exif::Tag::FocalPlaneXResolution)? * unit_mm;
+		let sensor_height = height as f64 / Self::exif_f64(exif, exif::Tag::FocalPlaneYResolution)? * unit_mm;
+		// diagonal of a 36x24mm frame
+		let crop_factor = 43.27 / sensor_width.hypot(sensor_height);
+		Some((focal_length * crop_factor).round())
+	}
+
+	// Calendar properties of a timestamp, with meteorological seasons turned
+	// around south of the equator. The week is numbered within its ISO year, which
+	// may differ from the calendar year in the first and last days of the year
+	fn calendar_properties(timestamp: NaiveDateTime, southern: bool) -> Vec<(String, PropertyValue)> {
+		const SEASONS: [&str; 4] = ["winter", "spring", "summer", "autumn"];
+		let week = timestamp.iso_week();
+		let season = (timestamp.month() as usize % 12 / 3 + if southern { 2 } else { 0 }) % 4;
+		vec![
+			(prepend!(CALC_PREFIX, "Year").to_string(), PropertyValue::Integer(timestamp.year() as i64)),
+			(prepend!(CALC_PREFIX, "Month").to_string(), PropertyValue::Text(format!("{:02}", timestamp.month()))),
+			(
+				prepend!(CALC_PREFIX, "IsoWeek").to_string(),
+				PropertyValue::Text(format!("{}-W{:02}", week.year(), week.week())),
+			),
+			(
+				prepend!(CALC_PREFIX, "Quarter").to_string(),
+				PropertyValue::Text(format!("Q{}", (timestamp.month() - 1) / 3 + 1)),
+			),
+			(prepend!(CALC_PREFIX, "Season").to_string(), PropertyValue::Text(SEASONS[season].to_string())),
+			(prepend!(CALC_PREFIX, "Weekday").to_string(), PropertyValue::Text(timestamp.format("%A").to_string())),
+		]
+	}
+
+	fn extract_calc(exif: &exif::Exif, properties: &mut Vec<(String, PropertyValue)>) {
+		if let Some((width, height)) = Self::pixel_dimensions(exif) {
+			let megapixels = (width as f64 * height as f64 / 1e5).round() / 10.0;
+			properties.push((prepend!(CALC_PREFIX, "Megapixels").to_string(), PropertyValue::Real(megapixels)));
+			properties.push((
+				prepend!(CALC_PREFIX, "AspectRatio").to_string(),
+				PropertyValue::Text(Self::aspect_ratio(width, height)),
+			));
+			// Orientation 5 to 8 are displayed rotated by 90 degrees
+			let rotated = matches!(Self::exif_uint(exif, exif::Tag::Orientation), Some(5..=8));
+			let orientation = match width.cmp(&height) {
+				std::cmp::Ordering::Equal => "square",
+				std::cmp::Ordering::Greater if !rotated => "landscape",
+				std::cmp::Ordering::Less if rotated => "landscape",
+				_ => "portrait",
+			};
+			properties
+				.push((prepend!(CALC_PREFIX, "Orientation").to_string(), PropertyValue::Text(orientation.to_string())));
+		}
+		if let Some(focal_length) = Self::focal_length_35(exif) {
+			properties.push((
+				prepend!(CALC_PREFIX, "FocalLength35").to_string(),
+				PropertyValue::Integer(focal_length as i64),
+			));
+		}
+	}
+
+	// Global time shift plus the offset of the camera which took the picture, if
+	// known
+	fn clock_offset(&self, properties: &[(String, PropertyValue)]) -> Duration {
+		let text = |key: &str| {
+			properties.iter().find_map(|(k, v)| match v {
+				PropertyValue::Text(text) if k == key => Some(text.trim().to_string()),
+				_ => None,
+			})
+		};
+		let camera_offset = text(prepend!(EXIF_PREFIX, "Model")).and_then(|model| {
+			let with_serial = text(prepend!(EXIF_PREFIX, "BodySerialNumber"))
+				.and_then(|serial| self.camera_offsets.get(&format!("{}#{}", model, serial)));
+			with_serial.or_else(|| self.camera_offsets.get(&model)).copied()
+		});
+		self.args.time_shift.unwrap_or_default() + camera_offset.unwrap_or_default()
+	}
+
+	// Signed decimal degrees, negative south of the equator and west of Greenwich
+	fn gps_decimal(exif: &exif::Exif, tag: exif::Tag, ref_tag: exif::Tag, negative_ref: &str) -> Option<f64> {
+		let exif::Value::Rational(ref dms) = exif.get_field(tag, In::PRIMARY)?.value else {
+			return None;
+		};
+		let decimal = dms.iter().take(3).zip([1.0, 60.0, 3600.0]).map(|(r, scale)| r.to_f64() / scale).sum::<f64>();
+		let negative = Self::gps_ref(exif, ref_tag).is_some_and(|r| r.starts_with(negative_ref.as_bytes()));
+		Some(if negative { -decimal } else { decimal })
+	}
+
+	fn gps_ref(exif: &exif::Exif, ref_tag: exif::Tag) -> Option<&[u8]> {
+		match exif.get_field(ref_tag, In::PRIMARY)?.value {
+			exif::Value::Ascii(ref text) => text.first().map(Vec::as_slice),
+			_ => None,
+		}
+	}
+
+	// Unlike exif_f64, zero is a valid altitude, speed or direction
+	fn gps_f64(exif: &exif::Exif, tag: exif::Tag) -> Option<f64> {
+		let exif::Value::Rational(ref r) = exif.get_field(tag, In::PRIMARY)?.value else {
+			return None;
+		};
+		r.first().map(|r| r.to_f64()).filter(|v| v.is_finite())
+	}
+
+	// Altitude in metres, negative below sea level, speed in km/h and direction
+	// the camera faced in degrees, to one decimal
+	fn extract_gps_motion(exif: &exif::Exif, properties: &mut Vec<(String, PropertyValue)>) {
+		let decimal = |value: f64| (value * 10.0).round() / 10.0;
+		if let Some(altitude) = Self::gps_f64(exif, exif::Tag::GPSAltitude) {
+			let below = Self::exif_uint(exif, exif::Tag::GPSAltitudeRef) == Some(1);
+			properties.push((
+				prepend!(GPS_PREFIX, "AltitudeM").to_string(),
+				PropertyValue::Real(decimal(if below { -altitude } else { altitude })),
+			));
+		}
+		if let Some(speed) = Self::gps_f64(exif, exif::Tag::GPSSpeed) {
+			// kilometres per hour by default
+			let km_per_unit = match Self::gps_ref(exif, exif::Tag::GPSSpeedRef) {
+				Some(b"M") => 1.609344,
+				Some(b"N") => 1.852,
+				_ => 1.0,
+			};
+			properties.push((
+				prepend!(GPS_PREFIX, "SpeedKmh").to_string(),
+				PropertyValue::Real(decimal(speed * km_per_unit)),
+			));
+		}
+		if let Some(direction) = Self::gps_f64(exif, exif::Tag::GPSImgDirection) {
+			properties.push((
+				prepend!(GPS_PREFIX, "ImgDirectionDeg").to_string(),
+				PropertyValue::Real(decimal(direction) % 360.0),
+			));
+		}
+	}
+
+	fn extract_gps(&self, exif: &exif::Exif, properties: &mut Vec<(String, PropertyValue)>) -> Option<(f64, f64)> {
+		let precision = self.args.gps_precision;
+		let latitude = Self::gps_decimal(exif, exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S");
+		let longitude = Self::gps_decimal(exif, exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, "W");
+		// signed numbers, south and west negative
+		let scale = 10f64.powi(precision as i32);
+		let decimal = |value: f64| (value * scale).round() / scale;
+		if let Some(latitude) = latitude {
+			properties.push((prepend!(GPS_PREFIX, "LatitudeDec").to_string(), PropertyValue::Real(decimal(latitude))));
+		}
+		if let Some(longitude) = longitude {
+			properties
+				.push((prepend!(GPS_PREFIX, "LongitudeDec").to_string(), PropertyValue::Real(decimal(longitude))));
+		}
+		if let (Some(latitude), Some(longitude)) = (latitude, longitude) {
+			properties.push((
+				prepend!(GPS_PREFIX, "Coords").to_string(),
+				PropertyValue::Text(format!("{:.*},{:.*}", precision, latitude, precision, longitude)),
+			));
+			properties.push((
+				prepend!(GPS_PREFIX, "GeoHash").to_string(),
+				PropertyValue::Text(geohash(latitude, longitude, self.args.geohash_precision)),
+			));
+		}
+		Self::extract_gps_motion(exif, properties);
+		latitude.zip(longitude)
+	}
+
+	// GPS receivers timestamp positions in UTC
+	fn gps_utc(exif: &exif::Exif) -> Option<NaiveDateTime> {
+		let exif::Value::Ascii(ref date) = exif.get_field(exif::Tag::GPSDateStamp, In::PRIMARY)?.value else {
+			return None;
+		};
+		let date = NaiveDate::parse_from_str(std::str::from_utf8(date.first()?).ok()?, "%Y:%m:%d").ok()?;
+		let exif::Value::Rational(ref hms) = exif.get_field(exif::Tag::GPSTimeStamp, In::PRIMARY)?.value else {
+			return None;
+		};
+		let seconds = hms.iter().take(3).zip([3600.0, 60.0, 1.0]).map(|(r, scale)| r.to_f64() * scale).sum::<f64>();
+		Some(date.and_hms_opt(0, 0, 0)? + Duration::milliseconds((seconds * 1000.0) as i64))
+	}
+
+	// Cameras record local time, with an offset only in recent Exif versions. When
+	// the offset is missing, the timezone is looked up from the GPS position if
+	// boundaries are available, otherwise the offset is estimated from the
+	// difference with the GPS clock
+	fn extract_timezone(
+		&self,
+		exif: &exif::Exif,
+		position: Option<(f64, f64)>,
+		properties: &mut Vec<(String, PropertyValue)>,
+	) -> Option<()> {
+		let timestamp = |properties: &[(String, PropertyValue)], key: &str| {
+			properties.iter().find_map(|(k, v)| match v {
+				PropertyValue::Timestamp(t) if k == key => Some(*t),
+				_ => None,
+			})
+		};
+		let recorded_offset = |properties: &[(String, PropertyValue)], key: &str| {
+			properties.iter().find_map(|(k, v)| match v {
+				PropertyValue::Text(text) if k == key => text.trim().parse::<FixedOffset>().ok(),
+				_ => None,
+			})
+		};
+		for (key, offset_key) in [
+			(prepend!(EXIF_PREFIX, "DateTime"), prepend!(EXIF_PREFIX, "OffsetTime")),
+			(prepend!(EXIF_PREFIX, "DateTimeDigitized"), prepend!(EXIF_PREFIX, "OffsetTimeDigitized")),
+		] {
+			let local = timestamp(properties, key);
+			let offset = recorded_offset(properties, offset_key);
+			if let Some(utc) = local.zip(offset).and_then(|(l, o)| l.and_local_timezone(o).earliest()) {
+				properties.push((format!("{}Utc", key), PropertyValue::Timestamp(utc.naive_utc())));
+			}
+		}
+
+		let key = prepend!(EXIF_PREFIX, "DateTimeOriginal");
+		let local = timestamp(properties, key)?;
+		let zone = position
+			.and_then(|(latitude, longitude)| self.tz_boundaries.as_ref()?.lookup(latitude, longitude))
+			.and_then(|tzid| tzid.parse::<Tz>().ok());
+		let (utc, tz_name) = match (recorded_offset(properties, prepend!(EXIF_PREFIX, "OffsetTimeOriginal")), zone) {
+			(Some(offset), _) => (local.and_local_timezone(offset).earliest()?.naive_utc(), offset.to_string()),
+			(None, Some(zone)) => (local.and_local_timezone(zone).earliest()?.naive_utc(), zone.name().to_string()),
+			(None, None) => {
+				// the two clocks are never quite in sync, offsets are rounded to the quarter
+				// hour
+				let minutes = ((local - Self::gps_utc(exif)?).num_seconds() as f64 / 900.0).round() as i64 * 15;
+				let offset =
+					FixedOffset::east_opt(i32::try_from(minutes * 60).ok()?).filter(|_| minutes.abs() <= 14 * 60)?;
+				(local - Duration::minutes(minutes), offset.to_string())
+			}
+		};
+		if let Some(render_timezone) = self.args.render_timezone {
+			if let Some((_, value)) = properties.iter_mut().find(|(k, _)| k == key) {
+				*value = PropertyValue::Timestamp(render_timezone.from_utc_datetime(&utc).naive_local());
+			}
+		}
+		properties.push((prepend!(EXIF_PREFIX, "DateTimeOriginalUtc").to_string(), PropertyValue::Timestamp(utc)));
+		properties.push((prepend!(EXIF_PREFIX, "DateTimeOriginalTz").to_string(), PropertyValue::Text(tz_name)));
+		Some(())
+	}
+
+	// Day, night, or the golden hour, when the sun is low enough for warm light
+	// and long shadows, from the sun's elevation where and when the picture was
+	// taken. Without a timezone, the GPS clock tells the time in UTC
+	fn extract_daylight(
+		exif: &exif::Exif,
+		position: Option<(f64, f64)>,
+		properties: &mut Vec<(String, PropertyValue)>,
+	) -> Option<()> {
+		let (latitude, longitude) = position?;
+		let utc = properties
+			.iter()
+			.find_map(|(k, v)| match v {
+				PropertyValue::Timestamp(t) if k == prepend!(EXIF_PREFIX, "DateTimeOriginalUtc") => Some(*t),
+				_ => None,
+			})
+			.or_else(|| Self::gps_utc(exif))?;
+		let daylight = match solar_elevation(utc, latitude, longitude) {
+			elevation if elevation >= 6.0 => "day",
+			elevation if elevation >= -4.0 => "golden-hour",
+			_ => "night",
+		};
+		properties.push((prepend!(CALC_PREFIX, "Daylight").to_string(), PropertyValue::Text(daylight.to_string())));
+		Some(())
+	}
+
+	fn needs_sha1(&self) -> bool { !self.args.no_sha1 || matches!(self.args.mode, Mode::Dedup | Mode::Dupes) }
+
+	fn moves_files(&self) -> bool {
+		self.args.mode == Mode::Move || (self.args.mode == Mode::Audit && self.args.repair)
+	}
+
+	fn creates_files(&self) -> bool {
+		!matches!(self.args.mode, Mode::Info | Mode::Audit | Mode::Stats | Mode::Dupes | Mode::Similar)
+			|| self.moves_files()
+	}
+
+	fn writes_files(&self) -> bool {
+		matches!(self.args.mode, Mode::Copy | Mode::Clone | Mode::Dedup) || self.moves_files()
+	}
+
+	fn run(&self) -> AppState {
+		let mut app_state = AppState::default();
+		if let Some(ref path) = self.args.log_json {
+			match fs::File::options().create(true).append(true).open(path) {
+				Ok(file) => {
+					app_state.event_log = Some(io::BufWriter::new(file));
+					if self.args.transactional && !self.args.dry_run {
+						app_state.transaction_events = Some(Vec::new());
+					}
+				}
+				Err(e) => {
+					error!("Unable to open event log {:?}: {}", path, e);
+					app_state.report_error();
+					return app_state;
+				}
+			}
+		}
+		if !matches!(self.args.mode, Mode::Info | Mode::Stats | Mode::Dupes | Mode::Similar) {
+			self.lint_template(&mut app_state);
+		}
+		if self.args.test_template {
+			self.test_template(&mut app_state);
+			return app_state;
+		}
+		let mut idx_counter: usize = self.args.idx_start;
+		self.open_checkpoint(&mut app_state, &mut idx_counter);
+		self.open_checksums(&mut app_state);
+		// iterate through all globs
+		for glob in &self.args.sources {
+			debug!("Matching pattern '{}'", glob);
+			let mut matches = self.find_matches(glob, &mut app_state).expect("Error extracting source files");
+			let mut deferred = Vec::new();
+			// applied a batch at a time, as found
+			while let Some(paths) = self.next_matches(&mut app_state, &mut matches) {
+				if app_state.is_aborted() {
+					break;
+				}
+				self.progress.add_files(&paths);
+
+				let mut plan = self.plan_matches(&mut app_state, &paths, &mut idx_counter);
+				if self.args.review && !plan.is_empty() {
+					plan = self.review_plan(&mut app_state, glob, plan);
+				}
+				if self.moves_files() && self.batch_size().is_some() {
+					matches.found.extend(paths.iter().map(|path| self.absolute(path)));
+					plan = self.defer_moves(&matches, plan, &mut deferred);
+				}
+				self.apply_batch(&mut app_state, plan, &paths);
+				if app_state.is_aborted() {
+					break;
+				}
+			}
+			if !deferred.is_empty() && !app_state.is_aborted() {
+				let paths = deferred.iter().map(|op| op.src.clone()).collect::<Vec<_>>();
+				self.apply_batch(&mut app_state, deferred, &paths);
+			}
+			if app_state.is_aborted() {
+				self.remove_scratch(&mut app_state);
+				break;
+			}
+		}
+		self.progress.finish();
+		if self.args.mode == Mode::Stats {
+			app_state.stats.print();
+		}
+		if self.args.mode == Mode::Dupes {
+			if self.args.json {
+				app_state.dupes.export();
+			} else {
+				app_state.dupes.print();
+			}
+		}
+		if self.args.mode == Mode::Similar {
+			if self.args.json {
+				app_state.similar.export(self.args.max_distance);
+			} else {
+				app_state.similar.print(self.args.max_distance);
+			}
+		}
+		if self.args.diff {
+			self.print_diff(&mut app_state);
+		}
+		if self.args.verbose > 0 {
+			for (name, count) in &app_state.empty_properties {
+				info!("{} was empty for {} file(s)", name, count);
+			}
+		}
+		self.finalize(&mut app_state);
+		self.run_batch_hook(&mut app_state);
+		app_state
+	}
+
+	// Runs which write files keep a checkpoint. A dry run only reads it, to show
+	// what resuming would do
+	fn open_checkpoint(&self, app_state: &mut AppState, idx_counter: &mut usize) {
+		let keeps_checkpoint = self.writes_files() && !self.args.dry_run;
+		if !keeps_checkpoint && !self.args.resume {
+			return;
+		}
+		let Some(path) = checkpoint::default_path(&self.cwd, std::env::args_os().skip(1)) else {
+			return;
+		};
+		if self.args.dry_run && !path.exists() {
+			warn!("No interrupted run to resume");
+			app_state.report_warning();
+			return;
+		}
+		match checkpoint::Checkpoint::open(&path, self.args.resume) {
+			Ok(checkpoint) => {
+				if checkpoint.is_resumed() {
+					info!("Resuming the interrupted run from {:?}", path);
+					if let Some(next_idx) = checkpoint.next_idx() {
+						*idx_counter = next_idx;
+					}
+					app_state.dest_seq = checkpoint.next_dest_seq().clone();
+				} else if self.args.resume {
+					warn!("No interrupted run to resume");
+					app_state.report_warning();
+				}
+				app_state.checkpoint = Some(checkpoint);
+			}
+			Err(e) => {
+				warn!("{}, continuing without", e);
+				app_state.report_warning();
+			}
+		}
+	}
+
+	fn open_checksums(&self, app_state: &mut AppState) {
+		let (Some(path), false) = (&self.args.write_checksums, self.args.dry_run) else {
+			return;
+		};
+		match checksums::Checksums::open(path, &self.absolute(path), self.args.resume) {
+			Ok(checksums) => app_state.checksums = Some(checksums),
+			Err(e) => app_state.report_error_message(e),
+		}
+	}
+
+	// The hash of the destination, computed again if its content was rewritten
+	// after the copy, otherwise the same as the source
+	fn dest_sha1(&self, op: &PlannedOp) -> Result<Option<String>, String> {
+		let rewritten =
+			!self.args.strip.is_empty() || (self.args.record_original == Some(RecordOriginal::Exif) && !op.sidecar);
+		if rewritten {
+			file_sha1(&op.dest).map(Some).map_err(|e| format!("Unable to compute hash for {:?}: {}", op.dest, e))
+		} else {
+			Ok(op.data.get(prepend!(SYS_PREFIX, "Sha1")).and_then(Value::as_str).map(str::to_string))
+		}
+	}
+
+	fn record_checkpoint(&self, app_state: &mut AppState, op: &PlannedOp) {
+		let Some(ref mut checkpoint) = app_state.checkpoint else {
+			return;
+		};
+		let src = portable::escape(self.absolute(&op.src).as_os_str());
+		let idx = op.data.get(TYPED_KEY).and_then(|typed| typed[prepend!(SYS_PREFIX, "Idx")].as_u64());
+		let seq = op.data.get(DEST_SEQ_KEYS[0]).and_then(Value::as_str).and_then(|seq| seq.parse().ok());
+		// the folder as DestSeq counts it
+		let dir = op.dest.parent().map(|dir| {
+			if self.case_insensitive {
+				PathBuf::from(dir.to_string_lossy().to_lowercase())
+			} else {
+				dir.to_path_buf()
+			}
+		});
+		if let Err(e) = checkpoint.record(&src, idx.map(|idx| idx as usize), dir.as_deref().zip(seq)) {
+			warn!("{}", e);
+			app_state.report_warning();
+			app_state.checkpoint = None;
+		}
+	}
+
+	// A typo in a property name would only render as an empty string, or fail
+	// in strict mode, for every file
+	fn lint_template(&self, app_state: &mut AppState) {
+		let known = lint::KnownProperties::new(
+			self.args.prop_command.iter().map(|(name, _)| self.attr_formatter.sanitize_key(name)),
+		);
+		for name in self.template_properties.iter().filter(|name| !known.contains(name)) {
+			warn!("{} in {} is not a property of any file", name, self.template_name());
+			app_state.report_warning();
+		}
+	}
+
+	// Prints the destination of each set of sample properties, as the first file
+	// in a folder
+	fn test_template(&self, app_state: &mut AppState) {
+		let samples = match self.args.sample {
+			Some(ref path) => match self.load_samples(path) {
+				Ok(samples) => samples,
+				Err(e) => return app_state.report_error_message(e),
+			},
+			None =>
+				vec![self.sample_data(sample::properties().into_iter().map(|(key, value)| (key.to_string(), value)))],
+		};
+		let idx = format!("{:01$}", self.args.idx_start, self.args.idx_width);
+		for mut data in samples {
+			for key in DEST_SEQ_KEYS.into_iter().chain([prepend!(SYS_PREFIX, "Idx")]) {
+				data.entry(key).or_insert_with(|| Value::String(idx.clone()));
+			}
+			match self.render_dest(&data) {
+				Ok(dest) => println!("{}", self.limit_length(portable::unescape(&dest)).to_string_lossy()),
+				Err(e) =>
+					app_state.report_error_message(format!("Invalid pattern or data {}: {}", self.template_name(), e)),
+			}
+		}
+	}
+
+	// Formatted as if extracted from a file, with the typed values
+	fn sample_data(&self, properties: impl Iterator<Item = (String, PropertyValue)>) -> Map<String, Value> {
+		let mut data = Map::new();
+		let mut typed = Map::new();
+		for (key, value) in properties {
+			typed.insert(key.clone(), value.to_typed());
+			if let Ok(value_as_string) = self.attr_formatter.as_string(&value) {
+				data.insert(key, Value::String(value_as_string));
+			}
+		}
+		data.insert(TYPED_KEY.to_string(), Value::Object(typed));
+		data
+	}
+
+	// Objects listed by export are used as they are, other objects are taken as
+	// raw property values, e.g. {"ExifModel": "X-T5"}
+	fn load_samples(&self, path: &Path) -> Result<Vec<Map<String, Value>>, String> {
+		let content =
+			fs::read_to_string(path).map_err(|e| format!("Unable to read sample properties {:?}: {}", path, e))?;
+		let mut samples = Vec::new();
+		for value in serde_json::Deserializer::from_str(&content).into_iter::<Value>() {
+			let value = value.map_err(|e| format!("Invalid sample properties {:?}: {}", path, e))?;
+			let objects = match value {
+				Value::Array(values) => values,
+				value => vec![value],
+			};
+			for object in objects {
+				let Value::Object(mut object) = object else {
+					return Err(format!("Invalid sample properties {:?}: expected JSON objects", path));
+				};
+				match object.remove("properties") {
+					Some(Value::Object(properties)) => samples.push(properties),
+					_ =>
+						samples.push(self.sample_data(
+							object.iter().map(|(key, value)| (key.clone(), PropertyValue::from_json(value))),
+						)),
+				}
+			}
+		}
+		Ok(samples)
+	}
+
+	fn print_diff(&self, app_state: &mut AppState) {
+		let [(a, a_data), (b, b_data)] = app_state.diffed.as_slice() else {
+			error!("diff compares two files, {} found", app_state.diffed.len());
+			app_state.report_error();
+			return;
+		};
+		let highlight = io::stdout().is_terminal();
+		let differences = diff::print((a, a_data), (b, b_data), self.args.max_display_len, highlight);
+		debug!("{} properties differ", differences);
+	}
+
+	// Runs a hook command, with the placeholders replaced in each argument. A
+	// failing hook is only a warning, as the files were processed anyway
+	fn run_hook(
+		&self,
+		app_state: &mut AppState,
+		command: &[String],
+		placeholders: &[(&str, &Path)],
+		env: Option<&Path>,
+	) {
+		let args = command
+			.iter()
+			.map(|arg| {
+				placeholders
+					.iter()
+					.fold(arg.clone(), |arg, (placeholder, path)| arg.replace(placeholder, &path.to_string_lossy()))
+			})
+			.collect::<Vec<_>>();
+		let Some((program, program_args)) = args.split_first() else {
+			return;
+		};
+		debug!("Running {:?}", args);
+		let mut process = std::process::Command::new(program);
+		process.args(program_args);
+		if let Some(journal) = env {
+			process.env(JOURNAL_ENV, journal);
+		}
+		match process.status() {
+			Ok(status) if status.success() => {}
+			Ok(status) => {
+				warn!("Command {:?} failed: {}", args, status);
+				app_state.report_warning();
+			}
+			Err(e) => {
+				warn!("Unable to run {:?}: {}", args, e);
+				app_state.report_warning();
+			}
+		}
+	}
+
+	// The files processed are listed in a temporary file, removed when the
+	// command completes
+	fn run_batch_hook(&self, app_state: &mut AppState) {
+		let Some(ref command) = self.exec_batch_after else {
+			return;
+		};
+		if app_state.batch_events.is_empty() || app_state.applied.is_empty() {
+			return;
+		}
+		let journal = std::env::temp_dir().join(format!("exif-namer-journal-{}.jsonl", uuid::Uuid::new_v4()));
+		let content =
+			app_state.batch_events.drain(..).map(|event| format!("{}\n", Value::Object(event))).collect::<String>();
+		if let Err(e) = fs::write(&journal, content) {
+			warn!("Unable to write journal {:?}: {}", journal, e);
+			app_state.report_warning();
+			return;
+		}
+		self.run_hook(app_state, command, &[], Some(&journal));
+		if let Err(e) = fs::remove_file(&journal) {
+			debug!("Unable to remove journal {:?}: {}", journal, e);
+		}
+	}
+
+	// With --transactional, any error reverts all the changes applied so far
+	fn finalize(&self, app_state: &mut AppState) {
+		if self.args.transactional && !self.args.dry_run && app_state.error_count() > 0 {
+			warn!("Errors found, rolling back all changes");
+			app_state.applied.clear();
+			for _ in 0..app_state.journal.rollback() {
+				app_state.report_error();
+			}
+			// so that the log tells which changes are no longer in place
+			for mut event in app_state.transaction_events.take().into_iter().flatten().rev() {
+				event.insert("result".to_string(), Value::from("rolled_back"));
+				app_state.log_event(event);
+			}
+			if let Some(Err(e)) = self.state_db.as_ref().map(state::StateDb::rollback) {
+				app_state.report_error_message(e);
+			}
+			if let Some(Err(e)) = app_state.checksums.take().map(checksums::Checksums::rollback) {
+				app_state.report_error_message(e);
+			}
+		} else {
+			for _ in 0..app_state.journal.commit(|path| self.discard_file(path)) {
+				app_state.report_error();
+			}
+			if let Some(Err(e)) = self.state_db.as_ref().map(state::StateDb::commit) {
+				app_state.report_error_message(e);
+			}
+			self.write_manifests(app_state);
+		}
+		// an aborted run can be resumed, unless its changes were rolled back
+		if let Some(checkpoint) = app_state.checkpoint.take().filter(|_| !self.args.dry_run) {
+			if app_state.is_aborted() && !self.args.transactional {
+				info!("Run interrupted, it can be resumed with --resume");
+			} else if let Err(e) = checkpoint.remove() {
+				warn!("{}", e);
+				app_state.report_warning();
+			}
+		}
+		// cached properties are valid regardless of the outcome
+		if let Some(Err(e)) = self.property_cache.as_ref().map(cache::PropertyCache::commit) {
+			warn!("{}", e);
+			app_state.report_warning();
+		}
+	}
+
+	// Appended to, so that each manifest lists all the files ever added to its
+	// folder
+	fn write_manifests(&self, app_state: &mut AppState) {
+		if self.args.dry_run {
+			return;
+		}
+		let mut by_dir = BTreeMap::<PathBuf, Vec<u8>>::new();
+		for (dest, entry) in &app_state.manifest_entries {
+			let dir = dest.parent().map(Path::to_path_buf).unwrap_or_default();
+			let lines = by_dir.entry(dir).or_default();
+			lines.extend_from_slice(entry.to_string().as_bytes());
+			lines.push(b'\n');
+		}
+		for (dir, content) in by_dir {
+			let path = dir.join(MANIFEST_NAME);
+			let written =
+				fs::File::options().create(true).append(true).open(&path).and_then(|mut file| file.write_all(&content));
+			if let Err(e) = written {
+				app_state.report_error_message(format!("Unable to write manifest {:?}: {}", path, e));
+			}
+		}
+	}
+
+	fn contains_files<P: AsRef<Path>>(&self, dir: P) -> io::Result<bool> {
+		for maybe_child in fs::read_dir(dir)? {
+			let child = maybe_child?;
+			if child.file_type()?.is_dir() && (child.file_name() == "." || child.file_name() == "..") {
+				continue;
+			}
+			return Ok(true);
+		}
+		Ok(false)
+	}
+
+	fn delete_empty_dir<P: AsRef<Path>>(&self, app_state: &mut AppState, path_ref: P) -> bool {
+		let candidate_path = path_ref.as_ref();
+		if !self.args.dry_run {
+			debug!("Attempting to delete directory {:?}", &candidate_path);
+			if let Ok(contains_files) = self.contains_files(candidate_path) {
+				if !contains_files {
+					if let Err(e) = fs::remove_dir(candidate_path) {
+						error!("Unable to delete directory {:?}: {}", candidate_path, e);
+					} else {
+						app_state.journal.record(JournalEntry::RemovedDir(candidate_path.to_path_buf()));
+						return true;
+					}
+				}
+			}
+		}
+		false
+	}
+
+	fn cleanup_empty_dirs(&self, app_state: &mut AppState, paths: &[PathBuf]) {
+		let mut candidate_paths = BTreeSet::new();
+
+		for src_path in paths.iter() {
+			if let Some(parent) = src_path.parent() {
+				for ancestor in parent.ancestors() {
+					if !ancestor.as_os_str().is_empty() && !candidate_paths.contains(ancestor) {
+						candidate_paths.insert(PathBuf::from(ancestor));
+					}
+				}
+			}
+		}
+
+		for candidate_path in candidate_paths.iter().rev() {
+			let deleted = self.delete_empty_dir(app_state, candidate_path);
+			if self.args.verbose > 0 {
+				println!("{} {:?}", if deleted { "rmdir" } else { "#rmdir" }, candidate_path);
+			}
+		}
+	}
+
+	// Properties are extracted from all the files first, so that they can be
+	// sorted before indexes are assigned and destinations rendered
+	fn plan_matches(&self, app_state: &mut AppState, paths: &[PathBuf], idx_counter: &mut usize) -> Vec<PlannedOp> {
+		let mut candidates = Vec::new();
+		// companion files follow their main file, even if matched themselves
+		let sidecars: HashSet<PathBuf> = paths.iter().flat_map(|path| self.find_sidecars(path)).collect();
+		let sources = paths
+			.iter()
+			.filter(|path| !sidecars.contains(*path))
+			.filter(|path| {
+				let Some(ref checkpoint) = app_state.checkpoint else {
+					return true;
+				};
+				let completed = checkpoint.is_completed(&portable::escape(self.absolute(path).as_os_str()));
+				if completed {
+					debug!("{:?} completed by the interrupted run, skipping", path);
+				}
+				!completed
+			})
+			.filter(|path| {
+				let Some(ref state_db) = self.state_db else {
+					return true;
+				};
+				let key = portable::escape(self.absolute(path).as_os_str());
+				let unchanged = self
+					.source_state(path)
+					.is_some_and(|(size, modified)| state_db.is_unchanged(&key, size, &modified));
+				if unchanged {
+					debug!("{:?} unchanged since last processed, skipping", path);
+				}
+				!unchanged
+			})
+			.collect::<Vec<_>>();
+		if self.args.exiftool && !sources.is_empty() {
+			match exiftool::run(&sources) {
+				Ok(tags) =>
+					app_state.exiftool_tags =
+						tags.into_iter().map(|(path, tags)| (self.absolute(&path), tags)).collect(),
+				Err(e) => app_state.report_error_message(e),
+			}
+		}
+		let mut read_ahead = self.read_ahead(&sources);
+		// for each file matching the current glob
+		for src_path in sources {
+			if self.error_limit_reached(app_state) {
+				break;
+			}
+			app_state.read_ahead =
+				read_ahead.as_mut().and_then(Iterator::next).map(|content| (src_path.clone(), content));
+			// extract properties as a String -> Value map
+			let planned_state = self.source_state(src_path);
+			let mut data = serde_json::value::Map::new();
+			let mut typed = serde_json::value::Map::new();
+			let mut timestamps = HashMap::new();
+			self.extract_properties(app_state, src_path, |app_state, key, value| {
+				if let PropertyValue::Timestamp(timestamp) = value {
+					timestamps.insert(key.to_owned(), *timestamp);
+				}
+				typed.insert(key.to_owned(), value.to_typed());
+				match self.attr_formatter.as_string(value) {
+					Ok(value_as_string) => {
+						data.insert(key.to_owned(), Value::String(value_as_string));
+					}
+					Err(e) => {
+						error!("Cannot convert {:?} to string: {}", value, e);
+						app_state.report_error();
+					}
+				}
+			});
+			self.progress.hashed(src_path);
+			data.insert(TYPED_KEY.to_string(), Value::Object(typed));
+			candidates.push(PlannedOp {
+				src: src_path.clone(),
+				dest: PathBuf::new(),
+				data,
+				timestamps,
+				also: Vec::new(),
+				planned_state,
+				sidecar: false,
+			});
+		}
+		self.assign_bursts(&mut candidates);
+		if let Some(sort_by) = self.args.sort_by {
+			self.sort_candidates(sort_by, &mut candidates);
+		}
+
+		let mut plan = Vec::new();
+		for PlannedOp { src: src_path, mut data, timestamps, planned_state, .. } in candidates {
+			if self.error_limit_reached(app_state) {
+				break;
+			}
+			if self.args.skip_organized && self.is_organized(&src_path, &data) {
+				debug!("{:?} is already organized, skipping", src_path);
+				let mut event = self.event(&src_path, Some(&src_path), "unchanged");
+				event.insert("reason".to_string(), Value::from("organized"));
+				app_state.log_event(event);
+				continue;
+			}
+			if self.args.idx_per_dir {
+				let dir = src_path.parent().map(Path::to_path_buf);
+				if app_state.idx_dir != dir {
+					*idx_counter = self.args.idx_start;
+					app_state.idx_dir = dir;
+				}
+			}
+			let idx = if self.args.idx_per_stem {
+				*app_state.stem_idx.entry(src_path.with_extension("")).or_insert_with(|| {
+					*idx_counter += 1;
+					*idx_counter - 1
+				})
+			} else {
+				*idx_counter += 1;
+				*idx_counter - 1
+			};
+			data.insert(
+				prepend!(SYS_PREFIX, "Idx").to_string(),
+				Value::String(format!("{:01$}", idx, self.args.idx_width)),
+			);
+			// the actual sequence number is only known once the destination folder is
+			for key in DEST_SEQ_KEYS {
+				data.insert(key.to_string(), Value::String(DEST_SEQ_PLACEHOLDER.to_string()));
+			}
+			if let Some(Value::Object(typed)) = data.get_mut(TYPED_KEY) {
+				typed.insert(prepend!(SYS_PREFIX, "Idx").to_string(), Value::from(idx));
+			}
+
+			for name in self.template_properties.iter().filter(|name| lint::is_empty(&data, name)) {
+				*app_state.empty_properties.entry(name.clone()).or_default() += 1;
+			}
+			match self.render_dest(&data) {
+				Ok(dest) => match self.assign_dest_seq(app_state, &dest, &mut data).map(|dest| self.limit_length(dest))
+				{
+					Ok(dest_path) =>
+						if self.reserve_dest(app_state, &src_path, &dest_path) {
+							self.plan_sidecars(app_state, &src_path, &dest_path, &data, &timestamps, &mut plan);
+							let also = self.plan_also(app_state, &src_path, &data);
+							plan.push(PlannedOp {
+								src: src_path,
+								dest: dest_path,
+								data,
+								timestamps,
+								also,
+								planned_state,
+								sidecar: false,
+							})
+						},
+					Err(e) => {
+						let mut event = self.event(&src_path, None, "error");
+						event.insert("error".to_string(), Value::from(e.clone()));
+						app_state.log_event(event);
+						app_state.report_error_message(e);
+					}
+				},
+				Err(e) => {
+					let message = format!("Invalid pattern or data {}: {}", self.template_name(), e);
+					let mut event = self.event(&src_path, None, "error");
+					event.insert("error".to_string(), Value::from(message.clone()));
+					app_state.log_event(event);
+					app_state.report_error_message(message);
+				}
+			}
+		}
+		plan
+	}
+
+	// The template, or the file it is read from, for messages
+	fn template_name(&self) -> String {
+		match self.args.destination_file {
+			Some(ref path) => path.to_string_lossy().into_owned(),
+			None => self.args.destination.clone(),
+		}
+	}
+
+	fn render_dest(&self, data: &Map<String, Value>) -> Result<String, handlebars::RenderError> {
+		self.render_template(DESTINATION_TEMPLATE_ID, data)
+	}
+
+	fn render_template(&self, template_id: &str, data: &Map<String, Value>) -> Result<String, handlebars::RenderError> {
+		let rendered = self.handlebars.render(template_id, data)?;
+		let windows = match self.args.target_os {
+			TargetOs::Auto => cfg!(windows),
+			target_os => target_os == TargetOs::Windows,
+		};
+		let rendered = match self.args.destination_file {
+			Some(_) if template_id == DESTINATION_TEMPLATE_ID => rendered.lines().map(str::trim).collect(),
+			_ => rendered,
+		};
+		let rendered = match Path::new(&rendered).extension() {
+			Some(ext) if self.args.ext_case != ExtCase::Keep => {
+				let ext = self.args.ext_case.apply(ext);
+				Path::new(&rendered).with_extension(ext).to_string_lossy().into_owned()
+			}
+			_ => rendered,
+		};
+		let rendered = if windows {
+			portable::windows_safe(Path::new(&rendered), &self.args.replacement).to_string_lossy().into_owned()
+		} else {
+			rendered
+		};
+		Ok(match self.args.output_dir {
+			Some(ref output_dir) => portable::escape(output_dir.join(rendered).as_os_str()),
+			None => rendered,
+		})
+	}
+
+	// Names are truncated first, then the file name again if the full path is
+	// still too long
+	fn limit_length(&self, dest: PathBuf) -> PathBuf {
+		let dest = portable::truncate_names(&dest, self.args.max_name_len, self.args.truncate_hash);
+		let Some(max_path_len) = self.args.max_path_len else {
+			return dest;
+		};
+		let excess = self.absolute(&dest).as_os_str().len().saturating_sub(max_path_len);
+		match dest.file_name().map(portable::escape) {
+			Some(name) if excess > 0 && excess < name.len() => dest.with_file_name(portable::unescape(
+				&portable::truncate_name(&name, name.len() - excess, self.args.truncate_hash),
+			)),
+			_ => dest,
+		}
+	}
+
+	// Destinations of --also, with the same properties as the main destination,
+	// including its DestSeq
+	fn plan_also(&self, app_state: &mut AppState, src: &PathBuf, data: &Map<String, Value>) -> Vec<(Mode, PathBuf)> {
+		let mut also = Vec::new();
+		for (i, (mode, template)) in self.args.also.iter().enumerate() {
+			match self.render_template(&format!("{}{}", ALSO_TEMPLATE_ID, i), data) {
+				Ok(rendered) => {
+					let dest = self.limit_length(portable::unescape(&rendered));
+					if self.reserve_dest(app_state, src, &dest) {
+						also.push((*mode, dest));
+					}
+				}
+				Err(e) => app_state.report_error_message(format!("Invalid pattern or data {}: {}", template, e)),
+			}
+		}
+		also
+	}
+
+	// Size and modification time, as recorded in the state database
+	fn source_state(&self, src: &Path) -> Option<(i64, String)> {
+		let metadata = fs::metadata(src).ok()?;
+		let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+		Some((metadata.len() as i64, format!("{}.{:09}", modified.as_secs(), modified.subsec_nanos())))
+	}
+
+	// Whether the source path is one the template could have rendered, with any
+	// index
+	fn is_organized(&self, src: &Path, data: &Map<String, Value>) -> bool {
+		let mut probe = data.clone();
+		probe.insert(PROBE_KEY.to_string(), Value::Bool(true));
+		probe.insert(prepend!(SYS_PREFIX, "Idx").to_string(), Value::String(IDX_WILDCARD.to_string()));
+		for key in DEST_SEQ_KEYS {
+			probe.insert(key.to_string(), Value::String(IDX_WILDCARD.to_string()));
+		}
+		if let Some(Value::Object(typed)) = probe.get_mut(TYPED_KEY) {
+			typed.insert(prepend!(SYS_PREFIX, "Idx").to_string(), Value::from(0));
+		}
+		let Ok(rendered) = self.render_dest(&probe) else {
+			return false;
+		};
+		let rendered = portable::escape(self.absolute(&portable::unescape(&rendered)).as_os_str());
+		let pattern = format!(
+			"^{}{}$",
+			if self.case_insensitive { "(?i)" } else { "" },
+			rendered.split(IDX_WILDCARD).map(regex::escape).collect::<Vec<_>>().join("[0-9]+")
+		);
+		regex::Regex::new(&pattern)
+			.is_ok_and(|pattern| pattern.is_match(&portable::escape(self.absolute(src).as_os_str())))
+	}
+
+	fn find_sidecars(&self, src: &Path) -> Vec<PathBuf> {
+		if !self.creates_files() {
+			return Vec::new();
+		}
+		let mut found = Vec::new();
+		for ext in self.args.with_sidecars.iter() {
+			for ext in [ext.clone(), ext.to_lowercase(), ext.to_uppercase()] {
+				let mut appended = src.as_os_str().to_os_string();
+				appended.push(".");
+				appended.push(&ext);
+				for candidate in [src.with_extension(&ext), PathBuf::from(appended)] {
+					// the same file, on case insensitive filesystems
+					let known = found
+						.iter()
+						.any(|f| f == &candidate || same_file::is_same_file(f, &candidate).unwrap_or(false));
+					if candidate != src && !known && candidate.is_file() {
+						found.push(candidate);
+					}
+				}
+			}
+		}
+		found
+	}
+
+	// Companion files get the same destination name as their main file, with
+	// their own extension
+	fn sidecar_dest(src: &Path, dest: &Path, sidecar: &Path) -> PathBuf {
+		let src_name = src.file_name().unwrap_or_default().to_string_lossy();
+		let sidecar_name = sidecar.file_name().unwrap_or_default().to_string_lossy();
+		match sidecar_name.strip_prefix(&format!("{}.", src_name)) {
+			Some(ext) => {
+				let mut appended = dest.as_os_str().to_os_string();
+				appended.push(".");
+				appended.push(ext);
+				PathBuf::from(appended)
+			}
+			None => dest.with_extension(sidecar.extension().unwrap_or_default()),
+		}
+	}
+
+	// Sidecars are planned just before their main file, only if the main file has
+	// a destination
+	fn plan_sidecars(
+		&self,
+		app_state: &mut AppState,
+		src: &Path,
+		dest: &Path,
+		data: &Map<String, Value>,
+		timestamps: &HashMap<String, NaiveDateTime>,
+		plan: &mut Vec<PlannedOp>,
+	) {
+		for sidecar in self.find_sidecars(src) {
+			let sidecar_dest = Self::sidecar_dest(src, dest, &sidecar);
+			if !self.reserve_dest(app_state, &sidecar, &sidecar_dest) {
+				continue;
+			}
+			// all the properties are those of the main file, except for the content hashes
+			let mut data = data.clone();
+			for key in [prepend!(SYS_PREFIX, "Sha1"), prepend!(SYS_PREFIX, "ShortId"), prepend!(SYS_PREFIX, "Crc32")] {
+				data.remove(key);
+			}
+			if self.needs_sha1() {
+				match file_hashes(&sidecar, self.args.crc32) {
+					Ok(hashes) => {
+						if let Some(id) = short_id(&hashes.sha1, self.args.short_id_length) {
+							data.insert(prepend!(SYS_PREFIX, "ShortId").to_string(), Value::String(id));
+						}
+						if let Some(crc32) = hashes.crc32 {
+							data.insert(prepend!(SYS_PREFIX, "Crc32").to_string(), Value::String(crc32));
+						}
+						data.insert(prepend!(SYS_PREFIX, "Sha1").to_string(), Value::String(hashes.sha1));
+					}
+					Err(e) => {
+						app_state.report_error_message(format!("Unable to compute hash for {:?}: {}", sidecar, e));
+						continue;
+					}
+				}
+			}
+			let planned_state = self.source_state(&sidecar);
+			plan.push(PlannedOp {
+				src: sidecar,
+				dest: sidecar_dest,
+				data,
+				timestamps: timestamps.clone(),
+				also: Vec::new(),
+				planned_state,
+				sidecar: true,
+			});
+		}
+	}
+
+	// Shots of the same camera in quick succession make a burst, identified by the
+	// time of its first shot. Single shots are not part of any burst
+	fn assign_bursts(&self, candidates: &mut [PlannedOp]) {
+		let text = |op: &PlannedOp, key: &str| op.data.get(key).and_then(Value::as_str).unwrap_or("").to_string();
+		let mut shots: Vec<(String, NaiveDateTime, usize)> = candidates
+			.iter()
+			.enumerate()
+			.filter_map(|(i, op)| {
+				let taken = op.timestamps.get(prepend!(EXIF_PREFIX, "DateTimeOriginal"))?;
+				let camera = format!(
+					"{}#{}",
+					text(op, prepend!(EXIF_PREFIX, "Model")),
+					text(op, prepend!(EXIF_PREFIX, "BodySerialNumber"))
+				);
+				Some((camera, *taken, i))
+			})
+			.collect();
+		shots.sort();
+		let gap = Duration::milliseconds(self.args.burst_gap);
+		let mut bursts: Vec<Vec<(NaiveDateTime, usize)>> = Vec::new();
+		let mut previous: Option<(&str, NaiveDateTime)> = None;
+		for (camera, taken, i) in shots.iter() {
+			match previous {
+				Some((previous_camera, previous_taken))
+					if previous_camera == camera && *taken - previous_taken <= gap =>
+					bursts.last_mut().expect("burst started").push((*taken, *i)),
+				_ => bursts.push(vec![(*taken, *i)]),
+			}
+			previous = Some((camera, *taken));
+		}
+		for burst in bursts.iter().filter(|burst| burst.len() > 1) {
+			let first = burst[0].0;
+			let id = format!(
+				"{}{:03}",
+				first.format(&self.args.timestamp_format),
+				first.and_utc().timestamp_subsec_millis()
+			);
+			for (frame, (_, i)) in burst.iter().enumerate() {
+				let data = &mut candidates[*i].data;
+				let frame = frame + 1;
+				data.insert(
+					prepend!(CALC_PREFIX, "BurstId").to_string(),
+					Value::String(self.attr_formatter.sanitize_value(&id)),
+				);
+				data.insert(
+					prepend!(CALC_PREFIX, "BurstFrame").to_string(),
+					Value::String(format!("{:01$}", frame, self.args.idx_width)),
+				);
+				if let Some(Value::Object(typed)) = data.get_mut(TYPED_KEY) {
+					typed.insert(prepend!(CALC_PREFIX, "BurstId").to_string(), Value::String(id.clone()));
+					typed.insert(prepend!(CALC_PREFIX, "BurstFrame").to_string(), Value::from(frame));
+				}
+			}
+		}
+	}
+
+	// Stable, so that files with the same key keep the glob order. Files without
+	// Exif dates are sorted by modification time, files without any date last
+	fn sort_candidates(&self, sort_by: SortBy, candidates: &mut [PlannedOp]) {
+		let timestamp = |op: &PlannedOp, key: &str| op.timestamps.get(key).copied();
+		match sort_by {
+			SortBy::ExifDate => candidates.sort_by_key(|op| {
+				let date = timestamp(op, prepend!(EXIF_PREFIX, "DateTimeOriginal"))
+					.or_else(|| timestamp(op, prepend!(EXIF_PREFIX, "DateTime")))
+					.or_else(|| timestamp(op, prepend!(SYS_PREFIX, "DateTimeModified")));
+				(date.is_none(), date)
+			}),
+			SortBy::Mtime => candidates.sort_by_key(|op| {
+				let date = timestamp(op, prepend!(SYS_PREFIX, "DateTimeModified"));
+				(date.is_none(), date)
+			}),
+			SortBy::Name => candidates.sort_by(|a, b| a.src.file_name().cmp(&b.src.file_name())),
+			SortBy::Size => candidates.sort_by_key(|op| {
+				op.data.get(prepend!(SYS_PREFIX, "Size")).and_then(Value::as_str).and_then(|s| s.parse::<u64>().ok())
+			}),
+		}
+		if self.args.reverse {
+			candidates.reverse();
+		}
+	}
+
+	// DestSeq or SysDirIdx counts files within each destination folder, so it can
+	// only appear in the file name
+	fn assign_dest_seq(
+		&self,
+		app_state: &mut AppState,
+		rendered: &str,
+		data: &mut Map<String, Value>,
+	) -> Result<PathBuf, String> {
+		let dir = Path::new(rendered).parent().map(Path::to_path_buf).unwrap_or_default();
+		if dir.to_string_lossy().contains(DEST_SEQ_PLACEHOLDER) {
+			return Err(format!(
+				"{} cannot be used in the folder part of {}",
+				DEST_SEQ_KEYS.join(" or "),
+				self.template_name()
+			));
+		}
+		// folders differing only by case are the same folder on case insensitive
+		// filesystems
+		let dir = if self.case_insensitive { PathBuf::from(dir.to_string_lossy().to_lowercase()) } else { dir };
+		let counter = app_state.dest_seq.entry(dir).or_insert(self.args.idx_start);
+		let seq = format!("{:01$}", counter, self.args.idx_width);
+		*counter += 1;
+		let dest = portable::unescape(&rendered.replace(DEST_SEQ_PLACEHOLDER, &seq));
+		for key in DEST_SEQ_KEYS {
+			data.insert(key.to_string(), Value::String(seq.clone()));
+		}
+		Ok(dest)
+	}
+
+	// Two files in the same batch cannot end up at the same destination. On case
+	// insensitive filesystems, destinations which differ only by case are the same
+	// file
+	fn reserve_dest(&self, app_state: &mut AppState, src: &PathBuf, dest: &PathBuf) -> bool {
+		if !self.creates_files() {
+			return true;
+		}
+		let dest_absolute = self.absolute(dest);
+		let key = if self.case_insensitive {
+			dest_absolute.to_string_lossy().to_lowercase()
+		} else {
+			dest_absolute.to_string_lossy().into_owned()
+		};
+		match app_state.planned_dests.get(&key) {
+			Some(other_src) => {
+				warn!("Destination {:?} of {:?} collides with the destination of {:?}, skipping", dest, src, other_src);
+				app_state.report_skipped(SkipReason::Conflict);
+				let mut event = self.event(src, Some(dest), "skipped");
+				event.insert("reason".to_string(), Value::from(SkipReason::Conflict.to_string()));
+				app_state.log_event(event);
+				false
+			}
+			None => {
+				app_state.planned_dests.insert(key, src.clone());
+				true
+			}
+		}
+	}
+
+	// Checked before each file, so that a misconfigured run stops early. A
+	// transaction is rolled back at the first error, so there is no point in going
+	// any further either
+	fn error_limit_reached(&self, app_state: &mut AppState) -> bool {
+		if !app_state.is_aborted() {
+			let limit = if self.args.fail_fast || self.args.transactional { Some(0) } else { self.args.max_errors };
+			if limit.is_some_and(|limit| app_state.error_count() > limit) {
+				error!("Aborting after {} error(s)", app_state.error_count());
+				app_state.abort();
+			}
+		}
+		app_state.is_aborted()
+	}
+
+	fn absolute(&self, path: &Path) -> PathBuf { std::path::absolute(path).unwrap_or_else(|_| self.cwd.join(path)) }
+
+	fn apply_plan(&self, app_state: &mut AppState, plan: &[PlannedOp]) {
+		let sources = self.stage_moves(app_state, plan);
+		for (op, src) in plan.iter().zip(sources.iter()) {
+			if self.error_limit_reached(app_state) {
+				break;
+			}
+			let Some(src) = src else {
+				let mut event = self.event(&op.src, Some(&op.dest), "error");
+				event.insert("error".to_string(), Value::from("Could not rename to a temporary name"));
+				app_state.log_event(event);
+				continue;
+			};
+			let size = self.progress.size(src);
+			let source_state = self.state_db.as_ref().and_then(|_| self.source_state(src));
+			let errors = app_state.error_count();
+			app_state.last_error = None;
+			app_state.last_skipped = None;
+			let started = Instant::now();
+			let applied = match self.args.mode {
+				Mode::Dedup => self.apply_dedup(app_state, src, &op.dest, &op.data),
+				Mode::Audit => self.apply_audit(app_state, src, &op.dest, &op.data),
+				mode => self.apply_mode(app_state, mode, src, &op.dest, &op.data),
+			};
+			if applied && !self.args.dry_run && self.writes_files() && !remote::is_remote(&op.dest) {
+				if let (Some(record), false) = (self.args.record_original, op.sidecar) {
+					self.record_original(app_state, op, record);
+				}
+				if let Some(ref key) = self.args.touch {
+					self.touch(app_state, op, key);
+				}
+			}
+			let result = Self::outcome(app_state, errors, applied);
+			// failed files are tried again on resume
+			if result != "error" && !self.args.dry_run {
+				self.record_checkpoint(app_state, op);
+			}
+			if let (Some(state_db), Some((size, modified)), "applied", false) =
+				(&self.state_db, source_state, result, self.args.dry_run)
+			{
+				let hash = op.data.get(prepend!(SYS_PREFIX, "Sha1")).and_then(Value::as_str);
+				let src_key = portable::escape(self.absolute(&op.src).as_os_str());
+				let dest = portable::escape(self.absolute(&op.dest).as_os_str());
+				if let Err(e) = state_db.record(&src_key, size, &modified, hash, &dest) {
+					app_state.report_error_message(e);
+				}
+			}
+			if let ("applied", false) = (result, self.args.dry_run) {
+				if app_state.checksums.is_some() && self.args.mode != Mode::Thumb && !remote::is_remote(&op.dest) {
+					let recorded = self.dest_sha1(op).and_then(|hash| match (hash, app_state.checksums.as_mut()) {
+						(Some(hash), Some(checksums)) => checksums.record(&self.absolute(&op.dest), &hash),
+						_ => Ok(()),
+					});
+					if let Err(e) = recorded {
+						app_state.report_error_message(e);
+					}
+				}
+				if self.args.dest_manifest && self.writes_files() && !remote::is_remote(&op.dest) {
+					let hash = self.dest_sha1(op).unwrap_or_else(|e| {
+						app_state.report_error_message(e);
+						None
+					});
+					let entry = self.manifest_entry(app_state, op, hash);
+					app_state.manifest_entries.push((self.absolute(&op.dest), entry));
+				}
+				if let Some(ref command) = self.exec_after {
+					self.run_hook(app_state, command, &[("{src}", &op.src), ("{dest}", &op.dest)], None);
+				}
+				if self.exec_batch_after.is_some() {
+					app_state.batch_events.push(self.event(&op.src, Some(&op.dest), result));
+				}
+			}
+			let mut event = self.event(&op.src, Some(&op.dest), result);
+			if let Some(reason) = app_state.last_skipped.take() {
+				event.insert("reason".to_string(), Value::from(reason.to_string()));
+			}
+			if let Some(error) = app_state.last_error.take() {
+				event.insert("error".to_string(), Value::from(error));
+			}
+			event.insert("duration_ms".to_string(), Value::from(started.elapsed().as_secs_f64() * 1000.0));
+			app_state.log_event(event);
+			if result == "applied" {
+				self.print_paths(op);
+				self.apply_also(app_state, op, src);
+			}
+			self.progress.applied(size);
+			// the extracted copy is not needed any more, so that the extracted files
+			// and their copies never take twice the space
+			if app_state.archive_entries.contains_key(src) {
+				let _ = fs::remove_file(src);
+			}
+		}
+	}
+
+	fn apply_batch(&self, app_state: &mut AppState, plan: Vec<PlannedOp>, paths: &[PathBuf]) {
+		let plan = self.check_sources(app_state, plan);
+		self.apply_plan(app_state, &plan);
+		self.remove_scratch(app_state);
+		if app_state.is_aborted() {
+			return;
+		}
+		self.progress.catch_up();
+
+		if self.moves_files() && self.args.delete_empty_dirs {
+			self.cleanup_empty_dirs(app_state, paths);
+		}
+	}
+
+	// The tree may change between planning and applying, e.g. while the plan is
+	// reviewed, or sources are hashed: sources which changed since they were
+	// planned are skipped, or stop the run with --on-source-changed abort
+	fn check_sources(&self, app_state: &mut AppState, plan: Vec<PlannedOp>) -> Vec<PlannedOp> {
+		let mut checked = Vec::with_capacity(plan.len());
+		for op in plan {
+			let current_state = self.source_state(&op.src);
+			if current_state == op.planned_state {
+				checked.push(op);
+				continue;
+			}
+			let change = if current_state.is_none() { "no longer exists" } else { "changed since it was planned" };
+			match self.args.on_source_changed {
+				OnSourceChanged::Skip => {
+					warn!("{:?} {}, skipping", op.src, change);
+					app_state.report_skipped(SkipReason::Changed);
+					let mut event = self.event(&op.src, Some(&op.dest), "skipped");
+					event.insert("reason".to_string(), Value::from(SkipReason::Changed.to_string()));
+					app_state.log_event(event);
+				}
+				OnSourceChanged::Abort => {
+					app_state.report_error_message(format!("{:?} {}, aborting", op.src, change));
+					app_state.abort();
+					return Vec::new();
+				}
 			}
 		}
+		checked
+	}
 
-		// Filesystem metadata properties
-		match fs::metadata(src) {
-			Ok(metadata) => {
-				add_property(
-					app_state,
-					prepend!(SYS_PREFIX, "DateTimeModified"),
-					&PropertyValue::from_opt_filetime(metadata.modified().ok()),
-				);
-				add_property(
-					app_state,
-					prepend!(SYS_PREFIX, "DateTimeCreated"),
-					&PropertyValue::from_opt_filetime(metadata.created().or_else(|_| metadata.modified()).ok()),
-				);
-				add_property(
-					app_state,
-					prepend!(SYS_PREFIX, "DateTimeAccessed"),
-					&PropertyValue::from_opt_filetime(metadata.accessed().ok()),
-				);
-				add_property(app_state, prepend!(SYS_PREFIX, "Size"), &PropertyValue::Integer(metadata.len() as i64));
+	// With --print or --print0, the names as they are, even if not valid UTF-8
+	fn print_paths(&self, op: &PlannedOp) {
+		let paths = match (self.args.print, self.args.print0) {
+			(Some(PrintPaths::Src), _) => vec![&op.src],
+			(Some(PrintPaths::Dest), _) => vec![&op.dest],
+			(Some(PrintPaths::Both), _) | (None, true) => vec![&op.src, &op.dest],
+			(None, false) => return,
+		};
+		let mut line = Vec::new();
+		for (i, path) in paths.iter().enumerate() {
+			line.extend_from_slice(path.as_os_str().as_encoded_bytes());
+			line.push(match (self.args.print0, i + 1 == paths.len()) {
+				(true, _) => b'\0',
+				(false, false) => b'\t',
+				(false, true) => b'\n',
+			});
+		}
+		if let Err(e) = io::stdout().lock().write_all(&line) {
+			debug!("Unable to print paths: {}", e);
+		}
+	}
+
+	// As recorded in the event log
+	fn outcome(app_state: &AppState, errors: usize, applied: bool) -> &'static str {
+		if app_state.error_count() > errors {
+			"error"
+		} else if app_state.last_skipped.is_some() {
+			"skipped"
+		} else if applied {
+			"applied"
+		} else {
+			"unchanged"
+		}
+	}
+
+	// Additional destinations are created from the main destination, where the
+	// file is now, or from the source if no file was created
+	fn apply_also(&self, app_state: &mut AppState, op: &PlannedOp, src: &PathBuf) {
+		let from = if self.creates_files() { &op.dest } else { src };
+		for (mode, dest) in op.also.iter() {
+			let errors = app_state.error_count();
+			app_state.last_error = None;
+			app_state.last_skipped = None;
+			let applied = self.apply_mode(app_state, *mode, from, dest, &op.data);
+			let result = Self::outcome(app_state, errors, applied);
+			let mut event = self.event(from, Some(dest), result);
+			event.insert("mode".to_string(), Value::from(mode.to_string()));
+			if let Some(reason) = app_state.last_skipped.take() {
+				event.insert("reason".to_string(), Value::from(reason.to_string()));
 			}
-			Err(e) => {
-				error!("Unable to read fs metadata for {:?}: {}", src, e);
-				app_state.report_error();
+			if let Some(error) = app_state.last_error.take() {
+				event.insert("error".to_string(), Value::from(error));
 			}
+			app_state.log_event(event);
 		}
+	}
 
-		if !self.args.no_sha1 {
-			// File content - Sha1 properties
-			if let Ok(mut file) = fs::File::open(&src) {
-				let mut hasher = Sha1::new();
-				match io::copy(&mut file, &mut hasher) {
-					Ok(_) => {
-						add_property(
-							app_state,
-							&prepend!(SYS_PREFIX, "Sha1"),
-							&PropertyValue::Text(hex::encode(hasher.finalize())),
-						);
-					}
-					Err(e) => {
-						error!("Unable to compute hash for {:?}: {}", &src, e);
-						app_state.report_error();
-					}
+	// Operations left out during the review are dropped from the plan. Cancelling
+	// the review cancels the whole run
+	fn review_plan(&self, app_state: &mut AppState, glob: &str, plan: Vec<PlannedOp>) -> Vec<PlannedOp> {
+		let items: Vec<ReviewItem> = plan
+			.iter()
+			.map(|op| {
+				let warning = if same_file::is_same_file(&op.src, &op.dest).unwrap_or(false) {
+					"same file"
+				} else if !(op.dest.exists() || op.dest.is_symlink()) || !self.creates_files() {
+					""
+				} else if self.args.backup.is_some() {
+					"exists, backup"
+				} else if self.args.force {
+					"exists, overwrite"
+				} else {
+					"exists, skip"
+				};
+				ReviewItem { src: op.src.clone(), dest: op.dest.clone(), warning: warning.to_string() }
+			})
+			.collect();
+		match review::review(&format!("{} {}", self.args.mode, glob), &items) {
+			Ok(Some(selected)) => {
+				let excluded = selected.iter().filter(|&&s| !s).count();
+				if excluded > 0 {
+					info!("{} file(s) excluded during review", excluded);
 				}
+				plan.into_iter().zip(selected).filter_map(|(op, selected)| selected.then_some(op)).collect()
+			}
+			Ok(None) => {
+				warn!("Review cancelled, no further changes applied");
+				app_state.abort();
+				Vec::new()
+			}
+			Err(e) => {
+				app_state.report_error_message(format!("Unable to show the review screen: {}", e));
+				app_state.abort();
+				Vec::new()
 			}
 		}
+	}
 
-		add_property(
-			app_state,
-			prepend!(SYS_PREFIX, "Uuid"),
-			&PropertyValue::from_opt_str(Some(&uuid::Uuid::new_v4().to_string())),
+	// Fields common to all the events in the event log
+	fn event(&self, src: &Path, dest: Option<&Path>, result: &str) -> Map<String, Value> {
+		let mut event = Map::new();
+		event.insert("src".to_string(), Value::from(portable::escape(src.as_os_str())));
+		event.insert(
+			"dest".to_string(),
+			dest.map_or(Value::Null, |dest| Value::from(portable::escape(dest.as_os_str()))),
 		);
+		event.insert("mode".to_string(), Value::from(self.args.mode.to_string()));
+		event.insert("dry_run".to_string(), Value::from(self.args.dry_run));
+		event.insert("result".to_string(), Value::from(result));
+		event
+	}
 
-		if !self.args.no_exif {
-			// File content - Exif properties
-			let exif_file = fs::File::open(src);
-			match exif_file {
-				Ok(file) => {
-					let mut buf_reader = io::BufReader::new(&file);
-					let exif_reader = exif::Reader::new();
-					if let Ok(exif) = exif_reader.read_from_container(&mut buf_reader) {
-						for f in exif.fields() {
-							debug!(
-								"{:30} {:50} {:10} {:.50}",
-								f.tag,
-								f.tag.description().unwrap_or(""),
-								f.ifd_num,
-								f.display_value().with_unit(&exif).to_string()
-							);
-							let value = match f.value {
-								exif::Value::Byte(ref n) => PropertyValue::from_opt_integer(n.first()),
-								exif::Value::Ascii(ref text) => {
-									let src = text.first().map(|v| std::str::from_utf8(&*v)).and_then(Result::ok);
-									match f.tag {
-										exif::Tag::DateTime
-										| exif::Tag::DateTimeOriginal
-										| exif::Tag::DateTimeDigitized => PropertyValue::from_opt_str_datetime(src),
-										_ => PropertyValue::from_opt_str(src),
-									}
-								}
-								exif::Value::Short(ref n) => PropertyValue::from_opt_integer(n.first()),
-								exif::Value::Long(ref n) => PropertyValue::from_opt_integer(n.first()),
-								exif::Value::Rational(ref r) => PropertyValue::from_opt_rational(r.first()),
-								exif::Value::SByte(ref n) => PropertyValue::from_opt_integer(n.first()),
-								exif::Value::Undefined(_, _) => PropertyValue::Text(f.display_value().to_string()),
-								exif::Value::SShort(ref n) => PropertyValue::from_opt_integer(n.first()),
-								exif::Value::SLong(ref n) => PropertyValue::from_opt_integer(n.first()),
-								exif::Value::SRational(ref r) => PropertyValue::from_opt_rational(r.first()),
-								exif::Value::Float(ref v) => PropertyValue::from_opt_real(v.first()),
-								exif::Value::Double(ref v) => PropertyValue::from_opt_real(v.first()),
-								exif::Value::Unknown(_, _, _) => PropertyValue::Nothing,
-							};
-							let key = match f.ifd_num {
-								In::THUMBNAIL => format!("{}{}", EXIFTN_PREFIX, f.tag),
-								_ => format!("{}{}", EXIF_PREFIX, f.tag),
-							};
-							add_property(app_state, &self.attr_formatter.sanitize_key(&key), &value);
-						}
-					}
+	// Moves whose destination is the source of another move in the same plan, e.g.
+	// when re-sequencing files in place, are applied in two phases: all the sources
+	// are first renamed to unique temporary names, then to their final
+	// destinations. Returns the source each operation should be applied from, None
+	// if the file could not be staged
+	fn stage_moves(&self, app_state: &mut AppState, plan: &[PlannedOp]) -> Vec<Option<PathBuf>> {
+		let mut sources: Vec<Option<PathBuf>> = plan.iter().map(|op| Some(op.src.clone())).collect();
+		if self.args.mode != Mode::Move {
+			return sources;
+		}
+		let absolute_sources: HashSet<PathBuf> = plan.iter().map(|op| self.absolute(&op.src)).collect();
+		let overlapping = plan.iter().any(|op| {
+			let dest = self.absolute(&op.dest);
+			dest != self.absolute(&op.src) && absolute_sources.contains(&dest)
+		});
+		if !overlapping {
+			return sources;
+		}
+
+		info!("Some destinations are also sources of other moves, renaming in two phases");
+		for (op, src) in plan.iter().zip(sources.iter_mut()) {
+			if self.absolute(&op.src) == self.absolute(&op.dest) {
+				continue;
+			}
+			if self.args.dry_run {
+				app_state.vacated.insert(self.absolute(&op.src));
+				continue;
+			}
+			let file_name = op.src.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+			let staged = op.src.with_file_name(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+			debug!("Staging {:?} as {:?}", op.src, staged);
+			match fs::rename(&op.src, &staged) {
+				Ok(()) => {
+					app_state.journal.record(JournalEntry::Renamed { from: op.src.clone(), to: staged.clone() });
+					*src = Some(staged);
+				}
+				Err(e) => {
+					error!("Could not rename {:?} to a temporary name: {}", op.src, e);
+					app_state.report_error();
+					*src = None;
 				}
-				Err(e) => error!("Unable to read EXIF from {:?}: {}", src, e),
 			}
 		}
+		sources
 	}
 
-	fn run(&self) -> AppState {
-		let mut app_state = AppState::default();
-		let mut idx_counter: usize = self.args.idx_start;
-		// iterate through all globs
-		for glob in &self.args.sources {
-			debug!("Matching pattern '{}'", glob);
-			let paths = self.find_matches(glob, &mut app_state).expect("Error extracting source files");
+	// The file added, with where it came from, as listed by --dest-manifest
+	fn manifest_entry(&self, app_state: &AppState, op: &PlannedOp, hash: Option<String>) -> Value {
+		let src = app_state.archive_entries.get(&op.src).unwrap_or(&op.src);
+		json!({
+			"name": op.dest.file_name().map(portable::escape),
+			"src": portable::escape(self.absolute(src).as_os_str()),
+			"sha1": hash,
+			"added": self.now.to_rfc3339(),
+		})
+	}
 
-			self.apply_matches(&mut app_state, &paths, &mut idx_counter);
+	// A failure to record leaves the file where it was put, with a warning
+	fn record_original(&self, app_state: &mut AppState, op: &PlannedOp, record: RecordOriginal) {
+		let src = app_state.archive_entries.get(&op.src).unwrap_or(&op.src);
+		let original = portable::escape(self.absolute(src).as_os_str());
+		let recorded = match record {
+			RecordOriginal::Xmp => self.record_original_xmp(app_state, &op.dest, &original),
+			RecordOriginal::Exif => record_original_exif(&op.dest, &original),
+		};
+		match recorded {
+			Ok(true) => debug!("Recorded original path {:?} in {:?}", original, op.dest),
+			Ok(false) => debug!("{:?} already has an original path recorded", op.dest),
+			Err(e) => {
+				warn!("Could not record the original path of {:?}: {}", op.dest, e);
+				app_state.report_warning();
+			}
+		}
+	}
 
-			if self.args.mode == Mode::Move && self.args.delete_empty_dirs {
-				self.cleanup_empty_dirs(&paths);
+	fn record_original_xmp(&self, app_state: &mut AppState, dest: &Path, original: &str) -> Result<bool, String> {
+		match xmp::find_sidecar(dest) {
+			Some(sidecar) => {
+				let content =
+					fs::read_to_string(&sidecar).map_err(|e| format!("Could not read {:?}: {}", sidecar, e))?;
+				let Some(content) = xmp::preserve_file_name(Some(&content), original)? else {
+					return Ok(false);
+				};
+				write_in_place(&sidecar, content.as_bytes())?;
+			}
+			None => {
+				let mut sidecar = dest.as_os_str().to_os_string();
+				sidecar.push(".xmp");
+				let sidecar = PathBuf::from(sidecar);
+				let content = xmp::preserve_file_name(None, original)?.unwrap_or_default();
+				fs::write(&sidecar, content).map_err(|e| format!("Could not write {:?}: {}", sidecar, e))?;
+				app_state.journal.record(JournalEntry::Created(sidecar));
 			}
 		}
-		app_state
+		Ok(true)
 	}
 
-	fn contains_files<P: AsRef<Path>>(&self, dir: P) -> io::Result<bool> {
-		for maybe_child in fs::read_dir(dir)? {
-			let child = maybe_child?;
-			if child.file_type()?.is_dir() && (child.file_name() == "." || child.file_name() == "..") {
-				continue;
+	// Timestamps carry no timezone, they are assumed to be in local time
+	fn touch(&self, app_state: &mut AppState, op: &PlannedOp, key: &str) {
+		let Some(timestamp) = op.timestamps.get(key) else {
+			warn!("No {} timestamp available for {:?}, modification time left unchanged", key, op.src);
+			app_state.report_warning();
+			return;
+		};
+		let Some(local) = timestamp.and_local_timezone(Local).earliest() else {
+			warn!("Invalid local time {} for {:?}, modification time left unchanged", timestamp, op.src);
+			app_state.report_warning();
+			return;
+		};
+		let touched = fs::metadata(&op.dest).and_then(|metadata| metadata.modified()).and_then(|modified| {
+			fs::File::options().write(true).open(&op.dest)?.set_modified(local.into())?;
+			Ok(modified)
+		});
+		match touched {
+			Ok(modified) => app_state.journal.record(JournalEntry::Touched { path: op.dest.clone(), modified }),
+			Err(e) => {
+				app_state.report_error_message(format!("Could not set modification time of {:?}: {}", op.dest, e));
 			}
-			return Ok(true);
 		}
-		Ok(false)
 	}
 
-	fn delete_empty_dir<P: AsRef<Path>>(&self, path_ref: P) -> bool {
-		let candidate_path = path_ref.as_ref();
-		if !self.args.dry_run {
-			debug!("Attempting to delete directory {:?}", &candidate_path);
-			if let Ok(contains_files) = self.contains_files(candidate_path) {
-				if !contains_files {
-					if let Err(e) = fs::remove_dir(candidate_path) {
-						error!("Unable to delete directory {:?}: {}", candidate_path, e);
-					} else {
-						return true;
-					}
+	// The first file with a given content hash is copied to its destination, all
+	// the following files with the same content are hard linked to that first copy
+	// instead
+	fn apply_dedup(&self, app_state: &mut AppState, src: &PathBuf, dest: &PathBuf, data: &Map<String, Value>) -> bool {
+		let Some(hash) = data.get(prepend!(SYS_PREFIX, "Sha1")).and_then(Value::as_str).map(str::to_owned) else {
+			app_state.report_error_message(format!("No content hash available for {:?}, skipping", src));
+			return false;
+		};
+		match app_state.dedup_targets.get(&hash).cloned() {
+			Some(first) => self.apply_mode(app_state, Mode::HardLink, &first, dest, data),
+			None => {
+				let copied = self.apply_mode(app_state, Mode::Dedup, src, dest, data);
+				if copied {
+					app_state.dedup_targets.insert(hash, dest.clone());
 				}
+				copied
 			}
 		}
-		return false;
 	}
 
-	fn cleanup_empty_dirs(&self, paths: &Vec<PathBuf>) {
-		let mut candidate_paths = BTreeSet::new();
+	// A file conforms if its current location is exactly where the template would
+	// put it. True only if the file did not conform and has been repaired
+	fn apply_audit(&self, app_state: &mut AppState, src: &PathBuf, dest: &PathBuf, data: &Map<String, Value>) -> bool {
+		let src_absolute = self.absolute(src);
+		let dest_absolute = self.absolute(dest);
+		if src_absolute == dest_absolute {
+			if self.args.verbose > 0 {
+				println!("{} {:?}", Mode::Audit, src);
+			}
+			false
+		} else if self.args.repair {
+			// the repair is a regular move, with the usual safety checks and dry run
+			// support
+			let repaired = self.apply_mode(app_state, Mode::Move, src, dest, data);
+			if repaired && !self.args.dry_run {
+				info!("{:?} did not conform to the destination template, repaired as {:?}", src, dest);
+			}
+			repaired
+		} else {
+			if self.args.verbose > 0 {
+				println!("{} {:?} {:?}", Mode::Audit, src, dest);
+			}
+			warn!("{:?} does not conform to the destination template, expected {:?}", src, dest);
+			app_state.report_warning();
+			false
+		}
+	}
 
-		for src_path in paths.iter() {
-			if let Some(parent) = src_path.parent() {
-				for ancestor in parent.ancestors() {
-					if !ancestor.as_os_str().is_empty() && !candidate_paths.contains(ancestor) {
-						candidate_paths.insert(PathBuf::from(ancestor));
-					}
+	// Renames cannot cross filesystem boundaries, in which case the file is copied,
+	// verified and only then removed from its source location
+	fn move_file(&self, src: &Path, dest: &Path, data: &Map<String, Value>) -> Result<(), String> {
+		match fs::rename(src, dest) {
+			Ok(()) => Ok(()),
+			Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+				debug!("{:?} and {:?} are on different filesystems, copying instead", src, dest);
+				fs::copy(src, dest).map_err(|e| format!("Could not copy {:?}: {}", src, e))?;
+				self.verify_copy(src, dest, data)?;
+				if self.args.preserve_xattrs {
+					copy_xattrs(src, dest)
+						.map_err(|e| format!("Could not copy extended attributes of {:?}: {}", src, e))?;
 				}
+				copy_file_times(src, dest).map_err(|e| format!("Could not preserve timestamps of {:?}: {}", src, e))?;
+				fs::remove_file(src).map_err(|e| format!("Copied {:?}, but could not remove it: {}", src, e))
 			}
+			Err(e) => Err(format!("Could not rename {:?}: {}", src, e)),
 		}
+	}
 
-		for candidate_path in candidate_paths.iter().rev() {
-			let deleted = self.delete_empty_dir(candidate_path);
-			if self.args.verbose {
-				println!("{} {:?}", if deleted { "rmdir" } else { "#rmdir" }, candidate_path);
+	fn copy_file(&self, mode: Mode, src: &Path, dest: &Path, data: &Map<String, Value>) -> Result<(), String> {
+		if !self.args.strip.is_empty() {
+			// a file which could not be stripped is not copied at all
+			let content = fs::read(src).map_err(|e| format!("Could not read {:?}: {}", src, e))?;
+			let stripped = metadata::strip_jpeg(&content, &self.args.strip)
+				.map_err(|e| format!("Could not strip metadata from {:?}: {}", src, e))?;
+			return fs::write(dest, stripped).map_err(|e| format!("Could not write {:?}: {}", dest, e));
+		} else if mode == Mode::Clone {
+			// FICLONE on Linux, clonefile on macOS
+			match reflink_copy::reflink_or_copy(src, dest) {
+				Ok(Some(_)) => debug!("Copy-on-write not supported for {:?}, copied instead", dest),
+				Ok(None) => {}
+				Err(e) => return Err(format!("Could not clone {:?}: {}", src, e)),
 			}
+		} else {
+			fs::copy(src, dest).map_err(|e| format!("Could not copy {:?}: {}", src, e))?;
+		}
+		if self.args.preserve_xattrs {
+			copy_xattrs(src, dest).map_err(|e| format!("Could not copy extended attributes of {:?}: {}", src, e))?;
 		}
+		if self.args.verify {
+			self.verify_copy(src, dest, data)?;
+		}
+		Ok(())
 	}
 
-	fn apply_matches(&self, app_state: &mut AppState, paths: &Vec<PathBuf>, idx_counter: &mut usize) {
-		// for each file matching the current glob
-		for src_path in paths.iter() {
-			// extract properties as a String -> Value map
-			let mut data = serde_json::value::Map::new();
-			data.insert(
-				prepend!(SYS_PREFIX, "Idx").to_string(),
-				Value::String(format!("{:01$}", idx_counter, self.args.idx_width)),
-			);
-			*idx_counter += 1;
-			self.extract_properties(app_state, src_path, |app_state, key, value| {
-				match self.attr_formatter.as_string(value) {
-					Ok(value_as_string) => {
-						data.insert(key.to_owned(), Value::String(value_as_string));
-					}
-					Err(e) => {
-						error!("Cannot convert {:?} to string: {}", value, e);
-						app_state.report_error();
-					}
-				}
-			});
+	// Compare the content hash of a freshly written destination with its source. A
+	// bad copy is removed, so that a later run can try again without --force
+	fn verify_copy(&self, src: &Path, dest: &Path, data: &Map<String, Value>) -> Result<(), String> {
+		let src_hash = match data.get(prepend!(SYS_PREFIX, "Sha1")).and_then(Value::as_str) {
+			Some(hash) => hash.to_owned(),
+			None => file_sha1(src).map_err(|e| format!("Unable to compute hash for {:?}: {}", src, e))?,
+		};
+		let dest_hash = file_sha1(dest).map_err(|e| format!("Unable to compute hash for {:?}: {}", dest, e))?;
+		if src_hash != dest_hash {
+			if let Err(e) = fs::remove_file(dest) {
+				error!("Unable to remove corrupted copy {:?}: {}", dest, e);
+			}
+			return Err(format!("Verification failed, content of {:?} differs from {:?}", dest, src));
+		}
+		debug!("Verified {:?} against {:?}", dest, src);
+		Ok(())
+	}
 
-			match self.handlebars.render(DESTINATION_TEMPLATE_ID, &data) {
-				Ok(dest) => {
-					let dest_path = PathBuf::from(dest);
-					self.apply_mode(app_state, self.args.mode, src_path, &dest_path, &data);
-				}
-				Err(e) => error!("Invalid pattern or data {}: {}", &self.args.destination, e),
+	// Same naming scheme as GNU cp/mv: simple backups append a suffix to the name,
+	// numbered backups append .~N~ with N one more than the highest existing one
+	fn backup_path(&self, backup_mode: BackupMode, dest: &Path) -> PathBuf {
+		let file_name = dest.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+		let numbered_prefix = format!("{}.~", file_name);
+		let last_number = dest
+			.parent()
+			.map(|parent| if parent.as_os_str().is_empty() { Path::new(".") } else { parent })
+			.and_then(|parent| fs::read_dir(parent).ok())
+			.into_iter()
+			.flatten()
+			.filter_map(|entry| entry.ok())
+			.filter_map(|entry| {
+				let name = entry.file_name().to_string_lossy().into_owned();
+				name.strip_prefix(&numbered_prefix)?.strip_suffix('~')?.parse::<usize>().ok()
+			})
+			.max();
+		let backup_name = match (backup_mode, last_number) {
+			(BackupMode::Numbered, None) => format!("{}1~", numbered_prefix),
+			(BackupMode::Numbered | BackupMode::Existing, Some(n)) => format!("{}{}~", numbered_prefix, n + 1),
+			(BackupMode::Simple | BackupMode::Existing, _) => format!("{}{}", file_name, self.args.backup_suffix),
+		};
+		dest.with_file_name(backup_name)
+	}
+
+	// In a transaction, files overwritten with --force are only renamed out of the
+	// way, and discarded once all the changes have been applied successfully
+	fn displace_file(&self, app_state: &mut AppState, path: &PathBuf) -> Result<(), String> {
+		if !self.args.transactional {
+			return self.discard_file(path);
+		}
+		let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+		let temp = path.with_file_name(format!(".{}.{}.displaced", file_name, uuid::Uuid::new_v4()));
+		fs::rename(path, &temp).map_err(|e| format!("Could not rename {:?} out of the way: {}", path, e))?;
+		app_state.journal.record(JournalEntry::Displaced { original: path.clone(), temp });
+		Ok(())
+	}
+
+	// Files overwritten with --force are either deleted or sent to the trash
+	fn discard_file(&self, path: &Path) -> Result<(), String> {
+		if self.args.use_trash {
+			trash::delete(path).map_err(|e| format!("Could not move {:?} to the trash: {}", path, e))
+		} else {
+			fs::remove_file(path).map_err(|e| format!("Could not remove {:?}: {}", path, e))
+		}
+	}
+
+	// Remote destinations are uploaded to directly, without a local copy. Neither
+	// undo nor --transactional can remove them
+	fn apply_remote(&self, app_state: &mut AppState, mode: Mode, src: &Path, dest: &Path) -> bool {
+		if !matches!(mode, Mode::Copy | Mode::Clone) {
+			app_state.report_error_message(format!("Remote destination {:?} can only be used with -m cp", dest));
+			return false;
+		}
+		let remote = match remote::Remote::parse(dest) {
+			Ok(remote) => remote,
+			Err(e) => {
+				app_state.report_error_message(format!("Invalid remote destination: {}", e));
+				return false;
+			}
+		};
+		match remote.exists() {
+			Ok(false) => {}
+			Ok(true) if self.args.force => debug!("Remote destination {:?} exists, overwriting", dest),
+			Ok(true) => {
+				warn!("Destination file exists, skipping. Use --force to overwrite");
+				app_state.report_skipped(SkipReason::Exists);
+				return false;
+			}
+			Err(e) => {
+				app_state.report_error_message(format!("Could not check remote destination {:?}: {}", dest, e));
+				return false;
+			}
+		}
+		if self.args.dry_run {
+			debug!("Dry run mode, will not upload");
+			app_state.report_applied(mode);
+			return true;
+		}
+		let uploaded = if self.args.strip.is_empty() {
+			fs::File::open(src)
+				.and_then(|file| Ok((file.metadata()?.len(), file)))
+				.map_err(|e| format!("Could not read {:?}: {}", src, e))
+				.and_then(|(len, file)| remote.upload(file, len, &mut app_state.remote_collections))
+		} else {
+			fs::read(src)
+				.map_err(|e| format!("Could not read {:?}: {}", src, e))
+				.and_then(|content| {
+					metadata::strip_jpeg(&content, &self.args.strip)
+						.map_err(|e| format!("Could not strip metadata from {:?}: {}", src, e))
+				})
+				.and_then(|stripped| {
+					remote.upload(stripped.as_slice(), stripped.len() as u64, &mut app_state.remote_collections)
+				})
+		};
+		match uploaded {
+			Ok(()) => {
+				app_state.report_applied(mode);
+				true
+			}
+			Err(e) => {
+				app_state.report_error_message(format!("Could not upload {:?} to {:?}: {}", src, dest, e));
+				false
 			}
 		}
 	}
@@ -643,60 +4944,98 @@ impl<'a> App<'a> {
 		src: &PathBuf,
 		dest: &PathBuf,
 		data: &Map<String, Value>,
-	) {
-		if self.args.verbose {
+	) -> bool {
+		if self.args.verbose > 0 {
 			println!("{} {:?} {:?}", mode, src, dest);
 		}
 
-		if self.args.mode != Mode::Info {
+		if remote::is_remote(dest)
+			&& !matches!(mode, Mode::Info | Mode::Audit | Mode::Stats | Mode::Dupes | Mode::Similar)
+		{
+			return self.apply_remote(app_state, mode, src, dest);
+		}
+
+		// known from the properties, so that dry runs skip the same files
+		if mode == Mode::Thumb && !data.contains_key(prepend!(EXIFTN_PREFIX, "JPEGInterchangeFormatLength")) {
+			warn!("No embedded thumbnail in {:?}, skipping", src);
+			app_state.report_skipped(SkipReason::NoThumbnail);
+			return false;
+		}
+
+		if !matches!(mode, Mode::Info | Mode::Audit | Mode::Stats | Mode::Dupes | Mode::Similar) {
 			if same_file::is_same_file(src, dest).unwrap_or(false) {
 				warn!("Source and destination file are the same, skipping");
-				app_state.report_warning();
-				return;
+				app_state.report_skipped(SkipReason::SameFile);
+				return false;
 			}
 
-			if dest.exists() || dest.is_symlink() {
-				if self.args.force {
-					if let Err(e) = fs::remove_file(dest) {
-						error!("Destination exists, and --force specified, but could not remove: {}", e);
-						app_state.report_error();
-						return;
+			// in a dry run, files which would have been moved out of the way by a two
+			// phase rename are still there
+			if (dest.exists() || dest.is_symlink()) && !app_state.vacated.contains(&self.absolute(dest)) {
+				if let Some(backup_mode) = self.args.backup {
+					let backup = self.backup_path(backup_mode, dest);
+					if self.args.verbose > 0 {
+						println!("backup {:?} {:?}", dest, backup);
+					}
+					if self.args.dry_run {
+						debug!("Dry run mode, will not back up existing destination {:?}", dest);
+					} else if let Err(e) = fs::rename(dest, &backup) {
+						app_state.report_error_message(format!(
+							"Destination exists, but could not back it up as {:?}: {}",
+							backup, e
+						));
+						return false;
+					} else {
+						app_state.journal.record(JournalEntry::Renamed { from: dest.clone(), to: backup });
+					}
+				} else if self.args.force {
+					if self.args.dry_run {
+						debug!("Dry run mode, will not remove existing destination {:?}", dest);
+					} else if let Err(e) = self.displace_file(app_state, dest) {
+						app_state.report_error_message(format!(
+							"Destination exists, and --force specified, but could not remove: {}",
+							e
+						));
+						return false;
 					}
 				} else {
-					warn!("Destination file exists, skipping. Use --force to overwrite");
-					app_state.report_warning();
-					return;
+					warn!("Destination file exists, skipping. Use --force or --backup to overwrite");
+					app_state.report_skipped(SkipReason::Exists);
+					return false;
 				}
 			}
 
 			if self.args.dry_run {
 				debug!("Dry run mode, will not make any filesystem change");
-				return;
+				app_state.report_applied(mode);
+				return true;
 			}
 
 			if let Some(parent) = dest.parent() {
 				if !parent.exists() {
-					if let Err(e) = fs::create_dir_all(parent) {
-						error!("Could not create containing directory {:?}: {}", parent, e);
-						app_state.report_error();
-						return;
+					let missing: Vec<PathBuf> = parent
+						.ancestors()
+						.take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+						.map(PathBuf::from)
+						.collect();
+					if let Err(e) = fs::create_dir_all(portable::long_path(parent)) {
+						app_state
+							.report_error_message(format!("Could not create containing directory {:?}: {}", parent, e));
+						return false;
+					}
+					for dir in missing.into_iter().rev() {
+						app_state.journal.record(JournalEntry::CreatedDir(dir));
 					}
 				}
 			}
 		}
 
+		// deep destinations are fine on Windows too
+		let (long_src, long_dest) = (portable::long_path(src), portable::long_path(dest));
 		#[allow(deprecated)]
-		match self.args.mode {
-			Mode::Move =>
-				if let Err(e) = fs::rename(src, dest) {
-					error!("Could not rename {:?}: {}", src, e);
-					app_state.report_error();
-				},
-			Mode::Copy =>
-				if let Err(e) = fs::copy(src, dest) {
-					error!("Could not copy {:?}: {}", src, e);
-					app_state.report_error();
-				},
+		let result = match mode {
+			Mode::Move => self.move_file(&long_src, &long_dest, data),
+			Mode::Copy | Mode::Clone | Mode::Dedup => self.copy_file(mode, &long_src, &long_dest, data),
 			Mode::SymLink => {
 				// if src is absolute, we use the absolute path no matter what
 				let target = if src.is_absolute() {
@@ -704,14 +5043,13 @@ impl<'a> App<'a> {
 				} else {
 					// if src is a relative path, we need the absolute path to either use it,
 					// or determine a relative path from the link name
-					let src_absolute = std::path::absolute(src).unwrap_or_else(|_| self.cwd.join(src));
+					let src_absolute = self.absolute(src);
 					if self.args.force_absolute_symlinks {
 						src_absolute
-					} else if let Some(src_relative) = pathdiff::diff_paths(
-						&src_absolute,
-						std::path::absolute(dest).unwrap_or_else(|_| self.cwd.join(dest)).parent().unwrap(),
-					) {
-						if self.args.verbose {
+					} else if let Some(src_relative) =
+						pathdiff::diff_paths(&src_absolute, self.absolute(dest).parent().unwrap())
+					{
+						if self.args.verbose > 0 {
 							println!("# -> {:?}", src_relative);
 						}
 						src_relative
@@ -720,22 +5058,65 @@ impl<'a> App<'a> {
 					}
 				};
 
-				if let Err(e) = fs::soft_link(target, dest) {
-					// this is deprecated, but we are sure we are linking files rather than
-					// directories, so there is no need to call the os-dependent version
-					error!("Could not symlink {:?}: {}", src, e);
-					app_state.report_error();
-				}
+				// this is deprecated, but we are sure we are linking files rather than
+				// directories, so there is no need to call the os-dependent version
+				fs::soft_link(target, &long_dest).map_err(|e| format!("Could not symlink {:?}: {}", src, e))
 			}
 			Mode::HardLink =>
-				if let Err(e) = fs::hard_link(src, dest) {
-					error!("Could not hard link {:?}: {}", src, e);
-					app_state.report_error();
-				},
+				fs::hard_link(&long_src, &long_dest).map_err(|e| format!("Could not hard link {:?}: {}", src, e)),
+			Mode::Thumb => read_thumbnail(&long_src).and_then(|thumbnail| {
+				fs::write(&long_dest, thumbnail).map_err(|e| format!("Could not write thumbnail {:?}: {}", dest, e))
+			}),
+			// audit never changes the filesystem, see apply_audit
+			Mode::Audit => Ok(()),
+			Mode::Stats => {
+				app_state.stats.add(data, TYPED_KEY);
+				Ok(())
+			}
+			Mode::Dupes => match data.get(prepend!(SYS_PREFIX, "Sha1")).and_then(Value::as_str) {
+				Some(hash) => {
+					let size = data.get(TYPED_KEY).and_then(|typed| typed[prepend!(SYS_PREFIX, "Size")].as_u64());
+					app_state.dupes.add(hash, size.unwrap_or(0), src.clone());
+					Ok(())
+				}
+				None => Err(format!("No content hash available for {:?}, skipping", src)),
+			},
+			// files without an image, or whose image cannot be decoded, are left out
+			Mode::Similar => {
+				match similar::image_hash(src) {
+					Ok(Some(hash)) => app_state.similar.add(src.clone(), hash),
+					Ok(None) => {}
+					Err(e) => {
+						warn!("{}, leaving it out", e);
+						app_state.report_warning();
+					}
+				}
+				Ok(())
+			}
+			Mode::Info if self.args.diff => {
+				let mut properties = data.clone();
+				properties.retain(|key, value| self.lists_property(key, value));
+				app_state.diffed.push((src.clone(), properties));
+				Ok(())
+			}
+			Mode::Info if self.args.export => {
+				let mut export = Map::new();
+				export.insert("src".to_string(), Value::from(portable::escape(src.as_os_str())));
+				let mut properties = data.clone();
+				properties.retain(|key, value| key == TYPED_KEY || self.lists_property(key, value));
+				if let Some(Value::Object(typed)) = properties.get_mut(TYPED_KEY) {
+					typed.retain(|key, value| self.lists_property(key, value));
+				}
+				export.insert("properties".to_string(), Value::Object(properties));
+				println!("{}", Value::Object(export));
+				Ok(())
+			}
 			// if "-m info" is enabled, display the data contained in the properties table
-			Mode::Info =>
-				for (key, value) in data {
-					let value_as_str = value.as_str().expect("The data table should only contain strings");
+			Mode::Info => {
+				for (key, value) in data.iter().filter(|(key, value)| self.lists_property(key, value)) {
+					let Some(value_as_str) = value.as_str() else {
+						continue;
+					};
 					let len = value_as_str.len();
 					if self.args.max_display_len > 0 && len > self.args.max_display_len {
 						println!(
@@ -748,28 +5129,140 @@ impl<'a> App<'a> {
 					} else {
 						println!("{{{{{}}}}} \"{}\"", key, value_as_str);
 					}
-				},
+				}
+				if let Some(Value::Object(typed)) = data.get(TYPED_KEY) {
+					for (key, value) in typed.iter().filter(|(key, value)| self.lists_property(key, value)) {
+						println!("{{{{{}.{}}}}} {}", TYPED_KEY, key, value);
+					}
+				}
+				Ok(())
+			}
+		};
+
+		match result {
+			Ok(()) => {
+				match mode {
+					Mode::Move =>
+						app_state.journal.record(JournalEntry::Renamed { from: src.clone(), to: dest.clone() }),
+					Mode::Info | Mode::Audit | Mode::Stats | Mode::Dupes | Mode::Similar => {}
+					_ => app_state.journal.record(JournalEntry::Created(dest.clone())),
+				}
+				app_state.report_applied(mode);
+				true
+			}
+			Err(message) => {
+				app_state.report_error_message(message);
+				false
+			}
 		}
 	}
 }
 
-fn main() -> ExitCode {
-	// Hardcoded log4rs to avoid loading a config file
+// The end of run summary is logged separately, so that it is shown even in
+// --quiet mode
+const SUMMARY_TARGET: &str = "exif_namer::summary";
+
+// Errors only with --quiet, more detail with each -v
+fn log_level(quiet: bool, verbose: u8) -> LevelFilter {
+	match (quiet, verbose) {
+		(true, _) => LevelFilter::Error,
+		(false, 0 | 1) => LevelFilter::Info,
+		(false, 2) => LevelFilter::Debug,
+		(false, _) => LevelFilter::Trace,
+	}
+}
+
+// Unless a log4rs configuration file is given, log to stderr and optionally to
+// a file
+fn init_logging(log_config: Option<&Path>, log_file: Option<&Path>, level: LevelFilter) -> Result<(), String> {
 	use log4rs::config::*;
-	let log_appender = Appender::builder()
-		.build("stderr".to_string(), Box::new(ConsoleAppender::builder().target(Target::Stderr).build()));
-	let log_root = Root::builder().appender("stderr".to_string()).build(LevelFilter::Info);
-	let log_config = Config::builder().appender(log_appender).build(log_root);
-	init_config(log_config.expect("Invalid log configuration")).expect("Unable to initialize log4rs");
+	if let Some(path) = log_config {
+		return log4rs::init_file(path, Default::default())
+			.map_err(|e| format!("Unable to load log configuration {:?}: {}", path, e));
+	}
+	let mut log_config = Config::builder().appender(
+		Appender::builder()
+			.build("stderr".to_string(), Box::new(ConsoleAppender::builder().target(Target::Stderr).build())),
+	);
+	let mut log_root = Root::builder().appender("stderr".to_string());
+	if let Some(path) = log_file {
+		let file_appender =
+			FileAppender::builder().build(path).map_err(|e| format!("Unable to open log file {:?}: {}", path, e))?;
+		log_config = log_config.appender(Appender::builder().build("file".to_string(), Box::new(file_appender)));
+		log_root = log_root.appender("file".to_string());
+	}
+	let log_config = log_config
+		.logger(Logger::builder().build(SUMMARY_TARGET, LevelFilter::Info.max(level)))
+		.build(log_root.build(level))
+		.map_err(|e| format!("Invalid log configuration: {}", e))?;
+	init_config(log_config).map(|_| ()).map_err(|e| format!("Unable to initialize log4rs: {}", e))
+}
+
+fn run_undo(undo_args: &UndoArgs) -> ExitCode {
+	if let Err(e) = init_logging(None, None, LevelFilter::Info) {
+		eprintln!("{}", e);
+		return ExitCode::FAILURE;
+	}
+	match undo::undo(&undo_args.log, undo_args.dry_run) {
+		Ok((reverted, failed)) => {
+			let dry_run = if undo_args.dry_run { " (dry run)" } else { "" };
+			info!(target: SUMMARY_TARGET, "{} reverted, {} failed{}", reverted, failed, dry_run);
+			if failed > 0 {
+				ExitCode::FAILURE
+			} else {
+				ExitCode::SUCCESS
+			}
+		}
+		Err(e) => {
+			error!("{}", e);
+			ExitCode::FAILURE
+		}
+	}
+}
+
+fn run_demo() -> ExitCode {
+	if let Err(e) = init_logging(None, None, LevelFilter::Info) {
+		eprintln!("{}", e);
+		return ExitCode::FAILURE;
+	}
+	demo::run_demo()
+}
+
+fn main() -> ExitCode {
+	let args = match Cli::try_parse_action(std::env::args_os()).unwrap_or_else(|e| e.exit()) {
+		Action::Run(args) => *args,
+		Action::Undo(undo_args) => return run_undo(&undo_args),
+		Action::Demo => return run_demo(),
+	};
+	if let Err(e) =
+		init_logging(args.log_config.as_deref(), args.log_file.as_deref(), log_level(args.quiet, args.verbose))
+	{
+		eprintln!("{}", e);
+		return ExitCode::FAILURE;
+	}
 
 	// Run the app
-	let app = App::new(Args::parse()).expect("Invalid arguments");
+	let app = App::new(args).expect("Invalid arguments");
 	let report = app.run();
 
 	// Report run status
+	if report.access_denied_count() > 0 {
+		warn!(target: SUMMARY_TARGET, "Access denied to {} file(s) or folder(s), including:", report.access_denied_count());
+		for path in report.access_denied_examples() {
+			warn!(target: SUMMARY_TARGET, "    {:?}", path);
+		}
+	}
+	let summary = report.summary();
+	if !summary.is_empty() {
+		info!(target: SUMMARY_TARGET, "{}{}", summary, if app.args.dry_run { " (dry run)" } else { "" });
+	}
 	if report.has_errors_or_warnings() {
-		warn!("{} error(s), {} warning(s)", report.error_count(), report.warning_count());
+		warn!(target: SUMMARY_TARGET, "{} error(s), {} warning(s)", report.error_count(), report.warning_count());
+	}
+	if report.error_count() > 0 {
 		ExitCode::FAILURE
+	} else if report.warning_count() > 0 {
+		ExitCode::from(EXIT_WARNINGS)
 	} else {
 		ExitCode::SUCCESS
 	}
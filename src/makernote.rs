@@ -0,0 +1,134 @@
+// MakerNote is a vendor specific block of metadata, mostly an IFD in the same
+// format as the rest of the TIFF structure, but with its own header and
+// conventions for offsets. Only the few tags which identify the camera body are
+// decoded here; Sony encrypts them, so they are not available for Sony cameras.
+// Lens serial numbers are recorded in the standard LensSerialNumber tag by most
+// recent cameras, and in model specific structures otherwise
+
+// Values found in the MakerNote of the camera which took the picture
+#[derive(Default, Debug)]
+pub struct MakerNote {
+	pub serial_number: Option<String>,
+	pub shutter_count: Option<u32>,
+}
+
+struct Tiff<'a> {
+	data: &'a [u8],
+	little_endian: bool,
+	// value offsets are relative to this position in data
+	base: usize,
+}
+
+impl<'a> Tiff<'a> {
+	fn u16(&self, at: usize) -> Option<u16> {
+		let bytes: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+		Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+	}
+
+	fn u32(&self, at: usize) -> Option<u32> {
+		let bytes: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+		Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+	}
+
+	// Entries of the IFD at the given position in data, as tag, type, count and
+	// position of the value
+	fn entries(&self, ifd: usize) -> Vec<(u16, u16, usize, usize)> {
+		let count = self.u16(ifd).unwrap_or(0) as usize;
+		(0..count)
+			.filter_map(|i| {
+				let entry = ifd + 2 + i * 12;
+				let (tag, kind, count) = (self.u16(entry)?, self.u16(entry + 2)?, self.u32(entry + 4)? as usize);
+				let size = match kind {
+					1 | 2 | 6 | 7 => 1,
+					3 | 8 => 2,
+					4 | 9 | 11 => 4,
+					_ => 8,
+				} * count;
+				let value = if size <= 4 { entry + 8 } else { self.base + self.u32(entry + 8)? as usize };
+				Some((tag, kind, count, value))
+			})
+			.collect()
+	}
+
+	fn ascii(&self, value: usize, count: usize) -> Option<String> {
+		let bytes = self.data.get(value..value + count)?;
+		let text = String::from_utf8_lossy(bytes.split(|&b| b == 0).next()?).trim().to_string();
+		(!text.is_empty()).then_some(text)
+	}
+
+	fn uint(&self, kind: u16, value: usize) -> Option<u32> {
+		match kind {
+			3 => self.u16(value).map(u32::from),
+			4 => self.u32(value),
+			_ => None,
+		}
+	}
+
+	fn find(&self, ifd: usize, wanted: u16) -> Option<(u16, usize, usize)> {
+		self.entries(ifd)
+			.into_iter()
+			.find(|(tag, ..)| *tag == wanted)
+			.map(|(_, kind, count, value)| (kind, count, value))
+	}
+
+	fn find_ascii(&self, ifd: usize, wanted: u16) -> Option<String> {
+		self.find(ifd, wanted).filter(|(kind, ..)| *kind == 2).and_then(|(_, count, value)| self.ascii(value, count))
+	}
+
+	fn find_uint(&self, ifd: usize, wanted: u16) -> Option<u32> {
+		self.find(ifd, wanted).and_then(|(kind, _, value)| self.uint(kind, value))
+	}
+}
+
+// Nikon type 3: a header followed by a complete TIFF structure, with offsets
+// relative to its own header
+fn decode_nikon(note: &[u8]) -> Option<MakerNote> {
+	if !note.starts_with(b"Nikon\0") {
+		return None;
+	}
+	let little_endian = match note.get(10..12)? {
+		b"II" => true,
+		b"MM" => false,
+		_ => return None,
+	};
+	let tiff = Tiff { data: note, little_endian, base: 10 };
+	let ifd = 10 + tiff.u32(14)? as usize;
+	Some(MakerNote { serial_number: tiff.find_ascii(ifd, 0x001d), shutter_count: tiff.find_uint(ifd, 0x00a7) })
+}
+
+// Canon: a bare IFD, with offsets relative to the enclosing TIFF structure
+fn decode_canon(tiff: &Tiff, offset: usize) -> MakerNote {
+	let serial_number = tiff
+		.find_uint(offset, 0x000c)
+		.filter(|&serial| serial > 0)
+		.map(|serial| serial.to_string())
+		.or_else(|| tiff.find_ascii(offset, 0x0096));
+	MakerNote { serial_number, shutter_count: None }
+}
+
+// Fujifilm: a header with the offset of the IFD, always little endian and with
+// offsets relative to the header
+fn decode_fujifilm(note: &[u8]) -> Option<MakerNote> {
+	if !note.starts_with(b"FUJIFILM") {
+		return None;
+	}
+	let tiff = Tiff { data: note, little_endian: true, base: 0 };
+	let ifd = tiff.u32(8)? as usize;
+	Some(MakerNote { serial_number: tiff.find_ascii(ifd, 0x0010), shutter_count: tiff.find_uint(ifd, 0x1438) })
+}
+
+// The layout depends on the vendor, as recorded in the Make tag. The MakerNote
+// is located at the given offset within the TIFF structure of the Exif data
+pub fn decode(make: &str, tiff: &[u8], little_endian: bool, offset: usize, len: usize) -> Option<MakerNote> {
+	let note = tiff.get(offset..offset + len)?;
+	let make = make.to_ascii_uppercase();
+	if make.starts_with("NIKON") {
+		decode_nikon(note)
+	} else if make.starts_with("CANON") {
+		Some(decode_canon(&Tiff { data: tiff, little_endian, base: 0 }, offset))
+	} else if make.starts_with("FUJIFILM") {
+		decode_fujifilm(note)
+	} else {
+		None
+	}
+}
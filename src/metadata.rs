@@ -0,0 +1,258 @@
+use crate::Strip;
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Tag, Value};
+use std::io::Cursor;
+
+const EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+// the main packet, and the continuation of packets larger than a segment
+const XMP_SIGNATURES: [&[u8]; 2] = [b"http://ns.adobe.com/xap/1.0/\0", b"http://ns.adobe.com/xmp/extension/\0"];
+
+// Serial numbers, and the proprietary maker notes, where most cameras record
+// the serial number again
+const SERIAL_TAGS: [Tag; 4] = [Tag::BodySerialNumber, Tag::LensSerialNumber, Tag::ImageUniqueID, Tag::MakerNote];
+
+// TIFF tag 269, not among the tags named by the exif crate
+const DOCUMENT_NAME: Tag = Tag(Context::Tiff, 0x010d);
+
+const SOI: u8 = 0xd8;
+const EOI: u8 = 0xd9;
+const SOS: u8 = 0xda;
+const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+
+// A segment before the image data, with its marker, its payload and its bytes
+// as a whole
+struct Segment<'a> {
+	marker: u8,
+	payload: &'a [u8],
+	bytes: &'a [u8],
+}
+
+// The segments of a JPEG file, and the image data which follows them up to the
+// end of the file
+fn segments(content: &[u8]) -> Result<(Vec<Segment<'_>>, &[u8]), String> {
+	if content.get(..2) != Some(&[0xff, SOI]) {
+		return Err("not a JPEG file".to_string());
+	}
+	let mut segments = Vec::new();
+	let mut pos = 2;
+	while pos < content.len() {
+		let (Some(0xff), Some(&marker)) = (content.get(pos), content.get(pos + 1)) else {
+			return Err(format!("invalid JPEG segment at offset {}", pos));
+		};
+		// fill bytes
+		if marker == 0xff {
+			pos += 1;
+			continue;
+		}
+		if marker == SOS || marker == EOI {
+			return Ok((segments, &content[pos..]));
+		}
+		let len = content
+			.get(pos + 2..pos + 4)
+			.map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+			.filter(|len| *len >= 2 && pos + 2 + len <= content.len())
+			.ok_or_else(|| format!("truncated JPEG segment at offset {}", pos))?;
+		let end = pos + 2 + len;
+		segments.push(Segment { marker, payload: &content[pos + 4..end], bytes: &content[pos..end] });
+		pos = end;
+	}
+	Err("no image data found".to_string())
+}
+
+fn is_exif(segment: &Segment) -> bool { segment.marker == APP1 && segment.payload.starts_with(EXIF_SIGNATURE) }
+
+fn read_exif(segment: &Segment) -> Result<exif::Exif, String> {
+	exif::Reader::new()
+		.read_raw(segment.payload[EXIF_SIGNATURE.len()..].to_vec())
+		.map_err(|e| format!("invalid Exif data: {}", e))
+}
+
+// The fields encoded again as the TIFF structure of an Exif segment, with the
+// thumbnail of the original, if any
+fn encode_exif<'a>(
+	exif: Option<&exif::Exif>,
+	fields: impl IntoIterator<Item = &'a Field>,
+	little_endian: bool,
+) -> Result<Vec<u8>, String> {
+	let mut writer = Writer::new();
+	for field in fields {
+		writer.push_field(field);
+	}
+	let thumbnail = exif.and_then(|exif| {
+		let thumbnail_field =
+			|tag| exif.get_field(tag, In::THUMBNAIL).and_then(|f| f.value.get_uint(0)).map(|n| n as usize);
+		let offset = thumbnail_field(Tag::JPEGInterchangeFormat)?;
+		let len = thumbnail_field(Tag::JPEGInterchangeFormatLength)?;
+		exif.buf().get(offset..offset.saturating_add(len))
+	});
+	if let Some(thumbnail) = thumbnail {
+		writer.set_jpeg(thumbnail, In::THUMBNAIL);
+	}
+	let mut encoded = Cursor::new(Vec::new());
+	writer.write(&mut encoded, little_endian).map_err(|e| format!("unable to encode Exif data: {}", e))?;
+	Ok(encoded.into_inner())
+}
+
+fn is_stripped(field: &Field, strip: &[Strip]) -> bool {
+	(strip.contains(&Strip::Gps) && field.tag.context() == Context::Gps)
+		|| (strip.contains(&Strip::Serial) && SERIAL_TAGS.contains(&field.tag))
+		// the writer can only encode values of known types
+		|| matches!(field.value, exif::Value::Unknown(..))
+}
+
+// The TIFF structure of an Exif segment encoded again without the stripped
+// fields, keeping the thumbnail. None if no field is left
+fn strip_exif(segment: &Segment, strip: &[Strip]) -> Result<Option<Vec<u8>>, String> {
+	let exif = read_exif(segment)?;
+	let fields = exif.fields().filter(|field| !is_stripped(field, strip)).collect::<Vec<_>>();
+	if fields.iter().all(|field| field.ifd_num != In::PRIMARY) {
+		return Ok(None);
+	}
+	encode_exif(Some(&exif), fields, exif.little_endian()).map(Some)
+}
+
+// Whether an XMP packet may hold what is being stripped. XMP is dropped as a
+// whole, rather than edited
+fn is_xmp_stripped(packet: &[u8], strip: &[Strip]) -> bool {
+	let contains = |needle: &[u8]| packet.windows(needle.len()).any(|window| window == needle);
+	strip.contains(&Strip::AllExif)
+		|| (strip.contains(&Strip::Gps) && contains(b"GPS"))
+		|| (strip.contains(&Strip::Serial) && contains(b"SerialNumber"))
+}
+
+fn segment(marker: u8, payload: &[u8]) -> Result<Vec<u8>, String> {
+	let len = u16::try_from(payload.len() + 2).map_err(|_| "metadata too large for a JPEG segment".to_string())?;
+	let mut segment = vec![0xff, marker];
+	segment.extend_from_slice(&len.to_be_bytes());
+	segment.extend_from_slice(payload);
+	Ok(segment)
+}
+
+// A copy of a JPEG file without the metadata to strip. The image data is left
+// as it is
+pub fn strip_jpeg(content: &[u8], strip: &[Strip]) -> Result<Vec<u8>, String> {
+	let (segments, image) = segments(content).map_err(|e| format!("unable to strip metadata: {}", e))?;
+	let mut stripped = content[..2].to_vec();
+	for segment in segments {
+		if is_exif(&segment) {
+			let tiff = if strip.contains(&Strip::AllExif) { None } else { strip_exif(&segment, strip)? };
+			if let Some(tiff) = tiff {
+				stripped.extend(self::segment(APP1, &[EXIF_SIGNATURE, &tiff].concat())?);
+			}
+		} else if segment.marker == APP1
+			&& XMP_SIGNATURES.iter().any(|signature| segment.payload.starts_with(signature))
+		{
+			if !is_xmp_stripped(segment.payload, strip) {
+				stripped.extend_from_slice(segment.bytes);
+			}
+		} else {
+			stripped.extend_from_slice(segment.bytes);
+		}
+	}
+	stripped.extend_from_slice(image);
+	Ok(stripped)
+}
+
+// The TIFF structure of an Exif segment with a DocumentName entry added to
+// IFD0. Rather than encoding the fields again, which would lose those of
+// unknown types and break maker notes pointing into the data, a new IFD0 is
+// appended and the original one is left unreferenced
+fn insert_document_name(tiff: &[u8], name: &str) -> Result<Vec<u8>, String> {
+	let little_endian = match tiff.get(..2) {
+		Some(b"II") => true,
+		Some(b"MM") => false,
+		_ => return Err("invalid Exif data: unknown byte order".to_string()),
+	};
+	let read_u16 =
+		|b: &[u8]| if little_endian { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) };
+	let u16_bytes = |n: u16| if little_endian { n.to_le_bytes() } else { n.to_be_bytes() };
+	let u32_bytes = |n: u32| if little_endian { n.to_le_bytes() } else { n.to_be_bytes() };
+	let truncated = || "invalid Exif data: truncated IFD0".to_string();
+	let too_large = |_| "Exif data too large".to_string();
+
+	let ifd = tiff.get(4..8).map(|b| [b[0], b[1], b[2], b[3]]).ok_or_else(truncated)?;
+	let ifd = if little_endian { u32::from_le_bytes(ifd) } else { u32::from_be_bytes(ifd) } as usize;
+	let count = tiff.get(ifd..ifd + 2).map(read_u16).ok_or_else(truncated)? as usize;
+	let entries = tiff.get(ifd + 2..ifd + 2 + 12 * count).ok_or_else(truncated)?;
+	let next_ifd = tiff.get(ifd + 2 + 12 * count..ifd + 6 + 12 * count).ok_or_else(truncated)?;
+
+	let mut value = name.as_bytes().to_vec();
+	value.push(0);
+	let mut updated = tiff.to_vec();
+	// IFDs start on a word boundary
+	if updated.len() % 2 == 1 {
+		updated.push(0);
+	}
+	let new_ifd = updated.len();
+	let mut entry = [u16_bytes(DOCUMENT_NAME.number()), u16_bytes(2)].concat();
+	entry.extend(u32_bytes(u32::try_from(value.len()).map_err(too_large)?));
+	if value.len() <= 4 {
+		let mut inline = value.clone();
+		inline.resize(4, 0);
+		entry.extend(inline);
+	} else {
+		// the value follows the new IFD0
+		let value_offset = new_ifd + 2 + 12 * (count + 1) + 4;
+		entry.extend(u32_bytes(u32::try_from(value_offset).map_err(too_large)?));
+	}
+	updated.extend(u16_bytes(u16::try_from(count + 1).map_err(too_large)?));
+	// entries are sorted by tag
+	let mut pending = Some(entry);
+	for existing in entries.chunks(12) {
+		if read_u16(existing) > DOCUMENT_NAME.number() {
+			if let Some(entry) = pending.take() {
+				updated.extend(entry);
+			}
+		}
+		updated.extend_from_slice(existing);
+	}
+	updated.extend(pending.unwrap_or_default());
+	updated.extend_from_slice(next_ifd);
+	if value.len() > 4 {
+		updated.extend(value);
+	}
+	updated[4..8].copy_from_slice(&u32_bytes(u32::try_from(new_ifd).map_err(too_large)?));
+	Ok(updated)
+}
+
+// A copy of a JPEG file with the name recorded in the DocumentName field of
+// the Exif data, which is added if missing. None if the file already has a
+// DocumentName, so that the first name recorded is kept
+pub fn record_document_name(content: &[u8], name: &str) -> Result<Option<Vec<u8>>, String> {
+	let (segments, image) = segments(content)?;
+	let exif = segments.iter().find(|segment| is_exif(segment));
+	let tiff = match exif {
+		Some(exif) => {
+			if read_exif(exif)?.get_field(DOCUMENT_NAME, In::PRIMARY).is_some() {
+				return Ok(None);
+			}
+			insert_document_name(&exif.payload[EXIF_SIGNATURE.len()..], name)?
+		}
+		None => {
+			let document_name =
+				Field { tag: DOCUMENT_NAME, ifd_num: In::PRIMARY, value: Value::Ascii(vec![name.as_bytes().to_vec()]) };
+			encode_exif(None, [&document_name], false)?
+		}
+	};
+	let exif_segment = segment(APP1, &[EXIF_SIGNATURE, &tiff].concat())?;
+	let mut recorded = content[..2].to_vec();
+	// a new Exif segment goes first, after the JFIF header if any
+	let insert_at = match exif {
+		Some(_) => segments.iter().position(is_exif),
+		None => Some(segments.iter().take_while(|segment| segment.marker == APP0).count()),
+	};
+	for (n, segment) in segments.iter().enumerate() {
+		if Some(n) == insert_at {
+			recorded.extend_from_slice(&exif_segment);
+		}
+		if !is_exif(segment) {
+			recorded.extend_from_slice(segment.bytes);
+		}
+	}
+	if insert_at == Some(segments.len()) {
+		recorded.extend_from_slice(&exif_segment);
+	}
+	recorded.extend_from_slice(image);
+	Ok(Some(recorded))
+}
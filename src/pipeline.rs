@@ -0,0 +1,59 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// The results of reading each file, in the order given, computed on worker
+// threads ahead of the file being processed. Workers stop once this is dropped
+pub struct ReadAhead<T> {
+	results: VecDeque<Receiver<T>>,
+	// a worker reads a file for each permit, so that memory is bounded
+	permits: SyncSender<()>,
+}
+
+pub fn read_ahead<I, T, F>(jobs: usize, files: Vec<I>, read: F) -> ReadAhead<T>
+where
+	I: Send + 'static,
+	T: Send + 'static,
+	F: Fn(I) -> T + Send + Sync + 'static, {
+	// at least one permit for each worker, so that all find the queue empty
+	let ahead = jobs * 2;
+	let (permits, permit_receiver) = mpsc::sync_channel(ahead);
+	for _ in 0..ahead {
+		let _ = permits.try_send(());
+	}
+	let mut results = VecDeque::new();
+	let mut queue = Vec::new();
+	for file in files {
+		let (sender, receiver) = mpsc::channel();
+		results.push_back(receiver);
+		queue.push((file, sender));
+	}
+	let queue = Arc::new(Mutex::new(queue.into_iter()));
+	let permit_receiver = Arc::new(Mutex::new(permit_receiver));
+	let read = Arc::new(read);
+	for _ in 0..jobs {
+		let (queue, permit_receiver, read) = (queue.clone(), permit_receiver.clone(), read.clone());
+		thread::spawn(move || {
+			while permit_receiver.lock().is_ok_and(|permits| permits.recv().is_ok()) {
+				let Some((file, sender)) = queue.lock().ok().and_then(|mut queue| queue.next()) else {
+					break;
+				};
+				// the result is dropped if processing stopped early
+				let _ = sender.send(read(file));
+			}
+		});
+	}
+	ReadAhead { results, permits }
+}
+
+impl<T> Iterator for ReadAhead<T> {
+	type Item = T;
+
+	// None once all files are read, or if a worker failed
+	fn next(&mut self) -> Option<T> {
+		let result = self.results.pop_front()?.recv().ok()?;
+		let _ = self.permits.try_send(());
+		Some(result)
+	}
+}
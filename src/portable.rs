@@ -0,0 +1,153 @@
+use sha1::{Digest, Sha1};
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+
+// Names Windows reserves for devices, regardless of case and extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+	"CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+	"LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Valid in names on other systems, but not on Windows, NTFS or exFAT
+const WINDOWS_INVALID_CHARACTERS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+fn windows_safe_name(name: &str, replacement: &str) -> String {
+	let name = name.replace(WINDOWS_INVALID_CHARACTERS, replacement);
+	// trailing dots and spaces are silently dropped by Windows
+	let trimmed = name.trim_end_matches(['.', ' ']);
+	let name = if trimmed.is_empty() { replacement.to_string() } else { trimmed.to_string() };
+	let stem = name.split('.').next().unwrap_or_default().trim_end();
+	if WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+		format!("{}{}{}", stem, replacement, &name[stem.len()..])
+	} else {
+		name
+	}
+}
+
+// Each name in the path made valid on Windows. The prefix and root, e.g. C:\,
+// are left alone
+pub fn windows_safe(path: &Path, replacement: &str) -> PathBuf {
+	path.components()
+		.map(|component| match component {
+			Component::Normal(name) => PathBuf::from(windows_safe_name(&name.to_string_lossy(), replacement)),
+			other => PathBuf::from(other.as_os_str()),
+		})
+		.collect()
+}
+
+// At most max_len bytes, keeping the extension, optionally followed by a short
+// hash of the whole name so that names truncated to the same text stay apart
+pub fn truncate_name(name: &str, max_len: usize, with_hash: bool) -> String {
+	if name.len() <= max_len {
+		return name.to_string();
+	}
+	let (stem, ext) = match name.rfind('.') {
+		Some(i) if i > 0 => name.split_at(i),
+		_ => (name, ""),
+	};
+	let suffix = if with_hash { format!("~{:.6}", hex::encode(Sha1::digest(name.as_bytes()))) } else { String::new() };
+	let mut end = max_len.saturating_sub(ext.len() + suffix.len()).min(stem.len());
+	while !stem.is_char_boundary(end) {
+		end -= 1;
+	}
+	format!("{}{}{}", &stem[..end], suffix, ext)
+}
+
+// Each name in the path truncated to at most max_len bytes
+pub fn truncate_names(path: &Path, max_len: usize, with_hash: bool) -> PathBuf {
+	if path.components().all(|component| component.as_os_str().len() <= max_len) {
+		return path.to_path_buf();
+	}
+	path.components()
+		.map(|component| match component {
+			Component::Normal(name) if name.len() > max_len =>
+				unescape(&truncate_name(&escape(name), max_len, with_hash)),
+			other => PathBuf::from(other.as_os_str()),
+		})
+		.collect()
+}
+
+fn swap_case(name: &str) -> String {
+	name.chars()
+		.flat_map(|c| if c.is_lowercase() { c.to_uppercase().collect::<Vec<_>>() } else { c.to_lowercase().collect() })
+		.collect()
+}
+
+// Whether the filesystem of the folder ignores case, found by looking up one of
+// its entries with the case of its name swapped. Unknown for folders without
+// entries whose name has a case
+pub fn is_case_insensitive(dir: &Path) -> Option<bool> {
+	let entry = std::fs::read_dir(dir).ok()?.filter_map(Result::ok).find(|entry| {
+		let name = entry.file_name().to_string_lossy().into_owned();
+		swap_case(&name) != name
+	})?;
+	let swapped = dir.join(swap_case(&entry.file_name().to_string_lossy()));
+	Some(same_file::is_same_file(entry.path(), swapped).unwrap_or(false))
+}
+
+// Paths longer than MAX_PATH need the extended-length prefix on Windows, e.g.
+// \\?\C:\photos\... or \\?\UNC\server\share\... The standard library adds it
+// by itself, but not all the crates used to copy files do
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+	const MAX_PATH: usize = 260;
+	let Ok(absolute) = std::path::absolute(path) else {
+		return path.to_path_buf();
+	};
+	let text = absolute.to_string_lossy();
+	if text.len() < MAX_PATH || text.starts_with(r"\\?\") {
+		return path.to_path_buf();
+	}
+	match text.strip_prefix(r"\\") {
+		Some(unc) => PathBuf::from(format!(r"\\?\UNC\{}", unc)),
+		None => PathBuf::from(format!(r"\\?\{}", text)),
+	}
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf { path.to_path_buf() }
+
+// Bytes of a path which are not valid UTF-8 go through templates as characters
+// from the end of the private use area, one for each byte, and are turned back
+// into the same bytes in the rendered destination
+const ESCAPED_BYTES: u32 = 0x10ff00;
+
+pub fn escape(name: &OsStr) -> String {
+	if let Some(text) = name.to_str() {
+		return text.to_string();
+	}
+	let mut escaped = String::new();
+	for chunk in name.as_encoded_bytes().utf8_chunks() {
+		escaped.push_str(chunk.valid());
+		escaped.extend(chunk.invalid().iter().filter_map(|b| char::from_u32(ESCAPED_BYTES + *b as u32)));
+	}
+	escaped
+}
+
+fn escaped_byte(c: char) -> Option<u8> { (c as u32).checked_sub(ESCAPED_BYTES).and_then(|b| u8::try_from(b).ok()) }
+
+#[cfg(unix)]
+pub fn unescape(text: &str) -> PathBuf {
+	use std::os::unix::ffi::OsStringExt;
+	if !text.chars().any(|c| escaped_byte(c).is_some()) {
+		return PathBuf::from(text);
+	}
+	let mut bytes = Vec::with_capacity(text.len());
+	for c in text.chars() {
+		match escaped_byte(c) {
+			Some(b) => bytes.push(b),
+			None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+		}
+	}
+	PathBuf::from(OsString::from_vec(bytes))
+}
+
+// Names are UTF-16 elsewhere, any byte left over is replaced
+#[cfg(not(unix))]
+pub fn unescape(text: &str) -> PathBuf {
+	PathBuf::from(
+		text.chars()
+			.map(|c| if escaped_byte(c).is_some() { char::REPLACEMENT_CHARACTER } else { c })
+			.collect::<String>(),
+	)
+}
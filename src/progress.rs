@@ -0,0 +1,82 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PROGRESS_TEMPLATE: &str =
+	"{elapsed_precise} [{wide_bar}] {msg} files, {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}, ETA {eta})";
+
+// Progress through all the matched files, on stderr. Each file counts for its
+// size once when hashed, if hashing is enabled, and once when applied, so that
+// throughput and ETA are measured in bytes rather than in files, which can have
+// very different sizes
+pub struct Progress {
+	bar: ProgressBar,
+	hashing: bool,
+	files_done: Cell<usize>,
+	files_total: Cell<usize>,
+}
+
+impl Progress {
+	pub fn new(enabled: bool, hashing: bool) -> Self {
+		let bar = if enabled {
+			ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::stderr())
+		} else {
+			ProgressBar::hidden()
+		};
+		bar.set_style(ProgressStyle::with_template(PROGRESS_TEMPLATE).expect("Invalid progress template"));
+		Progress { bar, hashing, files_done: Cell::new(0), files_total: Cell::new(0) }
+	}
+
+	fn passes(&self) -> u64 {
+		if self.hashing {
+			2
+		} else {
+			1
+		}
+	}
+
+	// sizes are not needed, and not even looked up, if the progress is not shown
+	pub fn size(&self, path: &Path) -> u64 {
+		if self.bar.is_hidden() {
+			0
+		} else {
+			fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+		}
+	}
+
+	fn update_message(&self) { self.bar.set_message(format!("{}/{}", self.files_done.get(), self.files_total.get())); }
+
+	// New files are only known one glob at a time, the total grows accordingly
+	pub fn add_files(&self, paths: &[PathBuf]) {
+		let bytes: u64 = paths.iter().map(|path| self.size(path)).sum();
+		self.files_total.set(self.files_total.get() + paths.len());
+		self.bar.inc_length(bytes * self.passes());
+		self.update_message();
+	}
+
+	pub fn hashed(&self, path: &Path) {
+		if self.hashing {
+			self.bar.inc(self.size(path));
+		}
+	}
+
+	// The size must be known before the file is moved away
+	pub fn applied(&self, size: u64) {
+		self.files_done.set(self.files_done.get() + 1);
+		self.bar.inc(size);
+		self.update_message();
+	}
+
+	// Files skipped while planning are never applied, so at the end of each glob
+	// the progress is brought up to date with the total
+	pub fn catch_up(&self) {
+		self.files_done.set(self.files_total.get());
+		if let Some(length) = self.bar.length() {
+			self.bar.set_position(length);
+		}
+		self.update_message();
+	}
+
+	pub fn finish(&self) { self.bar.finish(); }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+const S3_SCHEME: &str = "s3://";
+const DAV_SCHEME: &str = "dav://";
+const DAVS_SCHEME: &str = "davs://";
+
+// A destination on object storage or on a WebDAV server, written to directly
+// rather than through a local copy
+#[cfg_attr(not(feature = "remote"), allow(dead_code))]
+pub enum Remote {
+	S3 { bucket: String, key: String },
+	// base is the scheme and host of the server, path starts with /
+	Dav { base: String, path: String },
+}
+
+pub fn is_remote(dest: &Path) -> bool {
+	let dest = dest.to_string_lossy();
+	[S3_SCHEME, DAV_SCHEME, DAVS_SCHEME].iter().any(|scheme| dest.starts_with(scheme))
+}
+
+impl Remote {
+	// e.g. s3://bucket/2024/IMG_0001.jpg, dav://host/photos/IMG_0001.jpg or
+	// davs:// for https
+	pub fn parse(dest: &Path) -> Result<Self, String> {
+		let dest = dest.to_str().ok_or_else(|| "not a valid URL".to_string())?;
+		let split = |rest: &str| match rest.split_once('/') {
+			Some((host, path)) if !host.is_empty() && !path.is_empty() => Ok((host.to_string(), format!("/{}", path))),
+			_ => Err(format!("{} has no host and path", dest)),
+		};
+		if let Some(rest) = dest.strip_prefix(S3_SCHEME) {
+			let (bucket, key) = split(rest)?;
+			Ok(Remote::S3 { bucket, key: key[1..].to_string() })
+		} else if let Some(rest) = dest.strip_prefix(DAV_SCHEME) {
+			let (host, path) = split(rest)?;
+			Ok(Remote::Dav { base: format!("http://{}", host), path })
+		} else if let Some(rest) = dest.strip_prefix(DAVS_SCHEME) {
+			let (host, path) = split(rest)?;
+			Ok(Remote::Dav { base: format!("https://{}", host), path })
+		} else {
+			Err(format!("{} is not a remote destination", dest))
+		}
+	}
+}
+
+#[cfg(not(feature = "remote"))]
+impl Remote {
+	pub fn exists(&self) -> Result<bool, String> { Err(UNSUPPORTED.to_string()) }
+
+	pub fn upload(&self, _body: impl io::Read, _len: u64, _collections: &mut HashSet<String>) -> Result<(), String> {
+		Err(UNSUPPORTED.to_string())
+	}
+}
+
+#[cfg(not(feature = "remote"))]
+const UNSUPPORTED: &str = "built without support for remote destinations";
+
+#[cfg(feature = "remote")]
+use {
+	base64::Engine as _,
+	hmac::{Hmac, Mac},
+	sha2::{Digest, Sha256},
+};
+
+#[cfg(feature = "remote")]
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+// Everything but unreserved characters and separators, as required by the
+// canonical requests of S3 signatures
+#[cfg(feature = "remote")]
+fn encode_path(path: &str) -> String {
+	path.bytes()
+		.map(|b| {
+			if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+				(b as char).to_string()
+			} else {
+				format!("%{:02X}", b)
+			}
+		})
+		.collect()
+}
+
+#[cfg(feature = "remote")]
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+	let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+	mac.update(data.as_bytes());
+	mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(feature = "remote")]
+fn env(name: &str) -> Option<String> { std::env::var(name).ok().filter(|value| !value.is_empty()) }
+
+// Credentials and region from the usual AWS environment variables. With
+// AWS_ENDPOINT_URL, e.g. for MinIO, buckets are addressed by path
+#[cfg(feature = "remote")]
+fn s3_request(method: &str, bucket: &str, key: &str) -> Result<ureq::Request, String> {
+	let access_key = env("AWS_ACCESS_KEY_ID").ok_or_else(|| "AWS_ACCESS_KEY_ID is not set".to_string())?;
+	let secret_key = env("AWS_SECRET_ACCESS_KEY").ok_or_else(|| "AWS_SECRET_ACCESS_KEY is not set".to_string())?;
+	let region = env("AWS_REGION").or_else(|| env("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".to_string());
+	let (base, uri) = match env("AWS_ENDPOINT_URL") {
+		Some(endpoint) => (endpoint.trim_end_matches('/').to_string(), format!("/{}/{}", bucket, encode_path(key))),
+		None => (format!("https://{}.s3.{}.amazonaws.com", bucket, region), format!("/{}", encode_path(key))),
+	};
+	let host = base.split_once("://").map_or(base.as_str(), |(_, host)| host).to_string();
+	let now = chrono::Utc::now();
+	let date = now.format("%Y%m%d").to_string();
+	// sorted by name, as signed
+	let mut headers = vec![
+		("host", host),
+		("x-amz-content-sha256", UNSIGNED_PAYLOAD.to_string()),
+		("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+	];
+	if let Some(token) = env("AWS_SESSION_TOKEN") {
+		headers.push(("x-amz-security-token", token));
+	}
+	let canonical_headers = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect::<String>();
+	let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+	let canonical_request =
+		format!("{}\n{}\n\n{}\n{}\n{}", method, uri, canonical_headers, signed_headers, UNSIGNED_PAYLOAD);
+	let scope = format!("{}/{}/s3/aws4_request", date, region);
+	let string_to_sign = format!(
+		"AWS4-HMAC-SHA256\n{}\n{}\n{}",
+		headers[2].1,
+		scope,
+		hex::encode(Sha256::digest(canonical_request.as_bytes()))
+	);
+	let signing_key = [date.as_str(), &region, "s3", "aws4_request"]
+		.iter()
+		.fold(format!("AWS4{}", secret_key).into_bytes(), |key, part| hmac(&key, part));
+	let authorization = format!(
+		"AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+		access_key,
+		scope,
+		signed_headers,
+		hex::encode(hmac(&signing_key, &string_to_sign))
+	);
+	// the host header is set by the client from the URL
+	let request = headers
+		.iter()
+		.skip(1)
+		.fold(ureq::request(method, &format!("{}{}", base, uri)), |request, (name, value)| request.set(name, value));
+	Ok(request.set("Authorization", &authorization))
+}
+
+// Basic authentication with WEBDAV_USER and WEBDAV_PASSWORD, if set
+#[cfg(feature = "remote")]
+fn dav_request(method: &str, url: &str) -> ureq::Request {
+	let request = ureq::request(method, url);
+	match env("WEBDAV_USER") {
+		Some(user) => {
+			let credentials = format!("{}:{}", user, env("WEBDAV_PASSWORD").unwrap_or_default());
+			let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+			request.set("Authorization", &format!("Basic {}", encoded))
+		}
+		None => request,
+	}
+}
+
+// The status of a response, errors included
+#[cfg(feature = "remote")]
+fn status(response: Result<ureq::Response, ureq::Error>) -> Result<u16, String> {
+	match response {
+		Ok(response) => Ok(response.status()),
+		Err(ureq::Error::Status(code, _)) => Ok(code),
+		Err(e) => Err(e.to_string()),
+	}
+}
+
+#[cfg(feature = "remote")]
+impl Remote {
+	fn request(&self, method: &str) -> Result<ureq::Request, String> {
+		match self {
+			Remote::S3 { bucket, key } => s3_request(method, bucket, key),
+			Remote::Dav { base, path } => Ok(dav_request(method, &format!("{}{}", base, encode_path(path)))),
+		}
+	}
+
+	pub fn exists(&self) -> Result<bool, String> {
+		match status(self.request("HEAD")?.call())? {
+			200..=299 => Ok(true),
+			404 => Ok(false),
+			code => Err(format!("HTTP status {}", code)),
+		}
+	}
+
+	// WebDAV servers do not create missing collections on upload. Collections
+	// already created or found are remembered across uploads
+	fn create_collections(&self, collections: &mut HashSet<String>) -> Result<(), String> {
+		let Remote::Dav { base, path } = self else {
+			return Ok(());
+		};
+		for (i, _) in path.match_indices('/').skip(1) {
+			let collection = format!("{}{}/", base, encode_path(&path[..i]));
+			if collections.contains(&collection) {
+				continue;
+			}
+			// 405 if it exists already
+			match status(dav_request("MKCOL", &collection).call())? {
+				200..=299 | 405 => {}
+				code => return Err(format!("HTTP status {} creating {}", code, collection)),
+			}
+			collections.insert(collection);
+		}
+		Ok(())
+	}
+
+	pub fn upload(&self, body: impl io::Read, len: u64, collections: &mut HashSet<String>) -> Result<(), String> {
+		self.create_collections(collections)?;
+		let request = self.request("PUT")?.set("Content-Length", &len.to_string());
+		match request.send(body) {
+			Ok(_) => Ok(()),
+			Err(ureq::Error::Status(code, response)) => {
+				let message = response.into_string().unwrap_or_default();
+				Err(format!("HTTP status {} {}", code, message.trim()))
+			}
+			Err(e) => Err(e.to_string()),
+		}
+	}
+}
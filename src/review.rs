@@ -0,0 +1,129 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::path::PathBuf;
+
+// A planned operation as shown on the review screen. A non empty warning
+// highlights operations which will not be applied as planned, e.g. because the
+// destination already exists
+pub struct ReviewItem {
+	pub src: PathBuf,
+	pub dest: PathBuf,
+	pub warning: String,
+}
+
+const REVIEW_HELP: &str = "↑/↓ PgUp/PgDn move  space toggle  a toggle all  enter apply  q/esc cancel";
+
+struct Review<'a> {
+	title: String,
+	items: &'a [ReviewItem],
+	selected: Vec<bool>,
+	state: TableState,
+	page: usize,
+}
+
+impl<'a> Review<'a> {
+	fn move_by(&mut self, delta: isize) {
+		if self.items.is_empty() {
+			return;
+		}
+		let current = self.state.selected().unwrap_or(0) as isize;
+		let next = (current + delta).clamp(0, self.items.len() as isize - 1);
+		self.state.select(Some(next as usize));
+	}
+
+	fn toggle(&mut self) {
+		if let Some(i) = self.state.selected() {
+			self.selected[i] = !self.selected[i];
+			self.move_by(1);
+		}
+	}
+
+	fn toggle_all(&mut self) {
+		let all = self.selected.iter().all(|&s| s);
+		self.selected.iter_mut().for_each(|s| *s = !all);
+	}
+
+	fn draw(&mut self, frame: &mut Frame) {
+		let [table_area, help_area] = Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+		// rows inside the borders and below the header
+		self.page = table_area.height.saturating_sub(3).max(1) as usize;
+
+		let rows = self.items.iter().zip(self.selected.iter()).map(|(item, &selected)| {
+			let style = match (selected, item.warning.is_empty()) {
+				(false, _) => Style::default().fg(Color::DarkGray),
+				(true, false) => Style::default().fg(Color::Yellow),
+				(true, true) => Style::default(),
+			};
+			Row::new(vec![
+				Cell::from(if selected { "[x]" } else { "[ ]" }),
+				Cell::from(item.src.to_string_lossy().into_owned()),
+				Cell::from(item.dest.to_string_lossy().into_owned()),
+				Cell::from(item.warning.as_str()),
+			])
+			.style(style)
+		});
+		let count = self.selected.iter().filter(|&&s| s).count();
+		let table = Table::new(rows, [
+			Constraint::Length(3),
+			Constraint::Percentage(40),
+			Constraint::Percentage(45),
+			Constraint::Percentage(15),
+		])
+		.header(Row::new(vec!["", "Source", "Destination", ""]).style(Style::default().add_modifier(Modifier::BOLD)))
+		.block(Block::default().borders(Borders::ALL).title(format!(
+			"{} ({} of {} selected)",
+			self.title,
+			count,
+			self.items.len()
+		)))
+		.row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+		frame.render_stateful_widget(table, table_area, &mut self.state);
+		frame.render_widget(Paragraph::new(Line::from(REVIEW_HELP)), help_area);
+	}
+
+	fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<bool> {
+		loop {
+			terminal.draw(|frame| self.draw(frame))?;
+			if let Event::Key(key) = event::read()? {
+				if key.kind != KeyEventKind::Press {
+					continue;
+				}
+				let page = self.page as isize;
+				match key.code {
+					KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+					KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+					KeyCode::PageUp => self.move_by(-page),
+					KeyCode::PageDown => self.move_by(page),
+					KeyCode::Home => self.move_by(isize::MIN / 2),
+					KeyCode::End => self.move_by(isize::MAX / 2),
+					KeyCode::Char(' ') => self.toggle(),
+					KeyCode::Char('a') => self.toggle_all(),
+					KeyCode::Enter => return Ok(true),
+					KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+					_ => {}
+				}
+			}
+		}
+	}
+}
+
+// Shows the planned operations full screen, all initially selected. Returns
+// which ones have been approved, or None if the review was cancelled
+pub fn review(title: &str, items: &[ReviewItem]) -> io::Result<Option<Vec<bool>>> {
+	let mut review = Review {
+		title: title.to_string(),
+		items,
+		selected: vec![true; items.len()],
+		state: TableState::default().with_selected(Some(0)),
+		page: 1,
+	};
+	let mut terminal = ratatui::try_init()?;
+	let result = review.run(&mut terminal);
+	ratatui::restore();
+	Ok(if result? { Some(review.selected) } else { None })
+}
@@ -0,0 +1,86 @@
+use crate::PropertyValue;
+use chrono::{NaiveDate, NaiveDateTime};
+use std::path::PathBuf;
+
+fn timestamp(date: (i32, u32, u32), time: (u32, u32, u32)) -> PropertyValue {
+	let timestamp = NaiveDate::from_ymd_opt(date.0, date.1, date.2)
+		.and_then(|date| date.and_hms_opt(time.0, time.1, time.2))
+		.unwrap_or(NaiveDateTime::MIN);
+	PropertyValue::Timestamp(timestamp)
+}
+
+fn text(value: &str) -> PropertyValue { PropertyValue::Text(value.to_string()) }
+
+fn path(value: &str) -> PropertyValue { PropertyValue::Path(PathBuf::from(value)) }
+
+// Properties of a typical photo, DCIM/100MSDCF/DSC04696.ARW taken with a Sony
+// camera with GPS, for trying out templates without reading any file
+pub fn properties() -> Vec<(&'static str, PropertyValue)> {
+	vec![
+		("SysDateTimeNow", timestamp((2024, 8, 2), (9, 30, 0))),
+		("SysCwd", path("/media/card")),
+		("SysExt", path("ARW")),
+		("SysDotExt", path(".ARW")),
+		("SysName", path("DSC04696")),
+		("SysFullName", path("DSC04696.ARW")),
+		("SysPath", path("DCIM/100MSDCF")),
+		("SysPathElem0", path("DCIM")),
+		("SysPathElem1", path("100MSDCF")),
+		("SysPathAncestor0", path("100MSDCF")),
+		("SysPathAncestor1", path("DCIM")),
+		("SysPathHead0", path("DCIM")),
+		("SysPathHead1", path("DCIM/100MSDCF")),
+		("SysPathTail0", path("DCIM/100MSDCF")),
+		("SysPathTail1", path("100MSDCF")),
+		("SysDateTimeModified", timestamp((2024, 7, 27), (16, 38, 56))),
+		("SysDateTimeCreated", timestamp((2024, 7, 27), (16, 38, 56))),
+		("SysDateTimeAccessed", timestamp((2024, 8, 2), (9, 30, 0))),
+		("SysSize", PropertyValue::Integer(24_931_840)),
+		("SysSizeHuman", text("23.8MiB")),
+		("SysImageWidth", PropertyValue::Integer(6000)),
+		("SysImageHeight", PropertyValue::Integer(4000)),
+		("SysSha1", text("3f786850e387550fdab836ed7e6dc881de23001b")),
+		("SysShortId", text("voha91")),
+		("SysUuid", text("67e55044-10b1-426f-9247-bb680e5fe0c8")),
+		("ExifMake", text("SONY")),
+		("ExifModel", text("ILCE-9M3")),
+		("ExifBodySerialNumber", text("5102847")),
+		("ExifLensModel", text("SAMYANG AF 35-150mm F2-2.8")),
+		("ExifArtist", text("Nico Orru")),
+		("ExifDateTime", timestamp((2024, 7, 27), (16, 38, 55))),
+		("ExifDateTimeOriginal", timestamp((2024, 7, 27), (16, 38, 55))),
+		("ExifDateTimeDigitized", timestamp((2024, 7, 27), (16, 38, 55))),
+		("ExifDateTimeOriginalUtc", timestamp((2024, 7, 27), (14, 38, 55))),
+		("ExifDateTimeOriginalTz", text("Europe/Rome")),
+		("ExifOffsetTimeOriginal", text("+02:00")),
+		("ExifExposureTime", PropertyValue::Fraction(1, 3200)),
+		("ExifFNumber", PropertyValue::Fraction(25, 10)),
+		("ExifFocalLength", PropertyValue::Fraction(590, 10)),
+		("ExifFocalLengthIn35mmFilm", PropertyValue::Integer(59)),
+		("ExifPhotographicSensitivity", PropertyValue::Integer(250)),
+		("ExifOrientation", PropertyValue::Integer(1)),
+		("ExifPixelXDimension", PropertyValue::Integer(6000)),
+		("ExifPixelYDimension", PropertyValue::Integer(4000)),
+		("ExifShutterCount", PropertyValue::Integer(18_342)),
+		("GpsLatitudeDec", PropertyValue::Real(41.9028)),
+		("GpsLongitudeDec", PropertyValue::Real(12.4964)),
+		("GpsCoords", text("41.9028,12.4964")),
+		("GpsGeoHash", text("sr2yk")),
+		("GpsAltitudeM", PropertyValue::Real(21.4)),
+		("GpsSpeedKmh", PropertyValue::Real(0.0)),
+		("GpsImgDirectionDeg", PropertyValue::Real(254.3)),
+		("CalcMegapixels", PropertyValue::Real(24.0)),
+		("CalcAspectRatio", text("3:2")),
+		("CalcOrientation", text("landscape")),
+		("CalcFocalLength35", PropertyValue::Integer(59)),
+		("CalcYear", PropertyValue::Integer(2024)),
+		("CalcMonth", text("07")),
+		("CalcIsoWeek", text("2024-W30")),
+		("CalcQuarter", text("Q3")),
+		("CalcSeason", text("summer")),
+		("CalcWeekday", text("Saturday")),
+		("CalcDaylight", text("day")),
+		("XmpRating", PropertyValue::Integer(4)),
+		("XmpLabel", text("Green")),
+	]
+}
@@ -0,0 +1,165 @@
+use crate::portable;
+use jpeg_decoder::{Decoder, PixelFormat};
+use serde_json::json;
+use std::fs;
+use std::io::{self, Read as _};
+use std::path::{Path, PathBuf};
+
+// The difference hash compares the brightness of neighbouring cells of a 9x8
+// grid laid over the image, one bit for each pair
+const GRID_WIDTH: usize = 9;
+const GRID_HEIGHT: usize = 8;
+
+// Decoded at the smallest scale the JPEG decoder offers above this size
+const DECODE_SIZE: u16 = 64;
+
+const JPEG_MAGIC: [u8; 2] = [0xff, 0xd8];
+
+// Brightness of each pixel of a decoded image, from 0 to 255
+fn luma(pixels: &[u8], format: PixelFormat) -> Vec<f32> {
+	match format {
+		PixelFormat::L8 => pixels.iter().map(|&l| l as f32).collect(),
+		PixelFormat::L16 => pixels.chunks_exact(2).map(|l| l[0] as f32).collect(),
+		PixelFormat::RGB24 =>
+			pixels.chunks_exact(3).map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32).collect(),
+		// as decoded, each channel is the complement of the ink
+		PixelFormat::CMYK32 => pixels
+			.chunks_exact(4)
+			.map(|p| {
+				let k = p[3] as f32 / 255.0;
+				(0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) * k
+			})
+			.collect(),
+	}
+}
+
+// The difference hash of a JPEG image, so that re-exports, resized and slightly
+// edited copies differ by a few bits at most
+pub fn dhash(jpeg: &[u8]) -> Result<u64, String> {
+	let mut decoder = Decoder::new(jpeg);
+	decoder.scale(DECODE_SIZE, DECODE_SIZE).map_err(|e| e.to_string())?;
+	let pixels = decoder.decode().map_err(|e| e.to_string())?;
+	let info = decoder.info().ok_or("no image information")?;
+	let (width, height) = (info.width as usize, info.height as usize);
+	if width < GRID_WIDTH || height < GRID_HEIGHT {
+		return Err(format!("{}x{} is too small", width, height));
+	}
+	let luma = luma(&pixels, info.pixel_format);
+	// average brightness of each cell
+	let mut grid = [[0.0f32; GRID_WIDTH]; GRID_HEIGHT];
+	for (row, cells) in grid.iter_mut().enumerate() {
+		let (y0, y1) = (row * height / GRID_HEIGHT, (row + 1) * height / GRID_HEIGHT);
+		for (column, cell) in cells.iter_mut().enumerate() {
+			let (x0, x1) = (column * width / GRID_WIDTH, (column + 1) * width / GRID_WIDTH);
+			let sum = (y0..y1).flat_map(|y| (x0..x1).map(move |x| y * width + x)).map(|i| luma[i]).sum::<f32>();
+			*cell = sum / ((y1 - y0) * (x1 - x0)) as f32;
+		}
+	}
+	let mut hash = 0u64;
+	for cells in &grid {
+		for pair in cells.windows(2) {
+			hash = (hash << 1) | (pair[0] > pair[1]) as u64;
+		}
+	}
+	Ok(hash)
+}
+
+// JPEG files are hashed in full, other files by their embedded thumbnail, if
+// any. None if the file has no image to compare
+pub fn image_hash(src: &Path) -> Result<Option<u64>, String> {
+	let mut magic = [0u8; 2];
+	let is_jpeg = fs::File::open(src)
+		.and_then(|mut file| file.read_exact(&mut magic))
+		.map(|_| magic == JPEG_MAGIC)
+		.or_else(|e| if e.kind() == io::ErrorKind::UnexpectedEof { Ok(false) } else { Err(e) })
+		.map_err(|e| format!("Could not read {:?}: {}", src, e))?;
+	let jpeg = if is_jpeg {
+		fs::read(src).map_err(|e| format!("Could not read {:?}: {}", src, e))?
+	} else {
+		match crate::read_thumbnail(src) {
+			Ok(thumbnail) => thumbnail,
+			Err(_) => return Ok(None),
+		}
+	};
+	dhash(&jpeg).map(Some).map_err(|e| format!("Could not decode the image of {:?}: {}", src, e))
+}
+
+// Images collected by -m similar, with their hashes
+#[derive(Default, Debug)]
+pub struct Similar {
+	images: Vec<(PathBuf, u64)>,
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+	let mut root = i;
+	while parents[root] != root {
+		root = parents[root];
+	}
+	parents[i] = root;
+	root
+}
+
+impl Similar {
+	pub fn add(&mut self, path: PathBuf, hash: u64) { self.images.push((path, hash)); }
+
+	// Images within the distance of one another end up in the same cluster, even
+	// if further from the others. Clusters of more than one image, largest first
+	fn clusters(&self, max_distance: u32) -> Vec<Vec<usize>> {
+		let mut parents = (0..self.images.len()).collect::<Vec<_>>();
+		for i in 0..self.images.len() {
+			for j in i + 1..self.images.len() {
+				if (self.images[i].1 ^ self.images[j].1).count_ones() <= max_distance {
+					let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+					parents[b.max(a)] = a.min(b);
+				}
+			}
+		}
+		let mut clusters = Vec::<Vec<usize>>::new();
+		let mut cluster_of_root = vec![None::<usize>; self.images.len()];
+		for i in 0..self.images.len() {
+			let root = find(&mut parents, i);
+			match cluster_of_root[root] {
+				Some(cluster) => clusters[cluster].push(i),
+				None => {
+					cluster_of_root[root] = Some(clusters.len());
+					clusters.push(vec![i]);
+				}
+			}
+		}
+		clusters.retain(|cluster| cluster.len() > 1);
+		clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.len()));
+		clusters
+	}
+
+	// Each image with its distance from the first of its cluster
+	fn members(&self, cluster: &[usize]) -> Vec<(String, u32)> {
+		let first = self.images[cluster[0]].1;
+		cluster
+			.iter()
+			.map(|&i| (portable::escape(self.images[i].0.as_os_str()), (self.images[i].1 ^ first).count_ones()))
+			.collect()
+	}
+
+	pub fn print(&self, max_distance: u32) {
+		let clusters = self.clusters(max_distance);
+		for cluster in &clusters {
+			println!("{} similar images:", cluster.len());
+			for (path, distance) in self.members(cluster) {
+				println!("  {:>2} {}", distance, path);
+			}
+		}
+		println!(
+			"{} set(s) of similar images, {} images in total",
+			clusters.len(),
+			clusters.iter().map(Vec::len).sum::<usize>()
+		);
+	}
+
+	// One object per cluster, one per line
+	pub fn export(&self, max_distance: u32) {
+		for cluster in self.clusters(max_distance) {
+			let (paths, distances): (Vec<_>, Vec<_>) = self.members(&cluster).into_iter().unzip();
+			println!("{}", json!({ "paths": paths, "distances": distances }));
+		}
+	}
+}
@@ -0,0 +1,63 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+// Sources processed by previous runs, so that later runs can skip them as long
+// as they are unchanged. Changes are written in a single transaction, committed
+// at the end of the run
+pub struct StateDb {
+	connection: Connection,
+}
+
+impl StateDb {
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+		let path = path.as_ref();
+		let connection =
+			Connection::open(path).map_err(|e| format!("Unable to open state database {:?}: {}", path, e))?;
+		connection
+			.execute_batch(
+				"CREATE TABLE IF NOT EXISTS processed (
+					src TEXT PRIMARY KEY,
+					size INTEGER NOT NULL,
+					modified TEXT NOT NULL,
+					hash TEXT,
+					dest TEXT NOT NULL,
+					processed_at TEXT NOT NULL
+				);
+				BEGIN;",
+			)
+			.map_err(|e| format!("Unable to initialize state database {:?}: {}", path, e))?;
+		Ok(StateDb { connection })
+	}
+
+	// Whether the source has already been processed with the same size and
+	// modification time
+	pub fn is_unchanged(&self, src: &str, size: i64, modified: &str) -> bool {
+		self.connection
+			.query_row("SELECT size, modified FROM processed WHERE src = ?1", params![src], |row| {
+				Ok(row.get::<_, i64>(0)? == size && row.get::<_, String>(1)? == modified)
+			})
+			.optional()
+			.ok()
+			.flatten()
+			.unwrap_or(false)
+	}
+
+	pub fn record(&self, src: &str, size: i64, modified: &str, hash: Option<&str>, dest: &str) -> Result<(), String> {
+		self.connection
+			.execute(
+				"INSERT OR REPLACE INTO processed (src, size, modified, hash, dest, processed_at)
+				VALUES (?1, ?2, ?3, ?4, ?5, datetime('now'))",
+				params![src, size, modified, hash, dest],
+			)
+			.map(|_| ())
+			.map_err(|e| format!("Unable to record {} in the state database: {}", src, e))
+	}
+
+	pub fn commit(&self) -> Result<(), String> {
+		self.connection.execute_batch("COMMIT").map_err(|e| format!("Unable to save the state database: {}", e))
+	}
+
+	pub fn rollback(&self) -> Result<(), String> {
+		self.connection.execute_batch("ROLLBACK").map_err(|e| format!("Unable to roll back the state database: {}", e))
+	}
+}
@@ -0,0 +1,65 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+// Properties whose absence is worth knowing before reorganizing a collection
+const EXPECTED: [(&str, &str); 4] = [
+	("ExifDateTimeOriginal", "date taken"),
+	("ExifModel", "camera model"),
+	("ExifLensModel", "lens model"),
+	("GpsLatitudeDec", "GPS position"),
+];
+
+// Counts across all the files processed by -m stats
+#[derive(Default, Debug)]
+pub struct Stats {
+	files: usize,
+	bytes: u64,
+	by_model: BTreeMap<String, usize>,
+	by_lens: BTreeMap<String, usize>,
+	by_month: BTreeMap<String, usize>,
+	by_ext: BTreeMap<String, usize>,
+	missing: BTreeMap<&'static str, usize>,
+}
+
+fn typed<'a>(data: &'a Map<String, Value>, typed_key: &str, key: &str) -> Option<&'a Value> {
+	data.get(typed_key)?.get(key).filter(|value| !value.is_null())
+}
+
+impl Stats {
+	pub fn add(&mut self, data: &Map<String, Value>, typed_key: &str) {
+		self.files += 1;
+		self.bytes += typed(data, typed_key, "SysSize").and_then(Value::as_u64).unwrap_or(0);
+		let text = |key: &str| typed(data, typed_key, key).and_then(Value::as_str).filter(|text| !text.is_empty());
+		let count = |counts: &mut BTreeMap<String, usize>, value: Option<&str>| {
+			*counts.entry(value.unwrap_or("(unknown)").to_string()).or_default() += 1;
+		};
+		count(&mut self.by_model, text("ExifModel"));
+		count(&mut self.by_lens, text("ExifLensModel"));
+		// as YYYY-MM, from the ISO format of typed timestamps
+		count(&mut self.by_month, text("ExifDateTimeOriginal").and_then(|timestamp| timestamp.get(..7)));
+		let ext = text("SysExt").map(str::to_lowercase);
+		count(&mut self.by_ext, ext.as_deref());
+		for (key, description) in EXPECTED {
+			if typed(data, typed_key, key).is_none() {
+				*self.missing.entry(description).or_default() += 1;
+			}
+		}
+	}
+
+	fn section<K: std::fmt::Display>(title: &str, counts: &BTreeMap<K, usize>) {
+		println!("{}:", title);
+		for (key, count) in counts {
+			println!("  {:<40} {:>8}", key, count);
+		}
+	}
+
+	pub fn print(&self) {
+		println!("Files: {}", self.files);
+		println!("Total size: {} bytes", self.bytes);
+		Self::section("By camera model", &self.by_model);
+		Self::section("By lens", &self.by_lens);
+		Self::section("By month taken", &self.by_month);
+		Self::section("By extension", &self.by_ext);
+		Self::section("Missing", &self.missing);
+	}
+}
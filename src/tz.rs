@@ -0,0 +1,103 @@
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+// A polygon as a list of rings of (longitude, latitude) points: the first ring
+// is the outer boundary, the following ones are holes
+struct Polygon {
+	rings: Vec<Vec<(f64, f64)>>,
+	// longitude and latitude bounds of the outer ring, to skip most of the polygons
+	// cheaply
+	min: (f64, f64),
+	max: (f64, f64),
+}
+
+// Ray casting: a point is inside a ring if a ray from it crosses the boundary
+// an odd number of times
+fn ring_contains(ring: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+	let mut inside = false;
+	let mut j = ring.len().wrapping_sub(1);
+	for i in 0..ring.len() {
+		let (xi, yi) = ring[i];
+		let (xj, yj) = ring[j];
+		if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+			inside = !inside;
+		}
+		j = i;
+	}
+	inside
+}
+
+impl Polygon {
+	fn from_json(coordinates: &Value) -> Option<Self> {
+		let rings: Vec<Vec<(f64, f64)>> = coordinates
+			.as_array()?
+			.iter()
+			.map(|ring| {
+				ring.as_array()
+					.into_iter()
+					.flatten()
+					.filter_map(|point| Some((point.get(0)?.as_f64()?, point.get(1)?.as_f64()?)))
+					.collect()
+			})
+			.collect();
+		let outer = rings.first()?;
+		let min = outer.iter().fold((f64::MAX, f64::MAX), |(x, y), &(px, py)| (x.min(px), y.min(py)));
+		let max = outer.iter().fold((f64::MIN, f64::MIN), |(x, y), &(px, py)| (x.max(px), y.max(py)));
+		Some(Polygon { rings, min, max })
+	}
+
+	fn contains(&self, point: (f64, f64)) -> bool {
+		let (x, y) = point;
+		if x < self.min.0 || x > self.max.0 || y < self.min.1 || y > self.max.1 {
+			return false;
+		}
+		let mut rings = self.rings.iter();
+		rings.next().is_some_and(|outer| ring_contains(outer, point)) && !rings.any(|hole| ring_contains(hole, point))
+	}
+}
+
+// IANA timezone boundaries, as published in GeoJSON format by the
+// timezone-boundary-builder project: a FeatureCollection of Polygon or
+// MultiPolygon features, each with a "tzid" property
+pub struct TzBoundaries {
+	zones: Vec<(String, Vec<Polygon>)>,
+}
+
+impl TzBoundaries {
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+		let path = path.as_ref();
+		let content = fs::read(path).map_err(|e| format!("Unable to read timezone boundaries {:?}: {}", path, e))?;
+		let json: Value = serde_json::from_slice(&content)
+			.map_err(|e| format!("Unable to parse timezone boundaries {:?}: {}", path, e))?;
+		let features = json
+			.get("features")
+			.and_then(Value::as_array)
+			.ok_or_else(|| format!("No features found in timezone boundaries {:?}", path))?;
+		let mut zones = Vec::new();
+		for feature in features {
+			let Some(tzid) = feature.pointer("/properties/tzid").and_then(Value::as_str) else {
+				continue;
+			};
+			let Some(geometry) = feature.get("geometry") else {
+				continue;
+			};
+			let coordinates = geometry.get("coordinates").unwrap_or(&Value::Null);
+			let polygons = match geometry.get("type").and_then(Value::as_str) {
+				Some("Polygon") => Polygon::from_json(coordinates).into_iter().collect(),
+				Some("MultiPolygon") =>
+					coordinates.as_array().into_iter().flatten().filter_map(Polygon::from_json).collect(),
+				_ => Vec::new(),
+			};
+			zones.push((tzid.to_string(), polygons));
+		}
+		Ok(TzBoundaries { zones })
+	}
+
+	pub fn lookup(&self, latitude: f64, longitude: f64) -> Option<&str> {
+		self.zones
+			.iter()
+			.find(|(_, polygons)| polygons.iter().any(|polygon| polygon.contains((longitude, latitude))))
+			.map(|(tzid, _)| tzid.as_str())
+	}
+}
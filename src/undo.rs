@@ -0,0 +1,96 @@
+use crate::portable;
+use log::*;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// A change recorded in the event log written with --log-json
+enum Change {
+	Moved { src: PathBuf, dest: PathBuf },
+	Created(PathBuf),
+}
+
+// Only changes actually applied and still in place, in the order they were
+// applied. Paths are relative to the folder the run started from
+fn load(log: &Path) -> Result<Vec<Change>, String> {
+	let content = fs::read_to_string(log).map_err(|e| format!("Unable to read event log {:?}: {}", log, e))?;
+	// with the src, dest and mode of their event, to match rollback records
+	let mut changes: Vec<((String, String, String), Change)> = Vec::new();
+	for (i, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+		let event: Value =
+			serde_json::from_str(line).map_err(|e| format!("Invalid event at line {} of {:?}: {}", i + 1, log, e))?;
+		let field = |name: &str| event.get(name).and_then(Value::as_str);
+		if event.get("dry_run").and_then(Value::as_bool) != Some(false) {
+			continue;
+		}
+		let (Some(src), Some(dest)) = (field("src"), field("dest")) else {
+			continue;
+		};
+		let key = (src.to_string(), dest.to_string(), field("mode").unwrap_or_default().to_string());
+		match field("result") {
+			Some("applied") => {}
+			// reverted by --transactional, the most recent matching change
+			// is no longer in place
+			Some("rolled_back") => {
+				if let Some(pos) = changes.iter().rposition(|(applied, _)| *applied == key) {
+					changes.remove(pos);
+				}
+				continue;
+			}
+			_ => continue,
+		}
+		match field("mode") {
+			// audit --repair moves the files it repairs
+			Some("mv" | "audit") =>
+				changes.push((key, Change::Moved { src: portable::unescape(src), dest: portable::unescape(dest) })),
+			Some("cp" | "clone" | "symlink" | "ln" | "dedup" | "thumb") =>
+				changes.push((key, Change::Created(portable::unescape(dest)))),
+			// nothing was changed
+			Some("info" | "stats" | "dupes" | "similar") => {}
+			mode => warn!("Unknown mode {:?} at line {} of {:?}, not reverted", mode, i + 1, log),
+		}
+	}
+	Ok(changes.into_iter().map(|(_, change)| change).collect())
+}
+
+fn revert(change: &Change) -> io::Result<()> {
+	match change {
+		Change::Moved { src, dest } => {
+			if src.exists() {
+				return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} exists", src)));
+			}
+			if let Some(parent) = src.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+				fs::create_dir_all(parent)?;
+			}
+			fs::rename(dest, src)
+		}
+		Change::Created(dest) => fs::remove_file(dest),
+	}
+}
+
+// Reverts the changes of a previous run, most recent first: moved files are
+// moved back, copies and links are removed. Returns the number of changes
+// reverted, and of those which could not be
+pub fn undo(log: &Path, dry_run: bool) -> Result<(usize, usize), String> {
+	let changes = load(log)?;
+	let (mut reverted, mut failed) = (0, 0);
+	for change in changes.iter().rev() {
+		match change {
+			Change::Moved { src, dest } => println!("mv {:?} {:?}", dest, src),
+			Change::Created(dest) => println!("rm {:?}", dest),
+		}
+		if dry_run {
+			reverted += 1;
+			continue;
+		}
+		match revert(change) {
+			Ok(()) => reverted += 1,
+			Err(e) => {
+				error!("Could not revert: {}", e);
+				failed += 1;
+			}
+		}
+	}
+	Ok((reverted, failed))
+}
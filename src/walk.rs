@@ -0,0 +1,160 @@
+use crate::portable;
+use glob::{MatchOptions, Pattern, PatternError};
+use std::fs;
+use std::io;
+use std::path::{self, Component, Path, PathBuf};
+
+// A folder which could not be read
+pub struct WalkError {
+	pub path: PathBuf,
+	pub error: io::Error,
+}
+
+// The files matching a pattern, found lazily and in the same order as
+// glob::glob_with would. glob skips names which are not valid UTF-8, here they
+// are matched in the escaped form they take in templates
+pub struct Walk {
+	// one for each component of the pattern
+	steps: Vec<Pattern>,
+	// the pattern ends with a separator
+	require_dir: bool,
+	options: MatchOptions,
+	// where the walk starts, until it does
+	scope: Option<PathBuf>,
+	// paths left to match, with the step to match them against, None if matched
+	todo: Vec<Result<(PathBuf, Option<usize>), WalkError>>,
+}
+
+pub fn walk(pattern: &str, options: MatchOptions) -> Result<Walk, PatternError> {
+	Pattern::new(pattern)?;
+	// the prefix and root, if any, are not matched
+	let rest = Path::new(pattern)
+		.components()
+		.skip_while(|component| matches!(component, Component::Prefix(_) | Component::RootDir))
+		.collect::<PathBuf>();
+	let normalized = Path::new(pattern).iter().collect::<PathBuf>();
+	let root_len = normalized.as_os_str().len() - rest.as_os_str().len();
+	let scope = if root_len > 0 { PathBuf::from(&pattern[..root_len]) } else { PathBuf::from(".") };
+	let mut steps = pattern[root_len.min(pattern.len())..]
+		.split_terminator(path::is_separator)
+		.map(Pattern::new)
+		.collect::<Result<Vec<_>, _>>()?;
+	if root_len == pattern.len() {
+		steps.push(Pattern::new("")?);
+	}
+	Ok(Walk {
+		steps,
+		require_dir: pattern.ends_with(path::is_separator),
+		options,
+		scope: Some(scope),
+		todo: Vec::new(),
+	})
+}
+
+fn is_recursive(step: &Pattern) -> bool { step.as_str() == "**" }
+
+// A component without wildcards is looked up rather than listed
+fn literal(step: &Pattern) -> Option<&str> {
+	let text = step.as_str();
+	(!text.contains(['*', '?', '['])).then_some(text)
+}
+
+impl Walk {
+	fn matches(&self, idx: usize, path: &Path) -> bool {
+		path.file_name().is_some_and(|name| self.steps[idx].matches_with(&portable::escape(name), self.options))
+	}
+
+	fn add(&mut self, idx: usize, path: PathBuf) {
+		if idx + 1 == self.steps.len() {
+			self.todo.push(Ok((path, None)));
+		} else {
+			self.fill(idx + 1, &path);
+		}
+	}
+
+	// The paths within the folder to match against the step
+	fn fill(&mut self, idx: usize, path: &Path) {
+		let is_dir = path.is_dir();
+		let curdir = path == Path::new(".");
+		if let Some(name) = literal(&self.steps[idx]).map(str::to_string) {
+			let special = name == "." || name == "..";
+			let next_path = if curdir { PathBuf::from(&name) } else { path.join(&name) };
+			if (special && is_dir) || (!special && fs::metadata(&next_path).is_ok()) {
+				self.add(idx, next_path);
+			}
+			return;
+		}
+		if !is_dir {
+			return;
+		}
+		let children = fs::read_dir(path).and_then(|entries| {
+			entries
+				.map(|entry| entry.map(|entry| if curdir { PathBuf::from(entry.file_name()) } else { entry.path() }))
+				.collect::<io::Result<Vec<_>>>()
+		});
+		match children {
+			Ok(mut children) => {
+				// popped from the end, in order
+				children.sort_by(|a, b| b.file_name().cmp(&a.file_name()));
+				self.todo.extend(children.into_iter().map(|child| Ok((child, Some(idx)))));
+				// . and .. are only matched by a pattern starting with a dot
+				let step = self.steps[idx].clone();
+				if step.as_str().starts_with('.') {
+					for special in [".", ".."] {
+						if step.matches_with(special, self.options) {
+							self.add(idx, path.join(special));
+						}
+					}
+				}
+			}
+			Err(error) => self.todo.push(Err(WalkError { path: path.to_path_buf(), error })),
+		}
+	}
+}
+
+impl Iterator for Walk {
+	type Item = Result<PathBuf, WalkError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if let Some(scope) = self.scope.take() {
+			if !self.steps.is_empty() {
+				self.fill(0, &scope);
+			}
+		}
+		loop {
+			let (path, idx) = match self.todo.pop()? {
+				Ok(next) => next,
+				Err(e) => return Some(Err(e)),
+			};
+			let Some(mut idx) = idx else {
+				if self.require_dir && !path.is_dir() {
+					continue;
+				}
+				return Some(Ok(path));
+			};
+			if is_recursive(&self.steps[idx]) {
+				// consecutive ** are the same as one
+				let mut next = idx;
+				while next + 1 < self.steps.len() && is_recursive(&self.steps[next + 1]) {
+					next += 1;
+				}
+				if path.is_dir() {
+					self.fill(next, &path);
+					if next == self.steps.len() - 1 {
+						return Some(Ok(path));
+					}
+				} else if next == self.steps.len() - 1 {
+					continue;
+				}
+				idx = next + 1;
+			}
+			if self.matches(idx, &path) {
+				if idx < self.steps.len() - 1 {
+					self.fill(idx + 1, &path);
+				} else if !self.require_dir || path.is_dir() {
+					return Some(Ok(path));
+				}
+			}
+		}
+	}
+}
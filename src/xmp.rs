@@ -0,0 +1,109 @@
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// darktable color labels, by number
+const DARKTABLE_LABELS: [&str; 5] = ["Red", "Yellow", "Green", "Blue", "Purple"];
+
+const XMPMM_NAMESPACE: &str = "http://ns.adobe.com/xap/1.0/mm/";
+const PRESERVED_FILE_NAME: &str = "xmpMM:PreservedFileName";
+
+// Metadata written to XMP sidecars by Lightroom, darktable and similar editors
+#[derive(Default, Debug)]
+pub struct Xmp {
+	pub rating: Option<i64>,
+	pub label: Option<String>,
+	pub keywords: Vec<String>,
+	// from the most general to the most specific, separated by |
+	pub hierarchical_keywords: Vec<String>,
+}
+
+// Either IMG_0001.CR2.xmp, as written by darktable, or IMG_0001.xmp, as written
+// by Lightroom
+pub fn find_sidecar(src: &Path) -> Option<PathBuf> {
+	let mut candidates = Vec::new();
+	for ext in ["xmp", "XMP"] {
+		let mut with_ext = src.as_os_str().to_os_string();
+		with_ext.push(".");
+		with_ext.push(ext);
+		candidates.push(PathBuf::from(with_ext));
+		candidates.push(src.with_extension(ext));
+	}
+	candidates.into_iter().find(|candidate| candidate != src && candidate.is_file())
+}
+
+fn unescape(text: &str) -> String {
+	text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+// A simple property, either as an attribute, e.g. xmp:Rating="5", or as an
+// element, e.g. <xmp:Rating>5</xmp:Rating>
+fn simple(content: &str, name: &str) -> Option<String> {
+	let name = regex::escape(name);
+	let pattern = Regex::new(&format!(r#"{}\s*=\s*"([^"]*)"|<{}>([^<]*)</{}>"#, name, name, name)).ok()?;
+	let captures = pattern.captures(content)?;
+	captures.get(1).or_else(|| captures.get(2)).map(|m| unescape(m.as_str().trim()))
+}
+
+// The items of an array property, e.g. <dc:subject><rdf:Bag><rdf:li>...
+fn array(content: &str, name: &str) -> Vec<String> {
+	let name = regex::escape(name);
+	let Ok(pattern) = Regex::new(&format!(r"(?s)<{}>(.*?)</{}>", name, name)) else {
+		return Vec::new();
+	};
+	let Ok(item) = Regex::new(r"(?s)<rdf:li[^>]*>(.*?)</rdf:li>") else {
+		return Vec::new();
+	};
+	pattern
+		.captures(content)
+		.and_then(|captures| captures.get(1))
+		.map(|inner| item.captures_iter(inner.as_str()).map(|c| unescape(c[1].trim())).collect())
+		.unwrap_or_default()
+}
+
+pub fn load(path: &Path) -> Result<Xmp, String> {
+	let content = fs::read_to_string(path).map_err(|e| format!("Unable to read XMP sidecar {:?}: {}", path, e))?;
+	let label = simple(&content, "xmp:Label").filter(|label| !label.is_empty()).or_else(|| {
+		array(&content, "darktable:colorlabels")
+			.first()
+			.and_then(|n| n.parse::<usize>().ok())
+			.and_then(|n| DARKTABLE_LABELS.get(n))
+			.map(|label| label.to_string())
+	});
+	Ok(Xmp {
+		rating: simple(&content, "xmp:Rating").and_then(|rating| rating.parse().ok()),
+		label,
+		keywords: array(&content, "dc:subject"),
+		hierarchical_keywords: array(&content, "lr:hierarchicalSubject"),
+	})
+}
+
+// The content of a sidecar with xmpMM:PreservedFileName set to name, or of a
+// new sidecar with only that property if there is none. None if the sidecar
+// already has a name preserved
+pub fn preserve_file_name(content: Option<&str>, name: &str) -> Result<Option<String>, String> {
+	let property = format!("{}=\"{}\"", PRESERVED_FILE_NAME, escape(name));
+	let Some(content) = content else {
+		return Ok(Some(format!(
+			"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+			 <rdf:Description rdf:about=\"\"\n    xmlns:xmpMM=\"{}\"\n    {}/>\n </rdf:RDF>\n</x:xmpmeta>\n",
+			XMPMM_NAMESPACE, property
+		)));
+	};
+	if simple(content, PRESERVED_FILE_NAME).is_some() {
+		return Ok(None);
+	}
+	// added as attributes of the first description
+	const DESCRIPTION: &str = "<rdf:Description";
+	let at = content.find(DESCRIPTION).ok_or_else(|| "no rdf:Description found".to_string())? + DESCRIPTION.len();
+	let namespace = if content.contains("xmlns:xmpMM=") {
+		String::new()
+	} else {
+		format!("\n    xmlns:xmpMM=\"{}\"", XMPMM_NAMESPACE)
+	};
+	Ok(Some(format!("{}{}\n    {}{}", &content[..at], namespace, property, &content[at..])))
+}